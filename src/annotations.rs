@@ -0,0 +1,248 @@
+use pdfium_render::prelude::{PdfColor, PdfPage, PdfPageAnnotation, PdfPageAnnotationCommon, PdfPageAnnotationType, PdfQuadPoints, PdfRenderConfig};
+use serde::Serialize;
+use crate::geometry::{PageRect, PixelRect};
+
+///Аннотация на странице (заметка, выделение, штамп и т.д.)
+#[derive(Debug, Clone, Serialize)]
+pub struct PageAnnotation
+{
+    pub kind: AnnotationKind,
+    ///Область аннотации в пунктах pdf
+    pub rect: PageRect,
+    ///Область аннотации в пикселях изображения, которое отдает `convert_page` этого же `PdfService`
+    pub pixels: PixelRect,
+    pub author: Option<String>,
+    pub contents: Option<String>,
+    ///Дата изменения в формате pdf, например `D:20240131120000+03'00'`
+    pub modified: Option<String>,
+    ///Цвет аннотации в виде `#RRGGBB`
+    pub color: Option<String>,
+    ///Цвет заливки для фигур и свободного текста в виде `#RRGGBB`
+    pub interior_color: Option<String>,
+    ///Четырехугольники выделенного текста для выделения, подчеркивания и зачеркивания, для остальных пусто
+    pub quad_points: Vec<QuadPoints>,
+}
+
+///Четыре точки в пунктах pdf в том порядке, в котором они записаны в документе
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct QuadPoints
+{
+    pub points: [[f32; 2]; 4],
+}
+impl QuadPoints
+{
+    pub fn bounds(&self) -> PageRect
+    {
+        let xs = self.points.map(|p| p[0]);
+        let ys = self.points.map(|p| p[1]);
+        let min = |v: [f32; 4]| v.into_iter().fold(f32::INFINITY, f32::min);
+        let max = |v: [f32; 4]| v.into_iter().fold(f32::NEG_INFINITY, f32::max);
+        PageRect::new(min(xs), min(ys), max(xs), max(ys))
+    }
+    fn from_pdf(quad: &PdfQuadPoints) -> Self
+    {
+        Self { points: [[quad.x1.value, quad.y1.value], [quad.x2.value, quad.y2.value], [quad.x3.value, quad.y3.value], [quad.x4.value, quad.y4.value]] }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnotationKind
+{
+    ///Заметка (стикер)
+    Text,
+    FreeText,
+    Highlight,
+    Underline,
+    Squiggly,
+    Strikeout,
+    Stamp,
+    Caret,
+    ///Рисунок от руки
+    Ink,
+    Square,
+    Circle,
+    Line,
+    Polygon,
+    Polyline,
+    FileAttachment,
+    Redacted,
+    Other,
+}
+impl From<PdfPageAnnotationType> for AnnotationKind
+{
+    fn from(value: PdfPageAnnotationType) -> Self
+    {
+        match value
+        {
+            PdfPageAnnotationType::Text => AnnotationKind::Text,
+            PdfPageAnnotationType::FreeText => AnnotationKind::FreeText,
+            PdfPageAnnotationType::Highlight => AnnotationKind::Highlight,
+            PdfPageAnnotationType::Underline => AnnotationKind::Underline,
+            PdfPageAnnotationType::Squiggly => AnnotationKind::Squiggly,
+            PdfPageAnnotationType::Strikeout => AnnotationKind::Strikeout,
+            PdfPageAnnotationType::Stamp => AnnotationKind::Stamp,
+            PdfPageAnnotationType::Caret => AnnotationKind::Caret,
+            PdfPageAnnotationType::Ink => AnnotationKind::Ink,
+            PdfPageAnnotationType::Square => AnnotationKind::Square,
+            PdfPageAnnotationType::Circle => AnnotationKind::Circle,
+            PdfPageAnnotationType::Line => AnnotationKind::Line,
+            PdfPageAnnotationType::Polygon => AnnotationKind::Polygon,
+            PdfPageAnnotationType::Polyline => AnnotationKind::Polyline,
+            PdfPageAnnotationType::FileAttachment => AnnotationKind::FileAttachment,
+            PdfPageAnnotationType::Redacted => AnnotationKind::Redacted,
+            _ => AnnotationKind::Other
+        }
+    }
+}
+
+///Аннотации страницы, ссылки, поля форм и всплывающие окна пропускаются:
+///ссылки отдает `links`, а текст всплывающего окна уже есть в `contents` родительской аннотации
+pub(crate) fn page_annotations(page: &PdfPage, config: &PdfRenderConfig) -> Vec<PageAnnotation>
+{
+    let mut annotations = Vec::new();
+    for annotation in page.annotations().iter()
+    {
+        let annotation_type = annotation.annotation_type();
+        if matches!(annotation_type, PdfPageAnnotationType::Link | PdfPageAnnotationType::Popup | PdfPageAnnotationType::Widget | PdfPageAnnotationType::XfaWidget)
+        {
+            continue;
+        }
+        let Ok(bounds) = annotation.bounds() else { continue };
+        let rect = PageRect::from_pdf(&bounds);
+        let pixels = match PixelRect::from_page_rect(page, &rect, config)
+        {
+            Ok(pixels) => pixels,
+            Err(e) =>
+            {
+                logger::error!("Ошибка перевода координат аннотации в пиксели: {}", e);
+                continue;
+            }
+        };
+        annotations.push(PageAnnotation
+        {
+            kind: annotation_type.into(),
+            rect,
+            pixels,
+            author: annotation.creator(),
+            contents: annotation.contents(),
+            modified: annotation.modification_date(),
+            color: annotation.stroke_color().ok().map(|c| hex_color(&c)),
+            interior_color: annotation.fill_color().ok().map(|c| hex_color(&c)),
+            quad_points: quad_points(&annotation),
+        });
+    }
+    annotations
+}
+
+fn quad_points(annotation: &PdfPageAnnotation) -> Vec<QuadPoints>
+{
+    if !annotation.has_attachment_points()
+    {
+        return Vec::new();
+    }
+    annotation.attachment_points().iter().map(|q| QuadPoints::from_pdf(&q)).collect()
+}
+
+fn hex_color(color: &PdfColor) -> String
+{
+    format!("#{}", color.to_hex())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object};
+    use pdfium_render::prelude::{PdfPageAnnotationType, PdfRenderConfig};
+    use crate::PdfService;
+    use super::{page_annotations, AnnotationKind, QuadPoints};
+
+    #[test]
+    fn test_quad_points_bounds()
+    {
+        let quad = QuadPoints { points: [[10.0, 700.0], [90.0, 700.0], [10.0, 688.0], [90.0, 688.0]] };
+        let bounds = quad.bounds();
+        assert_eq!((bounds.left, bounds.bottom, bounds.right, bounds.top), (10.0, 688.0, 90.0, 700.0));
+    }
+
+    #[test]
+    fn test_annotation_kind()
+    {
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::Text), AnnotationKind::Text);
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::Highlight), AnnotationKind::Highlight);
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::Strikeout), AnnotationKind::Strikeout);
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::Ink), AnnotationKind::Ink);
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::FileAttachment), AnnotationKind::FileAttachment);
+        assert_eq!(AnnotationKind::from(PdfPageAnnotationType::Redacted), AnnotationKind::Redacted);
+        for other in [PdfPageAnnotationType::Watermark, PdfPageAnnotationType::Sound, PdfPageAnnotationType::Unknown]
+        {
+            assert_eq!(AnnotationKind::from(other), AnnotationKind::Other);
+        }
+    }
+
+    ///Заметка с всплывающим окном, ответ на нее и выделение текста
+    fn sample() -> Vec<u8>
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.new_object_id();
+        let rect = |left: i64, bottom: i64| Object::Array(vec![left.into(), bottom.into(), (left + 80).into(), (bottom + 12).into()]);
+        let note_id = document.new_object_id();
+        let popup = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "Popup", "Rect" => rect(300, 600), "Parent" => note_id, "P" => page_id });
+        document.objects.insert(note_id, Object::Dictionary(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Text", "Rect" => rect(50, 700), "P" => page_id,
+            "Contents" => lopdf::text_string("Проверить сумму"),
+            "T" => lopdf::text_string("Иванов"),
+            "M" => Object::string_literal("D:20240131120000+03'00'"),
+            "C" => vec![1.into(), 0.into(), 0.into()],
+            "Popup" => popup,
+        }));
+        let reply = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Text", "Rect" => rect(50, 700), "P" => page_id,
+            "Contents" => lopdf::text_string("Исправлено"), "T" => lopdf::text_string("Петров"), "IRT" => note_id, "RT" => "R",
+        });
+        let highlight = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Highlight", "Rect" => rect(10, 688), "P" => page_id,
+            "C" => vec![1.into(), 1.into(), 0.into()],
+            "QuadPoints" => vec![10.into(), 700.into(), 90.into(), 700.into(), 10.into(), 688.into(), 90.into(), 688.into()],
+        });
+        document.objects.insert(page_id, Object::Dictionary(dictionary!
+        {
+            "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "Annots" => vec![note_id.into(), popup.into(), reply.into(), highlight.into()],
+        }));
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_page_annotations()
+    {
+        let bytes = sample();
+        let pdfium = PdfService::get_instance().unwrap();
+        let document = pdfium.load_pdf_from_byte_slice(&bytes, None).unwrap();
+        let page = document.pages().get(0).unwrap();
+        let annotations = page_annotations(&page, &PdfRenderConfig::new().set_target_width(595));
+        //всплывающее окно не выводится отдельно, его текст - это contents заметки
+        assert_eq!(annotations.iter().map(|a| a.kind).collect::<Vec<_>>(), vec![AnnotationKind::Text, AnnotationKind::Text, AnnotationKind::Highlight]);
+        let note = &annotations[0];
+        assert_eq!(note.contents.as_deref(), Some("Проверить сумму"));
+        assert_eq!(note.author.as_deref(), Some("Иванов"));
+        assert_eq!(note.modified.as_deref(), Some("D:20240131120000+03'00'"));
+        assert_eq!(note.color.as_deref(), Some("#FF0000"));
+        assert_eq!(note.interior_color, None);
+        assert!(note.quad_points.is_empty());
+        let reply = &annotations[1];
+        assert_eq!((reply.contents.as_deref(), reply.author.as_deref()), (Some("Исправлено"), Some("Петров")));
+        let highlight = &annotations[2];
+        assert_eq!(highlight.color.as_deref(), Some("#FFFF00"));
+        assert_eq!(highlight.quad_points, vec![QuadPoints { points: [[10.0, 700.0], [90.0, 700.0], [10.0, 688.0], [90.0, 688.0]] }]);
+    }
+}
@@ -0,0 +1,106 @@
+use lopdf::{Document, Object};
+
+///Строка диапазонов страниц для импорта в pdfium ("3-7,10"), номера с 1
+///порядок страниц сохраняется, подряд идущие по возрастанию номера сворачиваются в диапазон
+pub(crate) fn page_range_string(pages: &[u32]) -> String
+{
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for &page in pages
+    {
+        match ranges.last_mut()
+        {
+            Some((_, end)) if *end + 1 == page => *end = page,
+            _ => ranges.push((page, page))
+        }
+    }
+    ranges.iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+///Копирование метаданных (словарь Info и XMP поток каталога) из `source` в сохраненный pdf `target`
+///pdfium не умеет записывать метаданные, поэтому документ после pdfium дописывается через lopdf
+pub(crate) fn copy_metadata(source: &Document, target: &[u8]) -> Result<Vec<u8>, lopdf::Error>
+{
+    let mut document = Document::load_mem(target)?;
+    let info = source.trailer.get(b"Info").ok()
+        .and_then(|i| source.dereference(i).ok())
+        .and_then(|(_, i)| i.as_dict().ok());
+    if let Some(info) = info
+    {
+        let mut copy = lopdf::Dictionary::new();
+        for (key, value) in info.iter()
+        {
+            //значения по ссылкам разыменовываются, вложенные словари и потоки в Info не нужны
+            if let Ok((_, value)) = source.dereference(value)
+                && !matches!(value, Object::Dictionary(_) | Object::Stream(_) | Object::Reference(_))
+            {
+                copy.set(key.clone(), value.clone());
+            }
+        }
+        let id = document.add_object(copy);
+        document.trailer.set("Info", id);
+    }
+    let xmp = source.catalog().ok()
+        .and_then(|c| c.get(b"Metadata").ok())
+        .and_then(|m| source.dereference(m).ok())
+        .and_then(|(_, m)| m.as_stream().ok());
+    if let Some(xmp) = xmp
+    {
+        let id = document.add_object(xmp.clone());
+        document.catalog_mut()?.set("Metadata", id);
+    }
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object, Stream};
+    use super::{copy_metadata, page_range_string};
+
+    #[test]
+    fn test_page_range_string()
+    {
+        assert_eq!(page_range_string(&[3, 4, 5, 6, 7]), "3-7");
+        assert_eq!(page_range_string(&[1, 3, 5, 6, 7, 2]), "1,3,5-7,2");
+        assert_eq!(page_range_string(&[7, 6]), "7,6");
+    }
+
+    fn document(title: Option<&str>) -> Document
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page = document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1 }));
+        let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+        if let Some(title) = title
+        {
+            let xmp = document.add_object(Stream::new(dictionary! { "Type" => "Metadata", "Subtype" => "XML" }, b"<x:xmpmeta/>".to_vec()));
+            catalog.set("Metadata", xmp);
+            let title = document.add_object(lopdf::text_string(title));
+            let info = document.add_object(dictionary! { "Title" => title, "Author" => Object::string_literal("Registry") });
+            document.trailer.set("Info", info);
+        }
+        let catalog = document.add_object(catalog);
+        document.trailer.set("Root", catalog);
+        document
+    }
+
+    #[test]
+    fn test_copy_metadata()
+    {
+        let mut target = Vec::new();
+        document(None).save_to(&mut target).unwrap();
+        let copied = Document::load_mem(&copy_metadata(&document(Some("Пакет документов")), &target).unwrap()).unwrap();
+        let info = copied.trailer.get(b"Info").and_then(|i| copied.dereference(i)).unwrap().1.as_dict().unwrap();
+        assert_eq!(lopdf::decode_text_string(info.get(b"Title").unwrap()).unwrap(), "Пакет документов");
+        assert_eq!(info.get(b"Author").unwrap().as_str().unwrap(), b"Registry");
+        let xmp = copied.catalog().unwrap().get(b"Metadata").and_then(|m| copied.dereference(m)).unwrap().1.as_stream().unwrap();
+        assert_eq!(xmp.content, b"<x:xmpmeta/>");
+        assert_eq!(copied.get_pages().len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use lopdf::{Dictionary, Document, Object};
+use serde::Serialize;
+use crate::error::Error;
+
+///Вложенный в pdf файл
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attachment
+{
+    ///Номер вложения (с 0), по нему вложение читается и сохраняется
+    pub index: usize,
+    pub name: String,
+    pub description: Option<String>,
+    ///Размер файла в байтах
+    pub size: Option<usize>,
+    ///MIME тип, если он указан в pdf, например `application/vnd.ms-excel`
+    pub mime_type: Option<String>,
+    ///Даты в формате pdf, например `D:20240131120000+03'00'`
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    ///Номер страницы (с 1), если файл прикреплен аннотацией к странице, для вложений документа `None`
+    pub page: Option<u32>,
+    ///Содержимое файла есть в pdf, `false` у ссылок на внешние файлы (без /EF), прочитать их нельзя
+    pub embedded: bool,
+}
+impl Attachment
+{
+    ///Безопасное имя файла для сохранения на диск: без каталогов, чтобы `../` в имени не вывело за пределы папки
+    pub fn file_name(&self) -> String
+    {
+        let name = self.name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+        if name.is_empty() || name == "." || name == ".."
+        {
+            format!("attachment_{}", self.index)
+        }
+        else
+        {
+            name.to_owned()
+        }
+    }
+}
+
+///Максимальная глубина дерева имен, защита от зацикленных ссылок
+const MAX_NAME_TREE_DEPTH: usize = 32;
+
+pub(crate) fn attachments(document: &Document) -> Vec<Attachment>
+{
+    file_specs(document).into_iter().enumerate().map(|(index, (page, key, spec))|
+    {
+        let file = embedded_file(document, spec);
+        let params = file.and_then(|f| deref(document, f.dict.get(b"Params").ok()?)?.as_dict().ok());
+        let size = params.and_then(|p| p.get(b"Size").ok()?.as_i64().ok()).map(|s| s.max(0) as usize)
+            .or_else(|| file.map(|f| f.decompressed_content().map(|c| c.len()).unwrap_or(f.content.len())));
+        Attachment
+        {
+            index,
+            name: name(document, spec, key),
+            description: text(document, spec, b"Desc"),
+            size,
+            mime_type: file.and_then(|f| f.dict.get(b"Subtype").ok()?.as_name_str().ok()).map(|s| s.replace("#2F", "/")),
+            created: params.and_then(|p| text(document, p, b"CreationDate")),
+            modified: params.and_then(|p| text(document, p, b"ModDate")),
+            page,
+            embedded: file.is_some(),
+        }
+    }).collect()
+}
+
+///Содержимое вложения номер `index`, `None` если такого вложения нет
+pub(crate) fn read(document: &Document, index: usize, path: &str) -> Option<Result<Vec<u8>, Error>>
+{
+    let (_, key, spec) = file_specs(document).into_iter().nth(index)?;
+    let Some(file) = embedded_file(document, spec) else
+    {
+        return Some(Err(Error::AttachmentNotEmbedded(name(document, spec, key), path.to_owned())));
+    };
+    if file.dict.get(b"Filter").is_err()
+    {
+        return Some(Ok(file.content.clone()));
+    }
+    Some(file.decompressed_content().map_err(Error::from))
+}
+
+///Спецификации файлов: сначала вложения документа (дерево имен EmbeddedFiles), потом аннотации FileAttachment по страницам
+fn file_specs(document: &Document) -> Vec<(Option<u32>, Option<String>, &Dictionary)>
+{
+    let mut specs = Vec::new();
+    let tree = document.catalog().ok()
+        .and_then(|c| deref(document, c.get(b"Names").ok()?))
+        .and_then(|n| deref(document, n.as_dict().ok()?.get(b"EmbeddedFiles").ok()?))
+        .and_then(|t| t.as_dict().ok());
+    if let Some(tree) = tree
+    {
+        name_tree(document, tree, 0, &mut |key, spec| specs.push((None, key, spec)));
+    }
+    for (page, id) in document.get_pages()
+    {
+        let Ok(annotations) = document.get_page_annotations(id) else { continue };
+        for annotation in annotations
+        {
+            if annotation.get(b"Subtype").and_then(|s| s.as_name()).ok() != Some(b"FileAttachment".as_slice())
+            {
+                continue;
+            }
+            if let Some(spec) = annotation.get(b"FS").ok().and_then(|fs| deref(document, fs)).and_then(|fs| fs.as_dict().ok())
+            {
+                specs.push((Some(page), None, spec));
+            }
+        }
+    }
+    specs
+}
+
+fn name_tree<'a>(document: &'a Document, node: &'a Dictionary, depth: usize, visitor: &mut dyn FnMut(Option<String>, &'a Dictionary))
+{
+    if depth > MAX_NAME_TREE_DEPTH
+    {
+        return;
+    }
+    if let Some(names) = node.get(b"Names").ok().and_then(|n| deref(document, n)).and_then(|n| n.as_array().ok())
+    {
+        for pair in names.chunks_exact(2)
+        {
+            if let Some(spec) = deref(document, &pair[1]).and_then(|s| s.as_dict().ok())
+            {
+                visitor(lopdf::decode_text_string(&pair[0]).ok(), spec);
+            }
+        }
+    }
+    if let Some(kids) = node.get(b"Kids").ok().and_then(|k| deref(document, k)).and_then(|k| k.as_array().ok())
+    {
+        for kid in kids
+        {
+            if let Some(kid) = deref(document, kid).and_then(|k| k.as_dict().ok())
+            {
+                name_tree(document, kid, depth + 1, visitor);
+            }
+        }
+    }
+}
+
+fn name(document: &Document, spec: &Dictionary, key: Option<String>) -> String
+{
+    text(document, spec, b"UF").or_else(|| text(document, spec, b"F")).or(key).unwrap_or_default()
+}
+
+fn embedded_file<'a>(document: &'a Document, spec: &'a Dictionary) -> Option<&'a lopdf::Stream>
+{
+    let ef = deref(document, spec.get(b"EF").ok()?)?.as_dict().ok()?;
+    let file = ef.get(b"UF").or_else(|_| ef.get(b"F")).ok()?;
+    deref(document, file)?.as_stream().ok()
+}
+
+fn deref<'a>(document: &'a Document, object: &'a Object) -> Option<&'a Object>
+{
+    document.dereference(object).ok().map(|(_, o)| o)
+}
+
+fn text(document: &Document, dict: &Dictionary, key: &[u8]) -> Option<String>
+{
+    lopdf::decode_text_string(deref(document, dict.get(key).ok()?)?).ok()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object, Stream};
+    use crate::error::Error;
+    use super::{attachments, read, Attachment};
+
+    ///Документ с одним вложением в дереве имен, одним прикрепленным к странице файлом и ссылкой на внешний файл
+    fn sample() -> Document
+    {
+        let mut document = Document::with_version("1.7");
+        let mut sheet = Stream::new(dictionary! { "Type" => "EmbeddedFile", "Subtype" => "text/csv", "Params" => dictionary! { "Size" => 11, "ModDate" => Object::string_literal("D:20240131120000+03'00'") } }, b"a;b\n1;2\n3;4".to_vec());
+        sheet.compress().unwrap();
+        let sheet = document.add_object(sheet);
+        let spec = document.add_object(dictionary! { "Type" => "Filespec", "F" => Object::string_literal("report.csv"), "UF" => lopdf::text_string("отчет.csv"), "Desc" => Object::string_literal("Report"), "EF" => dictionary! { "F" => sheet } });
+        let sig = document.add_object(Stream::new(dictionary! { "Type" => "EmbeddedFile" }, b"signature".to_vec()));
+        let annotation = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "FileAttachment", "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()], "FS" => dictionary! { "Type" => "Filespec", "F" => Object::string_literal("../../doc.sig"), "EF" => dictionary! { "F" => sig } } });
+        let external = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "FileAttachment", "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()], "FS" => dictionary! { "Type" => "Filespec", "F" => Object::string_literal("scan.tif") } });
+        let pages_id = document.new_object_id();
+        let page = document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Annots" => vec![annotation.into(), external.into()] });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1 }));
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "Names" => dictionary! { "EmbeddedFiles" => dictionary! { "Names" => vec![Object::string_literal("report.csv"), spec.into()] } } });
+        document.trailer.set("Root", catalog);
+        document
+    }
+
+    ///Сохранение и повторное чтение, чтобы проверить разбор записанного файла
+    fn reload(mut document: Document) -> Document
+    {
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        Document::load_mem(&buffer).unwrap()
+    }
+
+    #[test]
+    fn test_list_attachments()
+    {
+        let list = attachments(&reload(sample()));
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0], Attachment
+        {
+            index: 0,
+            name: "отчет.csv".to_owned(),
+            description: Some("Report".to_owned()),
+            size: Some(11),
+            mime_type: Some("text/csv".to_owned()),
+            created: None,
+            modified: Some("D:20240131120000+03'00'".to_owned()),
+            page: None,
+            embedded: true,
+        });
+        assert_eq!(list[1].page, Some(1));
+        assert_eq!(list[1].size, Some(9));
+        //каталоги из имени не попадают в имя файла на диске
+        assert_eq!(list[1].file_name(), "doc.sig");
+        assert_eq!((list[2].name.as_str(), list[2].size, list[2].embedded), ("scan.tif", None, false));
+    }
+
+    #[test]
+    fn test_read_attachment()
+    {
+        let document = sample();
+        assert_eq!(read(&document, 0, "a.pdf").unwrap().unwrap(), b"a;b\n1;2\n3;4");
+        assert_eq!(read(&document, 1, "a.pdf").unwrap().unwrap(), b"signature");
+        //у внешнего файла нет содержимого, пустой файл вместо него не отдается
+        assert!(matches!(read(&document, 2, "a.pdf"), Some(Err(Error::AttachmentNotEmbedded(name, _))) if name == "scan.tif"));
+        assert!(read(&document, 3, "a.pdf").is_none());
+    }
+}
@@ -0,0 +1,231 @@
+use image::{DynamicImage, GrayImage, Rgb, RgbImage, RgbaImage};
+use pdfium_render::prelude::PdfBitmapFormat;
+use crate::PageImageFormat;
+
+///Цвет фона по умолчанию, на который накладывается прозрачность для форматов без альфа канала
+pub(crate) const DEFAULT_BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+
+///Преобразование байт отрендеренной страницы (результат `PdfBitmap::as_rgba_bytes`) в изображение
+///с набором каналов, который поддерживает выбранный формат
+///для `Gray` pdfium отдает байты как есть, поэтому строки могут быть выровнены (stride > width)
+///для `BGR` и `BGRx` альфа канала фактически нет, поэтому изображение всегда rgb
+///для `BGRA` прозрачность сохраняется для png и webp, а для jpeg накладывается на `background`
+pub(crate) fn bitmap_to_image(bytes: Vec<u8>, width: u32, height: u32, bitmap_format: PdfBitmapFormat, image_format: PageImageFormat, background: Rgb<u8>) -> Option<DynamicImage>
+{
+    match bitmap_format
+    {
+        PdfBitmapFormat::Gray => gray_from_strided(bytes, width, height).map(DynamicImage::ImageLuma8),
+        PdfBitmapFormat::BGRA =>
+        {
+            let rgba = RgbaImage::from_raw(width, height, bytes)?;
+            Some(fit_to_format(DynamicImage::ImageRgba8(rgba), image_format, background))
+        },
+        _ =>
+        {
+            let rgba = RgbaImage::from_raw(width, height, bytes)?;
+            Some(DynamicImage::ImageRgb8(drop_alpha(&rgba)))
+        }
+    }
+}
+
+///Приведение изображения к набору каналов, который умеет кодировать выбранный формат
+///jpeg не поддерживает альфа канал, поэтому прозрачность накладывается на `background`,
+///остальные форматы принимают l8, la8, rgb8 и rgba8
+pub(crate) fn fit_to_format(image: DynamicImage, image_format: PageImageFormat, background: Rgb<u8>) -> DynamicImage
+{
+    match image_format
+    {
+        PageImageFormat::Jpeg => remove_alpha(image, background),
+        _ => match image
+        {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => image,
+            other if other.color().has_alpha() => DynamicImage::ImageRgba8(other.into_rgba8()),
+            other => DynamicImage::ImageRgb8(other.into_rgb8())
+        }
+    }
+}
+
+///Удаление альфа канала наложением изображения на `background`, результат всегда l8 или rgb8
+pub(crate) fn remove_alpha(image: DynamicImage, background: Rgb<u8>) -> DynamicImage
+{
+    match image
+    {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => image,
+        DynamicImage::ImageLumaA8(la) if is_gray(background) =>
+        {
+            DynamicImage::ImageLuma8(flatten_gray_alpha(&la, background))
+        },
+        other => DynamicImage::ImageRgb8(flatten_alpha(&other.into_rgba8(), background))
+    }
+}
+
+///Наложение изображения с альфа каналом на сплошной фон
+pub(crate) fn flatten_alpha(image: &RgbaImage, background: Rgb<u8>) -> RgbImage
+{
+    let mut out = RgbImage::new(image.width(), image.height());
+    for (src, dst) in image.pixels().zip(out.pixels_mut())
+    {
+        let [r, g, b, a] = src.0;
+        *dst = Rgb([blend(r, background[0], a), blend(g, background[1], a), blend(b, background[2], a)]);
+    }
+    out
+}
+
+fn flatten_gray_alpha(image: &image::GrayAlphaImage, background: Rgb<u8>) -> GrayImage
+{
+    let mut out = GrayImage::new(image.width(), image.height());
+    for (src, dst) in image.pixels().zip(out.pixels_mut())
+    {
+        let [l, a] = src.0;
+        dst.0 = [blend(l, background[0], a)];
+    }
+    out
+}
+
+fn drop_alpha(image: &RgbaImage) -> RgbImage
+{
+    let mut out = RgbImage::new(image.width(), image.height());
+    for (src, dst) in image.pixels().zip(out.pixels_mut())
+    {
+        let [r, g, b, _] = src.0;
+        *dst = Rgb([r, g, b]);
+    }
+    out
+}
+
+///Копирование строк серого изображения без байт выравнивания
+fn gray_from_strided(bytes: Vec<u8>, width: u32, height: u32) -> Option<GrayImage>
+{
+    if height == 0 || width == 0
+    {
+        return None;
+    }
+    let stride = bytes.len() / height as usize;
+    if stride < width as usize
+    {
+        return None;
+    }
+    if stride == width as usize
+    {
+        return GrayImage::from_raw(width, height, bytes);
+    }
+    let mut packed = Vec::with_capacity(width as usize * height as usize);
+    for row in bytes.chunks_exact(stride).take(height as usize)
+    {
+        packed.extend_from_slice(&row[..width as usize]);
+    }
+    GrayImage::from_raw(width, height, packed)
+}
+
+fn blend(color: u8, background: u8, alpha: u8) -> u8
+{
+    let a = alpha as u32;
+    ((color as u32 * a + background as u32 * (255 - a) + 127) / 255) as u8
+}
+
+fn is_gray(color: Rgb<u8>) -> bool
+{
+    color[0] == color[1] && color[1] == color[2]
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{DynamicImage, Rgb};
+    use pdfium_render::prelude::PdfBitmapFormat;
+    use crate::{encoder::encode_image, EncoderSettings, PageImageFormat};
+    use super::{bitmap_to_image, DEFAULT_BACKGROUND};
+
+    const BITMAP_FORMATS: [PdfBitmapFormat; 4] = [PdfBitmapFormat::Gray, PdfBitmapFormat::BGR, PdfBitmapFormat::BGRx, PdfBitmapFormat::BGRA];
+    const IMAGE_FORMATS: [PageImageFormat; 3] = [PageImageFormat::Jpeg, PageImageFormat::Png, PageImageFormat::Webp];
+
+    ///Байты в том виде, в котором их отдает `as_rgba_bytes` для 3x2 страницы:
+    ///gray с выравниванием строки до 4 байт, остальные форматы - rgba
+    fn sample_bytes(format: PdfBitmapFormat) -> Vec<u8>
+    {
+        match format
+        {
+            PdfBitmapFormat::Gray => vec![10, 20, 30, 0, 40, 50, 60, 0],
+            PdfBitmapFormat::BGRA => [[255, 0, 0, 255], [0, 255, 0, 128], [0, 0, 255, 0]].repeat(2).concat(),
+            _ => [[255, 0, 0, 0], [0, 255, 0, 0], [0, 0, 255, 0]].repeat(2).concat()
+        }
+    }
+
+    #[test]
+    fn test_all_format_combinations()
+    {
+        for bitmap_format in BITMAP_FORMATS
+        {
+            for image_format in IMAGE_FORMATS
+            {
+                let image = bitmap_to_image(sample_bytes(bitmap_format), 3, 2, bitmap_format, image_format, DEFAULT_BACKGROUND)
+                    .unwrap_or_else(|| panic!("{:?} -> {:?}", bitmap_format, image_format));
+                assert_eq!((image.width(), image.height()), (3, 2));
+                let encoded = encode_image(&image, image_format, &EncoderSettings::default())
+                    .unwrap_or_else(|e| panic!("{:?} -> {:?}: {}", bitmap_format, image_format, e));
+                let decoded = image::load_from_memory(&encoded).unwrap();
+                assert_eq!((decoded.width(), decoded.height()), (3, 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_gray_stride_removed()
+    {
+        let image = bitmap_to_image(sample_bytes(PdfBitmapFormat::Gray), 3, 2, PdfBitmapFormat::Gray, PageImageFormat::Png, DEFAULT_BACKGROUND).unwrap();
+        assert_eq!(image.as_luma8().unwrap().as_raw(), &vec![10, 20, 30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn test_channels_without_alpha_are_rgb()
+    {
+        for image_format in IMAGE_FORMATS
+        {
+            let image = bitmap_to_image(sample_bytes(PdfBitmapFormat::BGRx), 3, 2, PdfBitmapFormat::BGRx, image_format, DEFAULT_BACKGROUND).unwrap();
+            let rgb = image.as_rgb8().unwrap();
+            assert_eq!(rgb.get_pixel(0, 0), &Rgb([255, 0, 0]));
+            assert_eq!(rgb.get_pixel(2, 1), &Rgb([0, 0, 255]));
+        }
+    }
+
+    #[test]
+    fn test_alpha_kept_for_png_and_webp()
+    {
+        for image_format in [PageImageFormat::Png, PageImageFormat::Webp]
+        {
+            let image = bitmap_to_image(sample_bytes(PdfBitmapFormat::BGRA), 3, 2, PdfBitmapFormat::BGRA, image_format, DEFAULT_BACKGROUND).unwrap();
+            let encoded = encode_image(&image, image_format, &EncoderSettings::default()).unwrap();
+            let decoded = image::load_from_memory(&encoded).unwrap().into_rgba8();
+            assert_eq!(decoded.get_pixel(1, 0).0, [0, 255, 0, 128]);
+            assert_eq!(decoded.get_pixel(2, 0).0[3], 0);
+        }
+    }
+
+    #[test]
+    fn test_alpha_flattened_for_jpeg()
+    {
+        let background = Rgb([0, 0, 0]);
+        let image = bitmap_to_image(sample_bytes(PdfBitmapFormat::BGRA), 3, 2, PdfBitmapFormat::BGRA, PageImageFormat::Jpeg, background).unwrap();
+        let rgb = image.as_rgb8().unwrap();
+        assert_eq!(rgb.get_pixel(0, 0), &Rgb([255, 0, 0]));
+        assert_eq!(rgb.get_pixel(1, 0), &Rgb([0, 128, 0]));
+        assert_eq!(rgb.get_pixel(2, 0), &Rgb([0, 0, 0]));
+        let white = bitmap_to_image(sample_bytes(PdfBitmapFormat::BGRA), 3, 2, PdfBitmapFormat::BGRA, PageImageFormat::Jpeg, DEFAULT_BACKGROUND).unwrap();
+        assert_eq!(white.as_rgb8().unwrap().get_pixel(2, 0), &Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn test_wrong_buffer_length()
+    {
+        assert!(bitmap_to_image(vec![0; 10], 3, 2, PdfBitmapFormat::BGRA, PageImageFormat::Png, DEFAULT_BACKGROUND).is_none());
+        assert!(bitmap_to_image(vec![0; 4], 3, 2, PdfBitmapFormat::Gray, PageImageFormat::Png, DEFAULT_BACKGROUND).is_none());
+    }
+
+    #[test]
+    fn test_gray_alpha_flattened_for_jpeg()
+    {
+        let la = image::GrayAlphaImage::from_raw(1, 1, vec![0, 0]).unwrap();
+        let image = super::fit_to_format(DynamicImage::ImageLumaA8(la), PageImageFormat::Jpeg, DEFAULT_BACKGROUND);
+        assert_eq!(image.as_luma8().unwrap().as_raw(), &vec![255]);
+    }
+}
@@ -0,0 +1,168 @@
+use image::GrayImage;
+use pdfium_render::prelude::{PdfPage, PdfRenderConfig};
+use crate::{bitmap, error::Error, PageImageFormat};
+
+///Разрешение рендера для оценки заполненности, одна строка текста при нем еще заметна, а пылинки сливаются с фоном
+const RENDER_DPI: f32 = 50.0;
+///Поля страницы (доля с каждой стороны), которые не учитываются: края скана, тени от дыроколов и скоб
+const MARGIN: f32 = 0.05;
+///Насколько пиксель должен быть темнее бумаги, чтобы считаться краской
+const INK_CONTRAST: u8 = 48;
+///Сколько символов текстового слоя достаточно, чтобы страница не считалась пустой
+const MIN_TEXT_CHARS: usize = 3;
+
+///Заполненность страницы
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PageInk
+{
+    ///Доля пикселей с краской от 0 до 1 без полей и одиночных точек
+    pub coverage: f32,
+    ///Количество видимых символов текстового слоя
+    pub text_chars: usize,
+}
+impl PageInk
+{
+    ///Страница пустая, если в текстовом слое почти нет символов и краски не больше `threshold`
+    pub fn is_blank(&self, threshold: f32) -> bool
+    {
+        self.text_chars < MIN_TEXT_CHARS && self.coverage <= threshold
+    }
+}
+
+pub(crate) fn page_ink(page: &PdfPage, path: &str, page_number: u32) -> Result<PageInk, Error>
+{
+    let text_chars = page.text()?.chars().iter()
+        .filter(|c| c.unicode_char().is_some_and(|ch| !ch.is_whitespace() && !ch.is_control()))
+        .count();
+    let config = PdfRenderConfig::new().scale_page_by_factor(RENDER_DPI / 72.0);
+    let bitmap = page.render_with_config(&config)?;
+    let image = bitmap::bitmap_to_image(bitmap.as_rgba_bytes(), bitmap.width() as u32, bitmap.height() as u32, bitmap.format().unwrap_or_default(), PageImageFormat::Jpeg, bitmap::DEFAULT_BACKGROUND)
+        .ok_or(Error::ExtractDynamicImageError(path.to_owned(), page_number))?
+        .into_luma8();
+    Ok(PageInk { coverage: ink_coverage(&image), text_chars })
+}
+
+///Номера страниц (с 1), которые остаются после удаления пустых страниц `blank`,
+///если пустые все страницы, то ошибка: pdf без страниц не создается
+pub(crate) fn kept_pages(pages_count: u32, blank: &[u32], path: &str) -> Result<Vec<u32>, Error>
+{
+    let pages: Vec<u32> = (1..=pages_count).filter(|p| !blank.contains(p)).collect();
+    if pages.is_empty()
+    {
+        return Err(Error::AllPagesBlank(path.to_owned()));
+    }
+    Ok(pages)
+}
+
+///Доля пикселей с краской: уровень бумаги берется по светлым пикселям (у сканов бумага серая),
+///поля не учитываются, а пиксель без соседей с краской считается шумом
+pub(crate) fn ink_coverage(image: &GrayImage) -> f32
+{
+    let (width, height) = image.dimensions();
+    let (margin_x, margin_y) = ((width as f32 * MARGIN) as u32, (height as f32 * MARGIN) as u32);
+    let (x0, y0, x1, y1) = (margin_x, margin_y, width.saturating_sub(margin_x), height.saturating_sub(margin_y));
+    if x1 <= x0 || y1 <= y0
+    {
+        return 0.0;
+    }
+    let mut histogram = [0u32; 256];
+    for y in y0..y1
+    {
+        for x in x0..x1
+        {
+            histogram[image.get_pixel(x, y).0[0] as usize] += 1;
+        }
+    }
+    let total = (x1 - x0) * (y1 - y0);
+    //яркость, светлее которой 10% пикселей, - уровень бумаги
+    let mut brighter = 0;
+    let mut paper = 255;
+    for level in (0..256).rev()
+    {
+        brighter += histogram[level];
+        if brighter * 10 >= total
+        {
+            paper = level as u8;
+            break;
+        }
+    }
+    let ink_level = paper.saturating_sub(INK_CONTRAST);
+    let ink = |x: u32, y: u32| image.get_pixel(x, y).0[0] < ink_level;
+    let mut count = 0u32;
+    for y in y0..y1
+    {
+        for x in x0..x1
+        {
+            if !ink(x, y)
+            {
+                continue;
+            }
+            let has_neighbour = (y.saturating_sub(1)..=(y + 1).min(height - 1))
+                .flat_map(|ny| (x.saturating_sub(1)..=(x + 1).min(width - 1)).map(move |nx| (nx, ny)))
+                .any(|(nx, ny)| (nx, ny) != (x, y) && ink(nx, ny));
+            if has_neighbour
+            {
+                count += 1;
+            }
+        }
+    }
+    count as f32 / total as f32
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{GrayImage, Luma};
+    use crate::error::Error;
+    use super::{ink_coverage, kept_pages, PageInk};
+
+    #[test]
+    fn test_ink_coverage()
+    {
+        //серая бумага скана с пылинками и тенью у края
+        let mut page = GrayImage::from_pixel(400, 560, Luma([200]));
+        for (x, y) in [(50, 60), (120, 300), (333, 111), (200, 500)]
+        {
+            page.put_pixel(x, y, Luma([20]));
+        }
+        for y in 0..560
+        {
+            for x in 0..10
+            {
+                page.put_pixel(x, y, Luma([0]));
+            }
+        }
+        assert_eq!(ink_coverage(&page), 0.0);
+        //строка текста
+        for y in 100..106
+        {
+            for x in 50..350
+            {
+                if x % 3 != 0
+                {
+                    page.put_pixel(x, y, Luma([60]));
+                }
+            }
+        }
+        let coverage = ink_coverage(&page);
+        assert!(coverage > 0.005 && coverage < 0.01, "{}", coverage);
+    }
+
+    #[test]
+    fn test_is_blank()
+    {
+        assert!(PageInk { coverage: 0.0005, text_chars: 0 }.is_blank(0.001));
+        assert!(!PageInk { coverage: 0.002, text_chars: 0 }.is_blank(0.001));
+        //белый текст или текстовый слой поверх пустого скана: символы есть, страница не пустая
+        assert!(!PageInk { coverage: 0.0, text_chars: 40 }.is_blank(0.001));
+    }
+
+    #[test]
+    fn test_kept_pages()
+    {
+        assert_eq!(kept_pages(5, &[2, 5], "scan.pdf").unwrap(), vec![1, 3, 4]);
+        assert_eq!(kept_pages(2, &[], "scan.pdf").unwrap(), vec![1, 2]);
+        //пачка пустых листов - не ошибка выбора страниц, а отдельный случай
+        assert!(matches!(kept_pages(3, &[1, 2, 3], "scan.pdf"), Err(Error::AllPagesBlank(path)) if path == "scan.pdf"));
+    }
+}
@@ -0,0 +1,277 @@
+use pdfium_render::prelude::{PdfPage, PdfPageObject, PdfPageObjectCommon, PdfPageObjectsCommon, PdfPageTextRenderMode, PdfiumError};
+use serde::Serialize;
+use crate::geometry::PageRect;
+
+///Доля страницы под изображениями, начиная с которой страница считается сканом
+const SCAN_COVERAGE: f32 = 0.8;
+///Доля страницы под изображениями, начиная с которой страница с текстом считается смешанной
+const MIXED_COVERAGE: f32 = 0.25;
+///Сколько символов нужно, чтобы считать, что у страницы есть текстовый слой
+const MIN_TEXT_CHARS: usize = 10;
+///Доля нераспознаваемых символов, начиная с которой текст считается мусором (битая таблица ToUnicode)
+const GARBAGE_RATIO: f32 = 0.3;
+///Размер сетки, по которой считается площадь изображений (перекрытия учитываются один раз)
+const COVERAGE_GRID: usize = 64;
+
+///Происхождение страницы
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageClass
+{
+    ///Страница создана в редакторе: текст и векторная графика
+    Digital,
+    ///Скан без текстового слоя
+    Scanned,
+    ///Скан с текстовым слоем (как правило невидимым текстом после OCR)
+    ScannedWithText,
+    ///Часть страницы - изображения, часть - текст или вектор
+    Mixed,
+}
+
+///Показатели страницы, по которым определяется ее происхождение
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct PageMetrics
+{
+    pub text_objects: usize,
+    ///Текстовые объекты с режимом отрисовки 3 (невидимый текст)
+    pub invisible_text_objects: usize,
+    pub image_objects: usize,
+    pub path_objects: usize,
+    ///Доля площади страницы под изображениями от 0 до 1
+    pub image_coverage: f32,
+    ///Количество символов текстового слоя без пробелов
+    pub text_chars: usize,
+    ///Доля символов без юникода, из частной области или U+FFFD - признак битой таблицы ToUnicode
+    pub garbage_ratio: f32,
+}
+impl PageMetrics
+{
+    ///Текст можно извлечь и он не мусор
+    pub fn has_usable_text(&self) -> bool
+    {
+        self.text_chars >= MIN_TEXT_CHARS && self.garbage_ratio < GARBAGE_RATIO
+    }
+    ///Текст есть, но извлекается мусором
+    pub fn has_broken_text(&self) -> bool
+    {
+        self.text_chars >= MIN_TEXT_CHARS && self.garbage_ratio >= GARBAGE_RATIO
+    }
+}
+
+///Результат классификации страницы
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageClassification
+{
+    pub page: u32,
+    pub class: PageClass,
+    ///Странице нужен OCR: скан без текста, смешанная страница или текст с битой таблицей ToUnicode
+    pub needs_ocr: bool,
+    pub metrics: PageMetrics,
+}
+
+///Классификация всех страниц документа
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DocumentClassification
+{
+    ///Общий класс: класс страниц, если он у всех одинаковый, иначе `Mixed`
+    pub class: PageClass,
+    pub digital: u32,
+    pub scanned: u32,
+    pub scanned_with_text: u32,
+    pub mixed: u32,
+    pub pages: Vec<PageClassification>,
+}
+impl DocumentClassification
+{
+    ///Номера страниц (с 1), которые нужно отправить на OCR
+    pub fn needs_ocr_pages(&self) -> Vec<u32>
+    {
+        self.pages.iter().filter(|p| p.needs_ocr).map(|p| p.page).collect()
+    }
+    pub(crate) fn new(pages: Vec<PageClassification>) -> Self
+    {
+        let count = |class: PageClass| pages.iter().filter(|p| p.class == class).count() as u32;
+        let class = match pages.first()
+        {
+            Some(first) if pages.iter().all(|p| p.class == first.class) => first.class,
+            Some(_) => PageClass::Mixed,
+            None => PageClass::Digital
+        };
+        Self
+        {
+            class,
+            digital: count(PageClass::Digital),
+            scanned: count(PageClass::Scanned),
+            scanned_with_text: count(PageClass::ScannedWithText),
+            mixed: count(PageClass::Mixed),
+            pages,
+        }
+    }
+}
+
+pub(crate) fn classify_page(page: &PdfPage, page_number: u32) -> Result<PageClassification, PdfiumError>
+{
+    let mut metrics = PageMetrics::default();
+    let mut images = Vec::new();
+    for object in page.objects().iter()
+    {
+        collect(&object, &mut metrics, &mut images, None);
+    }
+    //область страницы без учета /Rotate и с настоящим началом координат, в тех же координатах, что и границы объектов
+    let page_rect = PageRect::from_pdf(&page.boundaries().bounding().map(|b| b.bounds).unwrap_or(page.page_size()));
+    metrics.image_coverage = coverage(&page_rect, &images);
+    let mut garbage = 0;
+    for c in page.text()?.chars().iter()
+    {
+        match c.unicode_char()
+        {
+            Some(ch) if ch.is_whitespace() => continue,
+            Some(ch) if !is_garbage(ch) => {},
+            _ => garbage += 1
+        }
+        metrics.text_chars += 1;
+    }
+    if metrics.text_chars > 0
+    {
+        metrics.garbage_ratio = garbage as f32 / metrics.text_chars as f32;
+    }
+    let class = classify(&metrics);
+    let needs_ocr = matches!(class, PageClass::Scanned | PageClass::Mixed) || metrics.has_broken_text();
+    Ok(PageClassification { page: page_number, class, needs_ocr, metrics })
+}
+
+///Подсчет объектов страницы, содержимое form xobject учитывается,
+///а за область вложенного изображения берется область самой формы: координаты вложенных объектов заданы в пространстве формы
+fn collect(object: &PdfPageObject, metrics: &mut PageMetrics, images: &mut Vec<PageRect>, form_bounds: Option<PageRect>)
+{
+    let bounds = || object.bounds().ok().map(|b| PageRect::from_pdf(&b.to_rect()));
+    match object
+    {
+        PdfPageObject::Text(text) =>
+        {
+            metrics.text_objects += 1;
+            if text.render_mode() == PdfPageTextRenderMode::Invisible
+            {
+                metrics.invisible_text_objects += 1;
+            }
+        },
+        PdfPageObject::Image(_) =>
+        {
+            metrics.image_objects += 1;
+            images.extend(form_bounds.or_else(bounds));
+        },
+        PdfPageObject::Path(_) => metrics.path_objects += 1,
+        PdfPageObject::XObjectForm(form) =>
+        {
+            let form_bounds = form_bounds.or_else(bounds);
+            for nested in form.iter()
+            {
+                collect(&nested, metrics, images, form_bounds);
+            }
+        },
+        _ => {}
+    }
+}
+
+///Доля страницы `page`, покрытая прямоугольниками, по сетке COVERAGE_GRID x COVERAGE_GRID
+fn coverage(page: &PageRect, rects: &[PageRect]) -> f32
+{
+    if page.width() <= 0.0 || page.height() <= 0.0 || rects.is_empty()
+    {
+        return 0.0;
+    }
+    let mut covered = 0;
+    for row in 0..COVERAGE_GRID
+    {
+        let y = page.bottom + (row as f32 + 0.5) * page.height() / COVERAGE_GRID as f32;
+        for column in 0..COVERAGE_GRID
+        {
+            let x = page.left + (column as f32 + 0.5) * page.width() / COVERAGE_GRID as f32;
+            if rects.iter().any(|r| x >= r.left && x <= r.right && y >= r.bottom && y <= r.top)
+            {
+                covered += 1;
+            }
+        }
+    }
+    covered as f32 / (COVERAGE_GRID * COVERAGE_GRID) as f32
+}
+
+fn is_garbage(ch: char) -> bool
+{
+    ch == '\u{FFFD}' || ch.is_control() || matches!(ch as u32, 0xE000..=0xF8FF | 0xF0000..=0x10FFFF)
+}
+
+fn classify(metrics: &PageMetrics) -> PageClass
+{
+    if metrics.image_coverage >= SCAN_COVERAGE
+    {
+        return if metrics.has_usable_text() { PageClass::ScannedWithText } else { PageClass::Scanned };
+    }
+    if metrics.image_coverage >= MIXED_COVERAGE
+    {
+        //изображение на части страницы без текста - скан с полями
+        return if metrics.text_chars > 0 || metrics.path_objects > 0 { PageClass::Mixed } else { PageClass::Scanned };
+    }
+    PageClass::Digital
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::geometry::PageRect;
+    use super::{classify, coverage, is_garbage, DocumentClassification, PageClass, PageClassification, PageMetrics};
+
+    #[test]
+    fn test_coverage()
+    {
+        //два перекрывающихся изображения на половине страницы считаются один раз
+        let a4 = PageRect::new(0.0, 0.0, 595.0, 842.0);
+        let rects = [PageRect::new(0.0, 0.0, 595.0, 421.0), PageRect::new(0.0, 200.0, 595.0, 421.0)];
+        assert!((coverage(&a4, &rects) - 0.5).abs() < 0.02);
+        assert_eq!(coverage(&a4, &[]), 0.0);
+        //изображение больше страницы
+        assert_eq!(coverage(&a4, &[PageRect::new(-10.0, -10.0, 700.0, 900.0)]), 1.0);
+    }
+
+    #[test]
+    fn test_coverage_page_bounds()
+    {
+        //скан с /Rotate 90: область страницы не повернута, как и изображение на ней,
+        //повернутые размеры 842x595 дали бы около 0.7
+        let scan = PageRect::new(0.0, 0.0, 595.0, 842.0);
+        assert_eq!(coverage(&scan, &[scan]), 1.0);
+        assert!(coverage(&PageRect::new(0.0, 0.0, 842.0, 595.0), &[scan]) < 0.75);
+        //MediaBox не от нуля: изображение на всю страницу и на ее нижнюю половину
+        let offset = PageRect::new(100.0, 200.0, 695.0, 1042.0);
+        assert_eq!(coverage(&offset, &[offset]), 1.0);
+        assert!((coverage(&offset, &[PageRect::new(100.0, 200.0, 695.0, 621.0)]) - 0.5).abs() < 0.02);
+        assert_eq!(coverage(&offset, &[PageRect::new(0.0, 0.0, 100.0, 200.0)]), 0.0);
+    }
+
+    #[test]
+    fn test_classify()
+    {
+        let scan = PageMetrics { image_objects: 1, image_coverage: 0.98, ..Default::default() };
+        assert_eq!(classify(&scan), PageClass::Scanned);
+        let ocr = PageMetrics { text_objects: 120, invisible_text_objects: 120, text_chars: 1800, ..scan };
+        assert_eq!(classify(&ocr), PageClass::ScannedWithText);
+        //текст из частной области юникода извлечь нельзя, страница считается сканом без текста
+        let garbage = PageMetrics { text_chars: 1800, garbage_ratio: 0.9, ..scan };
+        assert_eq!(classify(&garbage), PageClass::Scanned);
+        let digital = PageMetrics { text_objects: 40, text_chars: 2500, path_objects: 12, ..Default::default() };
+        assert_eq!(classify(&digital), PageClass::Digital);
+        assert_eq!(classify(&PageMetrics { image_objects: 1, image_coverage: 0.4, ..digital }), PageClass::Mixed);
+        assert_eq!(classify(&PageMetrics { image_objects: 1, image_coverage: 0.6, ..Default::default() }), PageClass::Scanned);
+        assert!(is_garbage('\u{E01A}') && is_garbage('\u{FFFD}') && !is_garbage('ж'));
+    }
+
+    #[test]
+    fn test_document_summary()
+    {
+        let page = |page: u32, class: PageClass| PageClassification { page, class, needs_ocr: class == PageClass::Scanned, metrics: PageMetrics::default() };
+        let document = DocumentClassification::new(vec![page(1, PageClass::Digital), page(2, PageClass::Scanned), page(3, PageClass::Scanned)]);
+        assert_eq!((document.class, document.digital, document.scanned, document.mixed), (PageClass::Mixed, 1, 2, 0));
+        assert_eq!(document.needs_ocr_pages(), vec![2, 3]);
+        assert_eq!(DocumentClassification::new(vec![page(1, PageClass::Scanned)]).class, PageClass::Scanned);
+    }
+}
@@ -0,0 +1,269 @@
+use image::{DynamicImage, GrayImage, Rgb};
+use crate::bitmap;
+
+///Цветовой режим выходного изображения
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorMode
+{
+    #[default]
+    Color,
+    ///Оттенки серого 8 бит
+    Gray,
+    ///Черно-белое изображение, для png сохраняется как палитра 1 бит
+    Bilevel(Binarization),
+    ///Оттенки серого с ограниченным количеством уровней (2..=255), для png сохраняется как палитра 1/2/4/8 бит
+    GrayLevels { levels: u8, dither: bool },
+}
+impl ColorMode
+{
+    ///Количество уровней серого для режимов, которые можно сохранить палитрой
+    pub(crate) fn palette_levels(&self) -> Option<u8>
+    {
+        match self
+        {
+            ColorMode::Bilevel(_) => Some(2),
+            ColorMode::GrayLevels { levels, .. } => Some((*levels).max(2)),
+            _ => None
+        }
+    }
+}
+
+///Способ перевода серого изображения в черно-белое (1 бит)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binarization
+{
+    ///Все что темнее порога - черное
+    Threshold(u8),
+    ///Рассеивание ошибки Флойда-Стейнберга, лучше передает полутона и фотографии
+    FloydSteinberg,
+    ///Упорядоченное псевдосмешение матрицей Байера 8x8, растр без "червей" Флойда-Стейнберга, хорошо для e-ink
+    Ordered,
+    ///Адаптивный порог Саувола по окну `window` пикселей, подходит для сканов с неравномерным фоном
+    ///`k` обычно 0.2..0.5
+    Sauvola { window: u32, k: f32 },
+}
+impl Default for Binarization
+{
+    fn default() -> Self
+    {
+        Binarization::Threshold(128)
+    }
+}
+
+///Применение цветового режима, для всех режимов кроме `Color` результат - l8 без альфа канала
+pub(crate) fn apply_color_mode(image: DynamicImage, mode: ColorMode, background: Rgb<u8>) -> DynamicImage
+{
+    if mode == ColorMode::Color
+    {
+        return image;
+    }
+    let gray = bitmap::remove_alpha(image, background).into_luma8();
+    let out = match mode
+    {
+        ColorMode::Color | ColorMode::Gray => gray,
+        ColorMode::Bilevel(method) => binarize(&gray, method),
+        ColorMode::GrayLevels { levels, dither } => quantize(&gray, levels.max(2), dither)
+    };
+    DynamicImage::ImageLuma8(out)
+}
+
+///Перевод серого изображения в черно-белое, в результате пиксели только 0 (черный) и 255 (белый)
+pub(crate) fn binarize(gray: &GrayImage, method: Binarization) -> GrayImage
+{
+    match method
+    {
+        Binarization::Threshold(threshold) => threshold_image(gray, threshold),
+        Binarization::FloydSteinberg => quantize(gray, 2, true),
+        Binarization::Ordered => ordered(gray),
+        Binarization::Sauvola { window, k } => sauvola(gray, window, k)
+    }
+}
+
+///Значение серого для уровня `index` из `levels` равномерно распределенных уровней
+pub(crate) fn level_value(index: u8, levels: u8) -> u8
+{
+    ((index as u32 * 255 + (levels as u32 - 1) / 2) / (levels as u32 - 1)) as u8
+}
+
+///Номер ближайшего уровня для значения серого
+pub(crate) fn level_index(value: u8, levels: u8) -> u8
+{
+    ((value as u32 * (levels as u32 - 1) + 127) / 255) as u8
+}
+
+fn threshold_image(gray: &GrayImage, threshold: u8) -> GrayImage
+{
+    let mut out = gray.clone();
+    for p in out.pixels_mut()
+    {
+        p.0[0] = if p.0[0] < threshold { 0 } else { 255 };
+    }
+    out
+}
+
+///Приведение к `levels` уровням серого, с рассеиванием ошибки Флойда-Стейнберга или без
+fn quantize(gray: &GrayImage, levels: u8, dither: bool) -> GrayImage
+{
+    let (width, height) = (gray.width() as usize, gray.height() as usize);
+    let mut out = GrayImage::new(gray.width(), gray.height());
+    //ошибка (x16) для текущей и следующей строки, с запасом по одному пикселю с каждой стороны
+    let mut current = vec![0i32; width + 2];
+    let mut next = vec![0i32; width + 2];
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let old = gray.get_pixel(x as u32, y as u32).0[0] as i32 + current[x + 1] / 16;
+            let new = level_value(level_index(old.clamp(0, 255) as u8, levels), levels) as i32;
+            out.get_pixel_mut(x as u32, y as u32).0[0] = new as u8;
+            if dither
+            {
+                let err = old - new;
+                current[x + 2] += err * 7;
+                next[x] += err * 3;
+                next[x + 1] += err * 5;
+                next[x + 2] += err;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = 0);
+    }
+    out
+}
+
+const BAYER_8X8: [[u8; 8]; 8] =
+[
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn ordered(gray: &GrayImage) -> GrayImage
+{
+    let mut out = gray.clone();
+    for (x, y, p) in out.enumerate_pixels_mut()
+    {
+        let threshold = (BAYER_8X8[(y % 8) as usize][(x % 8) as usize] as u32 * 4 + 2) as u8;
+        p.0[0] = if p.0[0] <= threshold { 0 } else { 255 };
+    }
+    out
+}
+
+///Адаптивный порог Саувола: T = m * (1 + k * (s / 128 - 1)), где m и s - среднее и отклонение в окне
+///среднее и отклонение считаются через интегральные изображения, поэтому размер окна не влияет на скорость
+fn sauvola(gray: &GrayImage, window: u32, k: f32) -> GrayImage
+{
+    let (width, height) = (gray.width() as usize, gray.height() as usize);
+    let stride = width + 1;
+    let mut sum = vec![0u64; stride * (height + 1)];
+    let mut sum_sq = vec![0u64; stride * (height + 1)];
+    for y in 0..height
+    {
+        let mut row_sum = 0u64;
+        let mut row_sum_sq = 0u64;
+        for x in 0..width
+        {
+            let v = gray.get_pixel(x as u32, y as u32).0[0] as u64;
+            row_sum += v;
+            row_sum_sq += v * v;
+            sum[(y + 1) * stride + x + 1] = sum[y * stride + x + 1] + row_sum;
+            sum_sq[(y + 1) * stride + x + 1] = sum_sq[y * stride + x + 1] + row_sum_sq;
+        }
+    }
+    let half = (window.max(3) / 2) as usize;
+    let mut out = GrayImage::new(gray.width(), gray.height());
+    for y in 0..height
+    {
+        let (y0, y1) = (y.saturating_sub(half), (y + half + 1).min(height));
+        for x in 0..width
+        {
+            let (x0, x1) = (x.saturating_sub(half), (x + half + 1).min(width));
+            let area = ((x1 - x0) * (y1 - y0)) as f64;
+            let rect = |table: &[u64]| table[y1 * stride + x1] + table[y0 * stride + x0] - table[y0 * stride + x1] - table[y1 * stride + x0];
+            let mean = rect(&sum) as f64 / area;
+            let variance = (rect(&sum_sq) as f64 / area - mean * mean).max(0.0);
+            let threshold = mean * (1.0 + k as f64 * (variance.sqrt() / 128.0 - 1.0));
+            let v = gray.get_pixel(x as u32, y as u32).0[0] as f64;
+            out.get_pixel_mut(x as u32, y as u32).0[0] = if v <= threshold { 0 } else { 255 };
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{DynamicImage, GrayImage, RgbImage};
+    use crate::bitmap::DEFAULT_BACKGROUND;
+    use super::{apply_color_mode, binarize, Binarization, ColorMode};
+
+    #[test]
+    fn test_threshold()
+    {
+        let gray = GrayImage::from_raw(4, 1, vec![0, 100, 150, 255]).unwrap();
+        let bw = binarize(&gray, Binarization::Threshold(128));
+        assert_eq!(bw.as_raw(), &vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_floyd_steinberg_keeps_average_tone()
+    {
+        let gray = GrayImage::from_pixel(32, 32, image::Luma([128]));
+        let bw = binarize(&gray, Binarization::FloydSteinberg);
+        assert!(bw.pixels().all(|p| p.0[0] == 0 || p.0[0] == 255));
+        let black = bw.pixels().filter(|p| p.0[0] == 0).count();
+        //примерно половина пикселей должна быть черной
+        assert!((400..=624).contains(&black), "{}", black);
+    }
+
+    #[test]
+    fn test_ordered_keeps_average_tone()
+    {
+        let gray = GrayImage::from_pixel(16, 16, image::Luma([64]));
+        let bw = binarize(&gray, Binarization::Ordered);
+        let black = bw.pixels().filter(|p| p.0[0] == 0).count();
+        //четверть яркости - три четверти черных пикселей
+        assert_eq!(black, 192);
+    }
+
+    #[test]
+    fn test_sauvola_uneven_background()
+    {
+        //фон плавно темнеет слева направо, текст - темная полоса посередине
+        let gray = GrayImage::from_fn(60, 20, |x, y| if (9..11).contains(&y) { image::Luma([20]) } else { image::Luma([230 - (x * 2) as u8]) });
+        let bw = binarize(&gray, Binarization::Sauvola { window: 15, k: 0.34 });
+        assert!((0..60).all(|x| bw.get_pixel(x, 10).0[0] == 0));
+        assert!((0..60).all(|x| bw.get_pixel(x, 2).0[0] == 255));
+        //обычный порог на темной части фона дает черное
+        let plain = binarize(&gray, Binarization::Threshold(128));
+        assert_eq!(plain.get_pixel(59, 2).0[0], 0);
+    }
+
+    #[test]
+    fn test_gray_levels()
+    {
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 4, |x, _| image::Rgb([(x * 4) as u8; 3])));
+        let four = apply_color_mode(gray.clone(), ColorMode::GrayLevels { levels: 4, dither: false }, DEFAULT_BACKGROUND);
+        let mut values: Vec<u8> = four.as_luma8().unwrap().pixels().map(|p| p.0[0]).collect();
+        values.sort();
+        values.dedup();
+        assert_eq!(values, vec![0, 85, 170, 255]);
+        let dithered = apply_color_mode(gray, ColorMode::GrayLevels { levels: 4, dither: true }, DEFAULT_BACKGROUND);
+        assert!(dithered.as_luma8().unwrap().pixels().all(|p| [0, 85, 170, 255].contains(&p.0[0])));
+    }
+
+    #[test]
+    fn test_color_mode_passthrough()
+    {
+        let rgba = DynamicImage::ImageRgba8(image::RgbaImage::new(2, 2));
+        assert!(apply_color_mode(rgba.clone(), ColorMode::Color, DEFAULT_BACKGROUND).as_rgba8().is_some());
+        //прозрачное накладывается на белый фон
+        let gray = apply_color_mode(rgba, ColorMode::Gray, DEFAULT_BACKGROUND);
+        assert_eq!(gray.as_luma8().unwrap().get_pixel(0, 0).0[0], 255);
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+use pdfium_render::prelude::{PdfPage, PdfPagePaperSize, PdfPageRenderRotation, PdfPoints};
+use serde::Serialize;
+use crate::{assemble, error::Error, PageImageFormat, PdfService};
+
+///Откуда берется страница редактируемого документа
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EditedPageSource
+{
+    ///Страница исходного документа (нумерация с 1)
+    Original { page: u32 },
+    ///Пустая страница, размер в пунктах pdf
+    Blank { width: f32, height: f32 },
+}
+
+///Страница в сеансе редактирования
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EditedPage
+{
+    pub source: EditedPageSource,
+    ///Дополнительный поворот по часовой стрелке в градусах (0, 90, 180, 270) к повороту, который уже есть в pdf
+    pub rotation: u16,
+}
+
+///Сеанс редактирования страниц: поворот, перемещение, удаление и вставка пустых страниц
+///изменения накапливаются и применяются к исходному файлу при рендере и сохранении, сам файл не меняется
+///нумерация страниц во всех методах - текущая, с учетом уже сделанных изменений
+pub struct PageEditor
+{
+    service: PdfService,
+    pages: Vec<EditedPage>,
+}
+impl PageEditor
+{
+    pub(crate) fn new(service: PdfService, pages_count: u32) -> Self
+    {
+        let pages = (1..=pages_count).map(|page| EditedPage { source: EditedPageSource::Original { page }, rotation: 0 }).collect();
+        Self { service, pages }
+    }
+    pub fn pages(&self) -> &[EditedPage]
+    {
+        &self.pages
+    }
+    pub fn pages_count(&self) -> u32
+    {
+        self.pages.len() as u32
+    }
+    ///Поворот страницы на `quarter_turns` четвертей оборота, положительные - по часовой стрелке
+    pub fn rotate(&mut self, page_number: u32, quarter_turns: i32) -> Result<(), Error>
+    {
+        let index = self.index(page_number)?;
+        let page = &mut self.pages[index];
+        let quarters = (page.rotation as i32 / 90 + quarter_turns).rem_euclid(4);
+        page.rotation = quarters as u16 * 90;
+        Ok(())
+    }
+    ///Перемещение страницы `from` так, чтобы она стала страницей номер `to`
+    pub fn move_page(&mut self, from: u32, to: u32) -> Result<(), Error>
+    {
+        let from = self.index(from)?;
+        let to = self.index(to)?;
+        let page = self.pages.remove(from);
+        self.pages.insert(to, page);
+        Ok(())
+    }
+    pub fn delete_page(&mut self, page_number: u32) -> Result<(), Error>
+    {
+        let index = self.index(page_number)?;
+        self.pages.remove(index);
+        Ok(())
+    }
+    ///Вставка пустой страницы размером `width` x `height` пунктов, после вставки она становится страницей номер `position`
+    ///`position` может быть на единицу больше количества страниц - вставка в конец
+    pub fn insert_blank_page(&mut self, position: u32, width: f32, height: f32) -> Result<(), Error>
+    {
+        if position < 1 || position > self.pages_count() + 1
+        {
+            return Err(self.wrong_page(position));
+        }
+        self.pages.insert((position - 1) as usize, EditedPage { source: EditedPageSource::Blank { width, height }, rotation: 0 });
+        Ok(())
+    }
+
+    ///Рендер страницы с учетом всех изменений сеанса, настройки рендера и кодирования берутся из `PdfService`
+    pub async fn convert_page(&self, page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, Error>
+    {
+        let edited = self.pages[self.index(page_number)?];
+        let config = self.service.render_config();
+        let background = self.service.background();
+        let image = self.service.with_pdfium(move |pdfium, path, path_str|
+        {
+            match edited.source
+            {
+                EditedPageSource::Original { page } =>
+                {
+                    let document = pdfium.load_pdf_from_file(path, None)?;
+                    let mut current = document.pages().get((page - 1) as u16)?;
+                    rotate_page(&mut current, edited.rotation)?;
+                    PdfService::page_to_image(&current, &config, image_format, background, path_str, page_number)
+                },
+                EditedPageSource::Blank { width, height } =>
+                {
+                    let mut document = pdfium.create_new_pdf()?;
+                    let mut current = document.pages_mut().create_page_at_end(PdfPagePaperSize::Custom(PdfPoints::new(width), PdfPoints::new(height)))?;
+                    rotate_page(&mut current, edited.rotation)?;
+                    PdfService::page_to_image(&current, &config, image_format, background, path_str, page_number)
+                }
+            }
+        }).await?;
+        self.service.gen_image(image, page_number, image_format).await
+    }
+
+    ///Сохранение документа со всеми изменениями, метаданные исходного файла сохраняются
+    pub async fn save(&self) -> Result<Vec<u8>, Error>
+    {
+        let pages = self.pages.clone();
+        self.service.with_pdfium(move |pdfium, path, path_str|
+        {
+            if pages.is_empty()
+            {
+                return Err(Error::NonePageSelect(path_str.to_owned()));
+            }
+            let source = pdfium.load_pdf_from_file(path, None)?;
+            let mut document = pdfium.create_new_pdf()?;
+            //подряд идущие страницы исходного документа импортируются одним вызовом, чтобы шрифты и изображения не дублировались
+            let mut run: Vec<u32> = Vec::new();
+            for edited in &pages
+            {
+                match edited.source
+                {
+                    EditedPageSource::Original { page } => run.push(page),
+                    EditedPageSource::Blank { width, height } =>
+                    {
+                        import_run(&mut document, &source, &mut run)?;
+                        document.pages_mut().create_page_at_end(PdfPagePaperSize::Custom(PdfPoints::new(width), PdfPoints::new(height)))?;
+                    }
+                }
+            }
+            import_run(&mut document, &source, &mut run)?;
+            for (index, edited) in pages.iter().enumerate()
+            {
+                let mut page = document.pages().get(index as u16)?;
+                rotate_page(&mut page, edited.rotation)?;
+            }
+            let bytes = document.save_to_bytes()?;
+            let raw = lopdf::Document::load(path)?;
+            Ok(assemble::copy_metadata(&raw, &bytes)?)
+        }).await
+    }
+
+    ///То же что и `save`, но результат сохраняется в файл
+    pub async fn save_to_file<P: AsRef<Path>>(&self, output: P) -> Result<(), Error>
+    {
+        let pdf = self.save().await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    fn index(&self, page_number: u32) -> Result<usize, Error>
+    {
+        if page_number < 1 || page_number > self.pages_count()
+        {
+            return Err(self.wrong_page(page_number));
+        }
+        Ok((page_number - 1) as usize)
+    }
+    fn wrong_page(&self, page_number: u32) -> Error
+    {
+        Error::WrongPageSelect(self.service.get_path().to_owned(), self.pages_count(), page_number)
+    }
+}
+
+fn import_run(document: &mut pdfium_render::prelude::PdfDocument, source: &pdfium_render::prelude::PdfDocument, run: &mut Vec<u32>) -> Result<(), Error>
+{
+    if !run.is_empty()
+    {
+        let destination = document.pages().len();
+        document.pages_mut().copy_pages_from_document(source, &assemble::page_range_string(run), destination)?;
+        run.clear();
+    }
+    Ok(())
+}
+
+///Добавление поворота к повороту, который уже задан у страницы
+fn rotate_page(page: &mut PdfPage, degrees: u16) -> Result<(), Error>
+{
+    if degrees != 0
+    {
+        let current = quarters(page.rotation()?);
+        page.set_rotation(from_quarters(current + degrees / 90));
+    }
+    Ok(())
+}
+
+fn quarters(rotation: PdfPageRenderRotation) -> u16
+{
+    match rotation
+    {
+        PdfPageRenderRotation::None => 0,
+        PdfPageRenderRotation::Degrees90 => 1,
+        PdfPageRenderRotation::Degrees180 => 2,
+        PdfPageRenderRotation::Degrees270 => 3
+    }
+}
+
+fn from_quarters(quarters: u16) -> PdfPageRenderRotation
+{
+    match quarters % 4
+    {
+        1 => PdfPageRenderRotation::Degrees90,
+        2 => PdfPageRenderRotation::Degrees180,
+        3 => PdfPageRenderRotation::Degrees270,
+        _ => PdfPageRenderRotation::None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use pdfium_render::prelude::PdfPageRenderRotation;
+    use crate::PdfService;
+    use super::{from_quarters, EditedPageSource, PageEditor};
+
+    fn original(editor: &PageEditor) -> Vec<Option<u32>>
+    {
+        editor.pages().iter().map(|p| match p.source { EditedPageSource::Original { page } => Some(page), _ => None }).collect()
+    }
+
+    #[test]
+    fn test_edit_operations()
+    {
+        let mut editor = PageEditor::new(PdfService::new("scan.pdf", 600, 800), 4);
+        editor.move_page(4, 1).unwrap();
+        assert_eq!(original(&editor), vec![Some(4), Some(1), Some(2), Some(3)]);
+        editor.delete_page(3).unwrap();
+        editor.insert_blank_page(4, 595.0, 842.0).unwrap();
+        assert_eq!(original(&editor), vec![Some(4), Some(1), Some(3), None]);
+        editor.rotate(1, 2).unwrap();
+        editor.rotate(1, 3).unwrap();
+        editor.rotate(2, -1).unwrap();
+        assert_eq!(editor.pages()[0].rotation, 90);
+        assert_eq!(editor.pages()[1].rotation, 270);
+        assert!(editor.delete_page(5).is_err());
+        assert!(editor.insert_blank_page(6, 595.0, 842.0).is_err());
+        editor.insert_blank_page(5, 595.0, 842.0).unwrap();
+        assert_eq!(editor.pages_count(), 5);
+    }
+
+    #[test]
+    fn test_rotation_wraps()
+    {
+        assert_eq!(from_quarters(3 + 2), PdfPageRenderRotation::Degrees90);
+        assert_eq!(from_quarters(4), PdfPageRenderRotation::None);
+    }
+}
@@ -0,0 +1,557 @@
+use std::io::Cursor;
+use image::{error::{EncodingError, ImageFormatHint}, imageops::FilterType, DynamicImage, GrayImage, ImageError, ImageFormat, ImageResult};
+use crate::{dither::{self, ColorMode}, PageImageFormat};
+
+///Настройки кодировщиков для каждого из форматов
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EncoderSettings
+{
+    pub jpeg: JpegSettings,
+    pub png: PngSettings,
+    pub webp: WebpSettings,
+    #[cfg(feature = "avif")]
+    pub avif: AvifSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JpegSettings
+{
+    ///Качество 1..=100
+    pub quality: u8,
+    ///Прогрессивная развертка, изображение на клиенте начинает показываться до полной загрузки
+    pub progressive: bool,
+}
+impl Default for JpegSettings
+{
+    fn default() -> Self
+    {
+        Self { quality: 90, progressive: false }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PngSettings
+{
+    pub compression: PngCompression,
+    pub filter: PngFilter,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PngCompression
+{
+    #[default]
+    Fast,
+    Default,
+    Best,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PngFilter
+{
+    NoFilter,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    #[default]
+    Adaptive,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WebpSettings
+{
+    #[default]
+    Lossless,
+    ///Сжатие с потерями через libwebp, качество 0.0..=100.0
+    #[cfg(feature = "webp_lossy")]
+    Lossy { quality: f32 },
+}
+
+#[cfg(feature = "avif")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AvifSettings
+{
+    ///Качество 1..=100
+    pub quality: u8,
+    ///Скорость кодирования 1..=10, чем меньше тем лучше сжатие и дольше кодирование
+    pub speed: u8,
+}
+#[cfg(feature = "avif")]
+impl Default for AvifSettings
+{
+    fn default() -> Self
+    {
+        Self { quality: 70, speed: 6 }
+    }
+}
+
+///Кодирование изображения в выбранный формат
+///изображение должно быть предварительно приведено к поддерживаемому формату набору каналов (`bitmap::fit_to_format`)
+pub(crate) fn encode_image(image: &DynamicImage, image_format: PageImageFormat, settings: &EncoderSettings) -> ImageResult<Vec<u8>>
+{
+    let mut buffer = Cursor::new(Vec::new());
+    match image_format
+    {
+        PageImageFormat::Png =>
+        {
+            let encoder = image::codecs::png::PngEncoder::new_with_quality(&mut buffer, settings.png.compression.into(), settings.png.filter.into());
+            image.write_with_encoder(encoder)?;
+        },
+        PageImageFormat::Jpeg =>
+        {
+            if settings.jpeg.progressive
+            {
+                return encode_progressive_jpeg(image, settings.jpeg.quality);
+            }
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, settings.jpeg.quality);
+            image.write_with_encoder(encoder)?;
+        },
+        PageImageFormat::Webp => match settings.webp
+        {
+            WebpSettings::Lossless =>
+            {
+                let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buffer);
+                image.write_with_encoder(encoder)?;
+            },
+            #[cfg(feature = "webp_lossy")]
+            WebpSettings::Lossy { quality } => return encode_lossy_webp(image, quality)
+        },
+        #[cfg(feature = "avif")]
+        PageImageFormat::Avif =>
+        {
+            let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut buffer, settings.avif.speed, settings.avif.quality);
+            image.write_with_encoder(encoder)?;
+        }
+    }
+    Ok(buffer.into_inner())
+}
+
+///Кодирование страницы с учетом цветового режима
+///черно-белые страницы и страницы с ограниченным количеством уровней серого сохраняются в png палитрой
+pub(crate) fn encode_page(image: &DynamicImage, image_format: PageImageFormat, settings: &EncoderSettings, color_mode: ColorMode) -> ImageResult<Vec<u8>>
+{
+    match (image_format, color_mode.palette_levels(), image.as_luma8())
+    {
+        (PageImageFormat::Png, Some(levels), Some(gray)) => encode_gray_palette(gray, levels, &settings.png),
+        _ => encode_image(image, image_format, settings)
+    }
+}
+
+///Png с палитрой из `levels` уровней серого, глубина 1/2/4/8 бит в зависимости от количества уровней
+fn encode_gray_palette(gray: &GrayImage, levels: u8, settings: &PngSettings) -> ImageResult<Vec<u8>>
+{
+    let (depth, bits) = match levels
+    {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8)
+    };
+    let palette: Vec<u8> = (0..levels).flat_map(|i| [dither::level_value(i, levels); 3]).collect();
+    let (width, height) = (gray.width(), gray.height());
+    let row_bytes = (width as usize * bits).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * height as usize];
+    for (y, row) in gray.rows().enumerate()
+    {
+        let line = &mut data[y * row_bytes..(y + 1) * row_bytes];
+        for (x, p) in row.enumerate()
+        {
+            let index = dither::level_index(p.0[0], levels);
+            let bit = x * bits;
+            line[bit / 8] |= index << (8 - bits - bit % 8);
+        }
+    }
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(palette);
+    encoder.set_compression(match settings.compression
+    {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Best => png::Compression::Best
+    });
+    match settings.filter
+    {
+        PngFilter::Adaptive => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+        filter => encoder.set_filter(match filter
+        {
+            PngFilter::Sub => png::FilterType::Sub,
+            PngFilter::Up => png::FilterType::Up,
+            PngFilter::Avg => png::FilterType::Avg,
+            PngFilter::Paeth => png::FilterType::Paeth,
+            _ => png::FilterType::NoFilter
+        })
+    }
+    let res = encoder.write_header().and_then(|mut writer| writer.write_image_data(&data));
+    res.map_err(|e| encoding_error(ImageFormat::Png, e.to_string()))?;
+    Ok(buffer)
+}
+
+///Ограничение размера закодированной страницы
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteBudget
+{
+    ///Максимальный размер результата в байтах
+    pub max_bytes: usize,
+    ///Ниже этого качества не опускаемся, вместо этого уменьшаем размеры изображения
+    pub min_quality: u8,
+    ///Минимальный масштаб относительно исходного рендера (0.0..=1.0)
+    pub min_scale: f32,
+}
+impl ByteBudget
+{
+    pub fn new(max_bytes: usize) -> Self
+    {
+        Self { max_bytes, min_quality: 30, min_scale: 0.25 }
+    }
+}
+
+///Результат кодирования с ограничением размера
+#[derive(Clone, Debug)]
+pub struct BudgetImage
+{
+    pub bytes: Vec<u8>,
+    ///Итоговое качество, `None` для форматов без потерь (png, webp без потерь)
+    pub quality: Option<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+///Шаг уменьшения размеров, если подбором качества уложиться не удалось
+const SCALE_STEP: f32 = 0.8;
+///Нижняя граница масштаба, если в бюджете указан нулевой, отрицательный или NaN `min_scale`
+const MIN_SCALE: f32 = 0.01;
+
+///Подбор качества (бинарный поиск) и, если нужно, размеров изображения, чтобы результат уложился в `budget.max_bytes`
+///возвращает `None` если не удалось уложиться даже с минимальным качеством и масштабом
+pub(crate) fn encode_within_budget(image: &DynamicImage, image_format: PageImageFormat, settings: &EncoderSettings, color_mode: ColorMode, budget: &ByteBudget) -> ImageResult<Option<BudgetImage>>
+{
+    //f32::max отбрасывает NaN
+    let min_scale = budget.min_scale.max(MIN_SCALE);
+    let mut scale = 1.0f32;
+    loop
+    {
+        let scaled = if scale < 1.0
+        {
+            let width = ((image.width() as f32 * scale).round() as u32).max(1);
+            let height = ((image.height() as f32 * scale).round() as u32).max(1);
+            image.resize_exact(width, height, FilterType::Triangle)
+        }
+        else
+        {
+            image.clone()
+        };
+        if let Some((bytes, quality)) = encode_best_quality(&scaled, image_format, settings, color_mode, budget)?
+        {
+            return Ok(Some(BudgetImage { bytes, quality, width: scaled.width(), height: scaled.height() }));
+        }
+        scale *= SCALE_STEP;
+        if scale < min_scale
+        {
+            return Ok(None);
+        }
+    }
+}
+
+///Наибольшее качество, при котором изображение укладывается в бюджет
+fn encode_best_quality(image: &DynamicImage, image_format: PageImageFormat, settings: &EncoderSettings, color_mode: ColorMode, budget: &ByteBudget) -> ImageResult<Option<(Vec<u8>, Option<u8>)>>
+{
+    let max_quality = match quality_of(settings, image_format)
+    {
+        Some(q) => q,
+        None =>
+        {
+            let bytes = encode_page(image, image_format, settings, color_mode)?;
+            return Ok((bytes.len() <= budget.max_bytes).then_some((bytes, None)));
+        }
+    };
+    let bytes = encode_page(image, image_format, &with_quality(settings, image_format, max_quality), color_mode)?;
+    if bytes.len() <= budget.max_bytes
+    {
+        return Ok(Some((bytes, Some(max_quality))));
+    }
+    let mut best = None;
+    let (mut low, mut high) = (budget.min_quality.min(max_quality), max_quality);
+    while low < high
+    {
+        let quality = low + (high - low) / 2;
+        let bytes = encode_page(image, image_format, &with_quality(settings, image_format, quality), color_mode)?;
+        if bytes.len() <= budget.max_bytes
+        {
+            best = Some((bytes, Some(quality)));
+            low = quality + 1;
+        }
+        else
+        {
+            high = quality;
+        }
+    }
+    Ok(best)
+}
+
+///Качество, с которого начинается подбор, `None` если у формата нет параметра качества
+fn quality_of(settings: &EncoderSettings, image_format: PageImageFormat) -> Option<u8>
+{
+    match image_format
+    {
+        PageImageFormat::Jpeg => Some(settings.jpeg.quality),
+        PageImageFormat::Png => None,
+        #[cfg(feature = "webp_lossy")]
+        PageImageFormat::Webp => match settings.webp
+        {
+            WebpSettings::Lossy { quality } => Some(quality.clamp(0.0, 100.0) as u8),
+            //без потерь в бюджет обычно не уложиться, поэтому переключаемся на сжатие с потерями
+            WebpSettings::Lossless => Some(90)
+        },
+        #[cfg(not(feature = "webp_lossy"))]
+        PageImageFormat::Webp => None,
+        #[cfg(feature = "avif")]
+        PageImageFormat::Avif => Some(settings.avif.quality)
+    }
+}
+
+fn with_quality(settings: &EncoderSettings, image_format: PageImageFormat, quality: u8) -> EncoderSettings
+{
+    let mut settings = *settings;
+    match image_format
+    {
+        PageImageFormat::Jpeg => settings.jpeg.quality = quality,
+        #[cfg(feature = "webp_lossy")]
+        PageImageFormat::Webp => settings.webp = WebpSettings::Lossy { quality: quality as f32 },
+        #[cfg(feature = "avif")]
+        PageImageFormat::Avif => settings.avif.quality = quality,
+        _ => ()
+    }
+    settings
+}
+
+///Кодировщик из image не умеет прогрессивный jpeg, поэтому используем jpeg-encoder
+fn encode_progressive_jpeg(image: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>>
+{
+    let mut buffer = Vec::new();
+    let (width, height) = (image.width(), image.height());
+    if width > u16::MAX as u32 || height > u16::MAX as u32
+    {
+        return Err(encoding_error(ImageFormat::Jpeg, format!("размер изображения {}x{} превышает максимальный для jpeg", width, height)));
+    }
+    let mut encoder = jpeg_encoder::Encoder::new(&mut buffer, quality);
+    encoder.set_progressive(true);
+    let res = match image
+    {
+        DynamicImage::ImageLuma8(gray) => encoder.encode(gray.as_raw(), width as u16, height as u16, jpeg_encoder::ColorType::Luma),
+        _ => encoder.encode(image.to_rgb8().as_raw(), width as u16, height as u16, jpeg_encoder::ColorType::Rgb)
+    };
+    res.map_err(|e| encoding_error(ImageFormat::Jpeg, e.to_string()))?;
+    Ok(buffer)
+}
+
+#[cfg(feature = "webp_lossy")]
+fn encode_lossy_webp(image: &DynamicImage, quality: f32) -> ImageResult<Vec<u8>>
+{
+    let (width, height) = (image.width(), image.height());
+    let res = match image
+    {
+        DynamicImage::ImageRgba8(rgba) => webp::Encoder::from_rgba(rgba.as_raw(), width, height).encode_simple(false, quality),
+        DynamicImage::ImageLumaA8(_) => webp::Encoder::from_rgba(image.to_rgba8().as_raw(), width, height).encode_simple(false, quality),
+        DynamicImage::ImageRgb8(rgb) => webp::Encoder::from_rgb(rgb.as_raw(), width, height).encode_simple(false, quality),
+        _ => webp::Encoder::from_rgb(image.to_rgb8().as_raw(), width, height).encode_simple(false, quality)
+    };
+    res.map(|memory| memory.to_vec())
+        .map_err(|e| encoding_error(ImageFormat::WebP, format!("{:?}", e)))
+}
+
+fn encoding_error(format: ImageFormat, message: String) -> ImageError
+{
+    ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), message))
+}
+
+impl From<PngCompression> for image::codecs::png::CompressionType
+{
+    fn from(value: PngCompression) -> Self
+    {
+        match value
+        {
+            PngCompression::Fast => Self::Fast,
+            PngCompression::Default => Self::Default,
+            PngCompression::Best => Self::Best
+        }
+    }
+}
+
+impl From<PngFilter> for image::codecs::png::FilterType
+{
+    fn from(value: PngFilter) -> Self
+    {
+        match value
+        {
+            PngFilter::NoFilter => Self::NoFilter,
+            PngFilter::Sub => Self::Sub,
+            PngFilter::Up => Self::Up,
+            PngFilter::Avg => Self::Avg,
+            PngFilter::Paeth => Self::Paeth,
+            PngFilter::Adaptive => Self::Adaptive
+        }
+    }
+}
+
+impl PageImageFormat
+{
+    ///Название формата для сообщений об ошибках
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            PageImageFormat::Jpeg => "jpeg",
+            PageImageFormat::Png => "png",
+            PageImageFormat::Webp => "webp",
+            #[cfg(feature = "avif")]
+            PageImageFormat::Avif => "avif"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{DynamicImage, RgbImage};
+    use crate::{dither::{apply_color_mode, Binarization, ColorMode}, bitmap::DEFAULT_BACKGROUND, PageImageFormat};
+    use super::{encode_image, encode_page, encode_within_budget, ByteBudget, EncoderSettings, JpegSettings, PngCompression, PngSettings};
+
+    ///Градиент, на котором видна разница между уровнями сжатия
+    fn sample_image() -> DynamicImage
+    {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8])))
+    }
+
+    #[test]
+    fn test_jpeg_quality_changes_size()
+    {
+        let low = EncoderSettings { jpeg: JpegSettings { quality: 20, progressive: false }, ..Default::default() };
+        let high = EncoderSettings { jpeg: JpegSettings { quality: 95, progressive: false }, ..Default::default() };
+        let low = encode_image(&sample_image(), PageImageFormat::Jpeg, &low).unwrap();
+        let high = encode_image(&sample_image(), PageImageFormat::Jpeg, &high).unwrap();
+        assert!(low.len() < high.len());
+    }
+
+    #[test]
+    fn test_progressive_jpeg()
+    {
+        let settings = EncoderSettings { jpeg: JpegSettings { quality: 80, progressive: true }, ..Default::default() };
+        for image in [sample_image(), DynamicImage::ImageLuma8(sample_image().to_luma8())]
+        {
+            let encoded = encode_image(&image, PageImageFormat::Jpeg, &settings).unwrap();
+            //маркер SOF2 - прогрессивный jpeg
+            assert!(encoded.windows(2).any(|w| w == [0xFF, 0xC2]));
+            let decoded = image::load_from_memory(&encoded).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (64, 64));
+        }
+    }
+
+    #[test]
+    fn test_png_compression()
+    {
+        let best = EncoderSettings { png: PngSettings { compression: PngCompression::Best, ..Default::default() }, ..Default::default() };
+        let encoded = encode_image(&sample_image(), PageImageFormat::Png, &best).unwrap();
+        let decoded = image::load_from_memory(&encoded).unwrap();
+        assert_eq!(decoded.to_rgb8(), sample_image().to_rgb8());
+    }
+
+    #[test]
+    fn test_budget_lowers_quality()
+    {
+        let settings = EncoderSettings::default();
+        let full = encode_image(&sample_image(), PageImageFormat::Jpeg, &settings).unwrap();
+        let budget = ByteBudget::new(full.len() * 2 / 3);
+        let result = encode_within_budget(&sample_image(), PageImageFormat::Jpeg, &settings, ColorMode::Color, &budget).unwrap().unwrap();
+        assert!(result.bytes.len() <= budget.max_bytes);
+        let quality = result.quality.unwrap();
+        assert!(quality < 90 && quality >= budget.min_quality);
+        assert_eq!((result.width, result.height), (64, 64));
+    }
+
+    #[test]
+    fn test_budget_fits_without_changes()
+    {
+        let result = encode_within_budget(&sample_image(), PageImageFormat::Jpeg, &EncoderSettings::default(), ColorMode::Color, &ByteBudget::new(1_000_000)).unwrap().unwrap();
+        assert_eq!(result.quality, Some(90));
+        assert_eq!((result.width, result.height), (64, 64));
+    }
+
+    #[test]
+    fn test_budget_scales_down_lossless()
+    {
+        let full = encode_image(&sample_image(), PageImageFormat::Png, &EncoderSettings::default()).unwrap();
+        let budget = ByteBudget::new(full.len() / 2);
+        let result = encode_within_budget(&sample_image(), PageImageFormat::Png, &EncoderSettings::default(), ColorMode::Color, &budget).unwrap().unwrap();
+        assert!(result.bytes.len() <= budget.max_bytes);
+        assert_eq!(result.quality, None);
+        assert!(result.width < 64 && result.height < 64);
+    }
+
+    #[test]
+    fn test_budget_impossible()
+    {
+        let result = encode_within_budget(&sample_image(), PageImageFormat::Jpeg, &EncoderSettings::default(), ColorMode::Color, &ByteBudget::new(10)).unwrap();
+        assert!(result.is_none());
+        //нулевой и NaN масштаб не должны зацикливать подбор
+        for min_scale in [0.0, -1.0, f32::NAN]
+        {
+            let budget = ByteBudget { min_scale, ..ByteBudget::new(10) };
+            let result = encode_within_budget(&sample_image(), PageImageFormat::Jpeg, &EncoderSettings::default(), ColorMode::Color, &budget).unwrap();
+            assert!(result.is_none());
+        }
+    }
+
+    #[test]
+    fn test_palette_png()
+    {
+        for (mode, levels) in [(ColorMode::Bilevel(Binarization::FloydSteinberg), 2usize), (ColorMode::GrayLevels { levels: 3, dither: false }, 3), (ColorMode::GrayLevels { levels: 16, dither: true }, 16), (ColorMode::GrayLevels { levels: 32, dither: false }, 32)]
+        {
+            let gray = apply_color_mode(sample_image(), mode, DEFAULT_BACKGROUND);
+            let encoded = encode_page(&gray, PageImageFormat::Png, &EncoderSettings::default(), mode).unwrap();
+            let decoder = png::Decoder::new(std::io::Cursor::new(&encoded));
+            let reader = decoder.read_info().unwrap();
+            assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+            assert_eq!(reader.info().palette.as_ref().unwrap().len(), levels * 3);
+            //после декодирования значения совпадают с исходными уровнями
+            let decoded = image::load_from_memory(&encoded).unwrap().to_luma8();
+            assert_eq!(decoded, gray.to_luma8());
+        }
+        let bilevel = ColorMode::Bilevel(Binarization::Threshold(128));
+        let gray = apply_color_mode(sample_image(), bilevel, DEFAULT_BACKGROUND);
+        let palette = encode_page(&gray, PageImageFormat::Png, &EncoderSettings::default(), bilevel).unwrap();
+        let plain = encode_image(&gray, PageImageFormat::Png, &EncoderSettings::default()).unwrap();
+        assert!(palette.len() < plain.len());
+    }
+
+    #[test]
+    fn test_gray_webp()
+    {
+        let gray = apply_color_mode(sample_image(), ColorMode::Bilevel(Binarization::Ordered), DEFAULT_BACKGROUND);
+        let encoded = encode_page(&gray, PageImageFormat::Webp, &EncoderSettings::default(), ColorMode::Bilevel(Binarization::Ordered)).unwrap();
+        assert_eq!(image::load_from_memory(&encoded).unwrap().to_luma8(), gray.to_luma8());
+    }
+
+    #[cfg(feature = "webp_lossy")]
+    #[test]
+    fn test_lossy_webp_smaller_than_lossless()
+    {
+        let lossy = EncoderSettings { webp: super::WebpSettings::Lossy { quality: 50.0 }, ..Default::default() };
+        //на шуме webp без потерь сжимает плохо, как на отсканированных страницах
+        let noise = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x.wrapping_mul(y).wrapping_mul(2654435761) >> 13) as u8, (x ^ y) as u8 * 3, (y * 4) as u8])));
+        let lossy = encode_image(&noise, PageImageFormat::Webp, &lossy).unwrap();
+        let lossless = encode_image(&noise, PageImageFormat::Webp, &EncoderSettings::default()).unwrap();
+        assert!(lossy.len() < lossless.len());
+        assert!(image::load_from_memory(&lossy).is_ok());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn test_avif()
+    {
+        let encoded = encode_image(&sample_image(), PageImageFormat::Avif, &EncoderSettings::default()).unwrap();
+        assert_eq!(&encoded[4..8], b"ftyp");
+    }
+}
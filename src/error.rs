@@ -8,6 +8,8 @@ pub enum Error
     WrongPageSelect(String, u32, u32),
     #[error("Для рендера pdf {0} не выбрано ни одной страницы")]
     NonePageSelect(String),
+    #[error("Все страницы pdf {0} пустые, после их удаления не остается ни одной страницы")]
+    AllPagesBlank(String),
     #[error("Ошибка преобразования изображения в формат rgba8, файл: {0} страница {1}")]
     Rgba8ConvertError(String, u32),
     #[error("Ошибка записи в буфер, файл: {0} страница {1}")]
@@ -18,6 +20,14 @@ pub enum Error
     PdfiumError(#[from] pdfium_render::prelude::PdfiumError),
     #[error(transparent)]
     ImageError(#[from] image::ImageError),
+    #[error(transparent)]
+    TiffError(#[from] tiff::TiffError),
+    #[error(transparent)]
+    LopdfError(#[from] lopdf::Error),
+    #[error(transparent)]
+    RegexError(#[from] regex::Error),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
     #[error("Ошибка создание изображения из файла: {0} страницы {1}")]
     ExtractDynamicImageError(String, u32),
     //Ошибка если дата и размер копируемого файла не может синхронизироваться больше 2 минут
@@ -25,6 +35,36 @@ pub enum Error
     FileTimeCopyError(String),
     #[error("Ошибка конвертирование страницы {0} файла {1} в формат {2}")]
     ImageConvertingError(u32, String, String),
+    #[error("Не удалось уложить страницу {0} файла {1} в {2} байт даже при минимальном качестве и размере")]
+    ByteBudgetExceeded(u32, String, usize),
+    #[error("Поле формы `{0}` не найдено в файле {1}")]
+    FormFieldNotFound(String, String),
+    #[error("Поле формы `{0}` файла {1} нельзя заполнить, у кнопок и подписей нет значения")]
+    FormFieldNotFillable(String, String),
+    #[error("Значение `{0}` нельзя выбрать в поле формы `{1}` файла {2}")]
+    FormValueNotAllowed(String, String, String),
+    #[error("Ошибка, на странице {1} pdf {0} всего {2} изображений, а выбрано изображение {3}")]
+    WrongImageSelect(String, u32, usize, usize),
+    #[error("Ошибка, в pdf {0} всего {1} вложений, а выбрано вложение {2}")]
+    WrongAttachmentSelect(String, usize, usize),
+    #[error("Вложение `{0}` в pdf {1} - ссылка на внешний файл, содержимого в pdf нет")]
+    AttachmentNotEmbedded(String, String),
+    #[error("Для объединения не передано ни одного pdf")]
+    NoneMergeSource,
+    #[error("Для создания pdf не передано ни одного изображения")]
+    NoneImageSource,
+    #[error("Изображение {0} в формате {1} не поддерживается, ожидается jpeg, png или tiff")]
+    UnsupportedImageInput(String, String),
+    #[error("Не удалось загрузить шрифт водяного знака: {0}")]
+    WatermarkFontError(String),
+    #[error("Неверный формат подписи документа `{0}`, ожидается `dhash:phash` в hex через `;`")]
+    SignatureParseError(String),
+    #[error("Не удалось запустить {0}: {1}")]
+    OcrStartError(String, String),
+    #[error("Ошибка распознавания, код {0} ({1}): {2}")]
+    OcrFailed(i32, String, String),
+    #[error("Распознавание через {0} не завершилось за {1} с")]
+    OcrTimeout(String, u64),
 }
 impl serde::Serialize for Error 
 {
@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use lopdf::{Document, Object, ObjectId};
+use pdfium_render::prelude::{PdfDocument, PdfFormField, PdfFormFieldCommon, PdfFormFieldOptions, PdfFormFieldType, PdfPageAnnotationCommon, PdfRenderConfig};
+use serde::Serialize;
+use crate::{error::Error, geometry::PageRect};
+
+///Поле формы AcroForm
+///у группы переключателей (radio) каждая кнопка - отдельный виджет, поэтому они идут отдельными записями с одним именем
+#[derive(Debug, Clone, Serialize)]
+pub struct FormField
+{
+    ///Полное имя поля, по нему поле заполняется в `fill_form`
+    pub name: String,
+    pub kind: FormFieldKind,
+    pub value: Option<String>,
+    ///Отмечен ли флажок или переключатель, для остальных типов `None`
+    pub checked: Option<bool>,
+    ///Варианты выбора для списков
+    pub options: Vec<String>,
+    pub read_only: bool,
+    pub required: bool,
+    ///Номер страницы (с 1)
+    pub page: u32,
+    ///Область поля в пунктах pdf
+    pub rect: PageRect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormFieldKind
+{
+    Text,
+    Checkbox,
+    RadioButton,
+    ComboBox,
+    ListBox,
+    PushButton,
+    Signature,
+    Unknown,
+}
+impl FormFieldKind
+{
+    ///Можно ли заполнить поле через `fill_form`: у кнопок и подписей значения нет
+    pub fn is_fillable(&self) -> bool
+    {
+        matches!(self, FormFieldKind::Text | FormFieldKind::Checkbox | FormFieldKind::RadioButton | FormFieldKind::ComboBox | FormFieldKind::ListBox)
+    }
+}
+impl From<PdfFormFieldType> for FormFieldKind
+{
+    fn from(value: PdfFormFieldType) -> Self
+    {
+        match value
+        {
+            PdfFormFieldType::Text => FormFieldKind::Text,
+            PdfFormFieldType::Checkbox => FormFieldKind::Checkbox,
+            PdfFormFieldType::RadioButton => FormFieldKind::RadioButton,
+            PdfFormFieldType::ComboBox => FormFieldKind::ComboBox,
+            PdfFormFieldType::ListBox => FormFieldKind::ListBox,
+            PdfFormFieldType::PushButton => FormFieldKind::PushButton,
+            PdfFormFieldType::Signature => FormFieldKind::Signature,
+            _ => FormFieldKind::Unknown
+        }
+    }
+}
+
+pub(crate) fn form_fields(document: &PdfDocument) -> Vec<FormField>
+{
+    let mut fields = Vec::new();
+    for (index, page) in document.pages().iter().enumerate()
+    {
+        for annotation in page.annotations().iter()
+        {
+            let Some(field) = annotation.as_form_field() else { continue };
+            let Some(name) = field.name() else { continue };
+            let Ok(bounds) = annotation.bounds() else { continue };
+            let (value, checked, options) = match field
+            {
+                PdfFormField::Text(text) => (text.value(), None, Vec::new()),
+                PdfFormField::Checkbox(checkbox) => (checkbox.group_value(), checkbox.is_checked().ok(), Vec::new()),
+                PdfFormField::RadioButton(radio) => (radio.group_value(), radio.is_checked().ok(), Vec::new()),
+                PdfFormField::ComboBox(combo) => (combo.value(), None, option_labels(combo.options())),
+                PdfFormField::ListBox(list) => (list.value(), None, option_labels(list.options())),
+                _ => (None, None, Vec::new())
+            };
+            fields.push(FormField
+            {
+                name,
+                kind: field.field_type().into(),
+                value,
+                checked,
+                options,
+                read_only: field.is_read_only(),
+                required: field.is_required(),
+                page: index as u32 + 1,
+                rect: PageRect::from_pdf(&bounds),
+            });
+        }
+    }
+    fields
+}
+
+///Заполнение текстовых полей значениями из `values` (имя поля -> значение) и проверка, что остальные поля есть и их можно заполнить,
+///возвращает имена заполненных полей
+///pdfium пишет значение в виджет и не обновляет внешний вид поля, а переключатели выбирает только по уже выбранному /AS,
+///поэтому флажки, переключатели и списки заполняются в `update_fields`
+pub(crate) fn fill_form(document: &PdfDocument, values: &HashMap<String, String>, path: &str) -> Result<Vec<String>, Error>
+{
+    let mut filled: Vec<String> = Vec::with_capacity(values.len());
+    for page in document.pages().iter()
+    {
+        for mut annotation in page.annotations().iter()
+        {
+            let Some(field) = annotation.as_form_field_mut() else { continue };
+            let Some(name) = field.name() else { continue };
+            let Some((name, value)) = values.get_key_value(&name) else { continue };
+            match field
+            {
+                PdfFormField::Text(text) => text.set_value(value)?,
+                PdfFormField::Checkbox(_) | PdfFormField::RadioButton(_) | PdfFormField::ComboBox(_) | PdfFormField::ListBox(_) => {},
+                _ => return Err(Error::FormFieldNotFillable(name.clone(), path.to_owned()))
+            }
+            if !filled.contains(name)
+            {
+                filled.push(name.clone());
+            }
+        }
+    }
+    if let Some(missing) = values.keys().find(|k| !filled.contains(k))
+    {
+        return Err(Error::FormFieldNotFound(missing.clone(), path.to_owned()));
+    }
+    Ok(filled)
+}
+
+///Терминальное поле AcroForm (без дочерних полей) и его виджеты
+struct TerminalField
+{
+    id: ObjectId,
+    name: String,
+    ///Тип поля /FT с учетом наследования: `Tx`, `Btn`, `Ch` или `Sig`
+    field_type: Vec<u8>,
+    ///Флаги поля /Ff с учетом наследования
+    flags: i64,
+    ///Дочерние виджеты без /T, у поля, совмещенного с виджетом, пусто
+    widgets: Vec<ObjectId>,
+}
+impl TerminalField
+{
+    ///Виджеты поля, у поля, совмещенного с виджетом, это само поле
+    fn widget_ids(&self) -> Vec<ObjectId>
+    {
+        if self.widgets.is_empty() { vec![self.id] } else { self.widgets.clone() }
+    }
+}
+
+///Флаг /Ff переключателя (radio)
+const RADIO_FLAG: i64 = 1 << 15;
+///Флаг /Ff выпадающего списка с возможностью ввести свое значение
+const EDIT_FLAG: i64 = 1 << 18;
+
+///Доводка заполненных полей `names` в сохраненном pdf:
+///значение текстового поля из виджета переносится в само поле (у поля с дочерними виджетами pdfium пишет /V в виджет),
+///флажки и переключатели выбираются по состояниям внешнего вида виджетов (/AS), в списках значение ищется в /Opt,
+///у текстовых полей и списков удаляется старый внешний вид и в /AcroForm ставится /NeedAppearances,
+///чтобы просмотрщик (и pdfium перед `flatten`) построил внешний вид по новому значению
+pub(crate) fn update_fields(pdf: &[u8], values: &HashMap<String, String>, names: &[String], path: &str) -> Result<Vec<u8>, Error>
+{
+    let mut document = Document::load_mem(pdf)?;
+    let form_id = document.catalog()?.get(b"AcroForm").and_then(Object::as_reference).ok();
+    let roots = match form_id
+    {
+        Some(id) => document.get_dictionary(id)?.get(b"Fields"),
+        None => document.catalog()?.get(b"AcroForm").and_then(Object::as_dict).and_then(|f| f.get(b"Fields"))
+    }.and_then(Object::as_array).cloned().unwrap_or_default();
+    let mut fields = Vec::new();
+    for root in &roots
+    {
+        terminal_fields(&document, root, "", None, 0, &mut fields);
+    }
+    for field in fields.iter().filter(|f| names.contains(&f.name))
+    {
+        let mut value = None;
+        for widget in &field.widgets
+        {
+            value = document.get_dictionary_mut(*widget)?.remove(b"V").or(value);
+        }
+        let value = match (field.field_type.as_slice(), values.get(&field.name))
+        {
+            (b"Btn", Some(value)) => Some(select_state(&mut document, field, value, path)?),
+            (b"Ch", Some(value)) => Some(select_option(&mut document, field, value, path)?),
+            _ => value
+        };
+        if field.field_type == b"Tx" || field.field_type == b"Ch"
+        {
+            for widget in field.widget_ids()
+            {
+                document.get_dictionary_mut(widget)?.remove(b"AP");
+            }
+        }
+        if let Some(value) = value
+        {
+            document.get_dictionary_mut(field.id)?.set("V", value);
+        }
+    }
+    let form = match form_id
+    {
+        Some(id) => document.get_dictionary_mut(id)?,
+        None => document.catalog_mut()?.get_mut(b"AcroForm")?.as_dict_mut()?
+    };
+    form.set("NeedAppearances", true);
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+///Выбор флажка или переключателя: у каждого виджета включенное состояние - ключ /AP /N кроме /Off,
+///флажок отмечается значениями из `is_checked_value`, переключатель - именем состояния, возвращает значение /V
+fn select_state(document: &mut Document, field: &TerminalField, value: &str, path: &str) -> Result<Object, Error>
+{
+    let radio = field.flags & RADIO_FLAG != 0;
+    let value = value.trim().trim_start_matches('/');
+    let checked = is_checked_value(value);
+    //у флажка без внешнего вида включенное состояние стандартное
+    let states: Vec<(ObjectId, Vec<u8>)> = field.widget_ids().into_iter().map(|w| (w, on_state(document, w).unwrap_or_else(|| b"Yes".to_vec()))).collect();
+    let selected = |state: &[u8]| if radio { state == value.as_bytes() } else { checked };
+    if radio && !states.iter().any(|(_, state)| selected(state))
+    {
+        return Err(Error::FormValueNotAllowed(value.to_owned(), field.name.clone(), path.to_owned()));
+    }
+    let mut result = Object::Name(b"Off".to_vec());
+    for (widget, state) in states
+    {
+        let appearance = if selected(&state)
+        {
+            result = Object::Name(state.clone());
+            state
+        }
+        else
+        {
+            b"Off".to_vec()
+        };
+        document.get_dictionary_mut(widget)?.set("AS", Object::Name(appearance));
+    }
+    Ok(result)
+}
+
+///Включенное состояние виджета флажка или переключателя
+fn on_state(document: &Document, widget: ObjectId) -> Option<Vec<u8>>
+{
+    let appearance = document.get_dictionary(widget).ok()?.get(b"AP").ok()?;
+    let (_, appearance) = document.dereference(appearance).ok()?;
+    let (_, normal) = document.dereference(appearance.as_dict().ok()?.get(b"N").ok()?).ok()?;
+    normal.as_dict().ok()?.iter().map(|(state, _)| state).find(|state| state.as_slice() != b"Off").cloned()
+}
+
+///Выбор варианта списка: значение сравнивается с экспортным значением и подписью вариантов /Opt,
+///в выпадающий список с правом ввода можно записать и свое значение, возвращает значение /V
+fn select_option(document: &mut Document, field: &TerminalField, value: &str, path: &str) -> Result<Object, Error>
+{
+    let options = document.get_dictionary(field.id)?.get(b"Opt").and_then(Object::as_array).cloned().unwrap_or_default();
+    let text = |object: &Object| document.dereference(object).ok().and_then(|(_, o)| lopdf::decode_text_string(o).ok());
+    let position = options.iter().position(|option| match document.dereference(option).map(|(_, o)| o)
+    {
+        Ok(Object::Array(pair)) => pair.iter().any(|o| text(o).as_deref() == Some(value)),
+        Ok(option) => text(option).as_deref() == Some(value),
+        Err(_) => false
+    });
+    let export = match position.map(|index| document.dereference(&options[index]).map(|(_, o)| o.clone()))
+    {
+        Some(Ok(Object::Array(pair))) => pair.first().cloned().unwrap_or_else(|| lopdf::text_string(value)),
+        Some(Ok(option)) => option,
+        _ if field.flags & EDIT_FLAG != 0 => lopdf::text_string(value),
+        _ => return Err(Error::FormValueNotAllowed(value.to_owned(), field.name.clone(), path.to_owned()))
+    };
+    let dictionary = document.get_dictionary_mut(field.id)?;
+    match position
+    {
+        Some(index) => dictionary.set("I", vec![Object::Integer(index as i64)]),
+        None => { dictionary.remove(b"I"); }
+    }
+    Ok(export)
+}
+
+fn terminal_fields(document: &Document, object: &Object, parent: &str, inherited_type: Option<&[u8]>, inherited_flags: i64, fields: &mut Vec<TerminalField>)
+{
+    let Ok(id) = object.as_reference() else { return };
+    let Ok(dictionary) = document.get_dictionary(id) else { return };
+    let Some(partial) = dictionary.get(b"T").ok().and_then(|t| lopdf::decode_text_string(t).ok()) else { return };
+    let name = if parent.is_empty() { partial } else { format!("{}.{}", parent, partial) };
+    let field_type = dictionary.get(b"FT").and_then(Object::as_name).ok().or(inherited_type);
+    let flags = dictionary.get(b"Ff").and_then(Object::as_i64).unwrap_or(inherited_flags);
+    let kids = dictionary.get(b"Kids").and_then(Object::as_array).map(|k| k.as_slice()).unwrap_or_default();
+    let is_field = |kid: &Object| kid.as_reference().and_then(|k| document.get_dictionary(k)).is_ok_and(|k| k.has(b"T"));
+    if kids.iter().any(is_field)
+    {
+        for kid in kids.iter().filter(|k| is_field(k))
+        {
+            terminal_fields(document, kid, &name, field_type, flags, fields);
+        }
+        return;
+    }
+    let widgets = kids.iter().filter_map(|k| k.as_reference().ok()).collect();
+    fields.push(TerminalField { id, name, field_type: field_type.unwrap_or_default().to_vec(), flags, widgets });
+}
+
+///Ключ словаря страницы, в котором на время `flatten` лежат аннотации, не являющиеся виджетами полей
+const STASHED_ANNOTS: &str = "PdfViewerStashedAnnots";
+
+///pdfium переносит в содержимое все аннотации страницы, поэтому перед `flatten` комментарии, выделения, ссылки
+///и остальные аннотации кроме виджетов полей убираются из /Annots, а `restore_annotations` возвращает их обратно
+pub(crate) fn stash_annotations(pdf: &[u8]) -> Result<Vec<u8>, lopdf::Error>
+{
+    let mut document = Document::load_mem(pdf)?;
+    for page_id in document.get_pages().into_values()
+    {
+        let Ok(annots) = document.get_dictionary(page_id)?.get(b"Annots") else { continue };
+        let Ok((_, Object::Array(annots))) = document.dereference(annots) else { continue };
+        let (widgets, stashed): (Vec<Object>, Vec<Object>) = annots.iter().cloned().partition(|a| is_widget(&document, a));
+        if stashed.is_empty()
+        {
+            continue;
+        }
+        let page = document.get_dictionary_mut(page_id)?;
+        page.set("Annots", widgets);
+        page.set(STASHED_ANNOTS, stashed);
+    }
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+///Возврат аннотаций, убранных `stash_annotations`
+pub(crate) fn restore_annotations(pdf: &[u8]) -> Result<Vec<u8>, lopdf::Error>
+{
+    let mut document = Document::load_mem(pdf)?;
+    for page_id in document.get_pages().into_values()
+    {
+        let Some(Object::Array(stashed)) = document.get_dictionary_mut(page_id)?.remove(STASHED_ANNOTS.as_bytes()) else { continue };
+        let mut annots = document.get_dictionary(page_id)?.get(b"Annots")
+            .and_then(|a| document.dereference(a))
+            .and_then(|(_, a)| a.as_array())
+            .cloned()
+            .unwrap_or_default();
+        annots.extend(stashed);
+        document.get_dictionary_mut(page_id)?.set("Annots", annots);
+    }
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn is_widget(document: &Document, annotation: &Object) -> bool
+{
+    document.dereference(annotation).and_then(|(_, a)| a.as_dict()).and_then(|a| a.get(b"Subtype")).and_then(Object::as_name).is_ok_and(|s| s == b"Widget")
+}
+
+///Перенос аннотаций страниц в содержимое, после чего поля перестают быть редактируемыми,
+///чтобы перенести только поля, остальные аннотации убираются на это время через `stash_annotations`
+///недостающий внешний вид полей при /NeedAppearances pdfium строит только при отрисовке аннотаций,
+///поэтому перед переносом страница отрисовывается в маленьком размере
+pub(crate) fn flatten(document: &PdfDocument) -> Result<(), Error>
+{
+    let config = PdfRenderConfig::new().set_target_width(64).render_form_data(false);
+    for mut page in document.pages().iter()
+    {
+        if page.annotations().is_empty()
+        {
+            continue;
+        }
+        page.render_with_config(&config)?;
+        page.flatten()?;
+    }
+    Ok(())
+}
+
+fn option_labels(options: &PdfFormFieldOptions) -> Vec<String>
+{
+    options.iter().filter_map(|o| o.label().cloned()).collect()
+}
+
+fn is_checked_value(value: &str) -> bool
+{
+    let value = value.trim().trim_start_matches('/').to_lowercase();
+    matches!(value.as_str(), "true" | "yes" | "on" | "1" | "да")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::HashMap;
+    use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+    use crate::PdfService;
+    use crate::error::Error;
+    use super::{fill_form, flatten, is_checked_value, restore_annotations, stash_annotations, update_fields, FormFieldKind};
+
+    #[test]
+    fn test_checkbox_values()
+    {
+        for value in ["true", "Yes", "/Yes", "on", "1", "Да"]
+        {
+            assert!(is_checked_value(value), "{}", value);
+        }
+        for value in ["false", "Off", "/Off", "0", "", "нет"]
+        {
+            assert!(!is_checked_value(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_fillable_kinds()
+    {
+        assert!(FormFieldKind::Text.is_fillable());
+        assert!(FormFieldKind::Checkbox.is_fillable());
+        assert!(FormFieldKind::RadioButton.is_fillable());
+        assert!(FormFieldKind::ComboBox.is_fillable());
+        assert!(FormFieldKind::ListBox.is_fillable());
+        assert!(!FormFieldKind::Signature.is_fillable());
+        assert!(!FormFieldKind::PushButton.is_fillable());
+    }
+
+    ///Текстовое поле `applicant.name` с дочерним виджетом и старым внешним видом, поле `comment`, совмещенное с виджетом, и заметка
+    ///возвращает документ, поле `applicant.name` и его виджет
+    fn sample() -> (Document, ObjectId, ObjectId)
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.new_object_id();
+        let font = document.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica", "Encoding" => "WinAnsiEncoding" });
+        let appearance = |document: &mut Document, text: &str| -> ObjectId
+        {
+            let content = format!("/Tx BMC BT /Helv 12 Tf 2 5 Td ({}) Tj ET EMC", text).into_bytes();
+            document.add_object(Stream::new(dictionary! { "Type" => "XObject", "Subtype" => "Form", "BBox" => vec![0.into(), 0.into(), 250.into(), 20.into()], "Resources" => dictionary! { "Font" => dictionary! { "Helv" => font } } }, content))
+        };
+        let old = appearance(&mut document, "Old value");
+        let comment_appearance = appearance(&mut document, "Keep me");
+        let applicant = document.new_object_id();
+        let name = document.new_object_id();
+        let widget = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Widget", "Rect" => vec![50.into(), 700.into(), 300.into(), 720.into()],
+            "Parent" => name, "P" => page_id, "AP" => dictionary! { "N" => old },
+        });
+        document.objects.insert(name, Object::Dictionary(dictionary! { "T" => Object::string_literal("name"), "FT" => "Tx", "Parent" => applicant, "Kids" => vec![widget.into()], "V" => Object::string_literal("Old value") }));
+        document.objects.insert(applicant, Object::Dictionary(dictionary! { "T" => Object::string_literal("applicant"), "Kids" => vec![name.into()] }));
+        let comment = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Widget", "Rect" => vec![50.into(), 600.into(), 300.into(), 620.into()],
+            "T" => Object::string_literal("comment"), "FT" => "Tx", "V" => Object::string_literal("Keep me"), "P" => page_id, "AP" => dictionary! { "N" => comment_appearance },
+        });
+        let note = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "Text", "Rect" => vec![10.into(), 10.into(), 30.into(), 30.into()], "Contents" => Object::string_literal("note") });
+        document.objects.insert(page_id, Object::Dictionary(dictionary!
+        {
+            "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Annots" => vec![widget.into(), comment.into(), note.into()],
+        }));
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let form = document.add_object(dictionary! { "Fields" => vec![applicant.into(), comment.into()], "DA" => Object::string_literal("/Helv 12 Tf 0 g"), "DR" => dictionary! { "Font" => dictionary! { "Helv" => font } } });
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "AcroForm" => form });
+        document.trailer.set("Root", catalog);
+        (document, name, widget)
+    }
+
+    fn save(mut document: Document) -> Vec<u8>
+    {
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_update_fields()
+    {
+        //так документ выглядит после set_value в pdfium: значение в виджете, внешний вид старый
+        let (mut document, name, widget) = sample();
+        document.get_dictionary_mut(widget).unwrap().set("V", Object::string_literal("Approved"));
+        let values = HashMap::from([("applicant.name".to_owned(), "Approved".to_owned())]);
+        let updated = Document::load_mem(&update_fields(&save(document), &values, &["applicant.name".to_owned()], "form.pdf").unwrap()).unwrap();
+        let field = updated.get_dictionary(name).unwrap();
+        assert_eq!(field.get(b"V").unwrap().as_str().unwrap(), b"Approved");
+        let widget = updated.get_dictionary(widget).unwrap();
+        assert!(!widget.has(b"V") && !widget.has(b"AP"));
+        let form = updated.get_dictionary(updated.catalog().unwrap().get(b"AcroForm").unwrap().as_reference().unwrap()).unwrap();
+        assert!(form.get(b"NeedAppearances").unwrap().as_bool().unwrap());
+        //у незаполненного поля внешний вид остается
+        let comment = form.get(b"Fields").unwrap().as_array().unwrap()[1].as_reference().unwrap();
+        assert!(updated.get_dictionary(comment).unwrap().has(b"AP"));
+    }
+
+    #[test]
+    fn test_fill_and_flatten()
+    {
+        let pdfium = PdfService::get_instance().unwrap();
+        let original = save(sample().0);
+        let document = pdfium.load_pdf_from_byte_slice(&original, None).unwrap();
+        let values = HashMap::from([("applicant.name".to_owned(), "Approved".to_owned())]);
+        let filled = fill_form(&document, &values, "form.pdf").unwrap();
+        assert_eq!(filled, vec!["applicant.name".to_owned()]);
+        let pdf = update_fields(&document.save_to_bytes().unwrap(), &values, &filled, "form.pdf").unwrap();
+        let pdf = stash_annotations(&pdf).unwrap();
+        let document = pdfium.load_pdf_from_byte_slice(&pdf, None).unwrap();
+        flatten(&document).unwrap();
+        let pdf = restore_annotations(&document.save_to_bytes().unwrap()).unwrap();
+        let document = pdfium.load_pdf_from_byte_slice(&pdf, None).unwrap();
+        let page = document.pages().get(0).unwrap();
+        //поля перенесены в содержимое, комментарий остался аннотацией
+        assert_eq!(page.annotations().len(), 1);
+        assert!(page.annotations().get(0).unwrap().as_form_field().is_none());
+        let text = page.text().unwrap().all();
+        assert!(text.contains("Approved"), "{}", text);
+        assert!(text.contains("Keep me"), "{}", text);
+        assert!(!text.contains("Old value"), "{}", text);
+        assert!(fill_form(&document, &HashMap::from([("missing".to_owned(), "1".to_owned())]), "form.pdf").is_err());
+    }
+
+    ///Страница с флажком `agree`, группой переключателей `sex` (состояния `M` и `F`), выпадающим списком `region`
+    ///с парами (значение, подпись), списком `lang` и выпадающим списком `city` с правом ввода
+    fn choice_sample() -> (Document, Vec<ObjectId>)
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.new_object_id();
+        let blank = document.add_object(Stream::new(dictionary! { "Type" => "XObject", "Subtype" => "Form", "BBox" => vec![0.into(), 0.into(), 10.into(), 10.into()] }, Vec::new()));
+        let rect = || vec![0.into(), 0.into(), 10.into(), 10.into()];
+        let states = |state: &str| dictionary! { "N" => dictionary! { state => blank, "Off" => blank } };
+        let agree = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Widget", "Rect" => rect(), "P" => page_id,
+            "T" => Object::string_literal("agree"), "FT" => "Btn", "V" => "Off", "AS" => "Off", "AP" => states("On"),
+        });
+        let sex = document.new_object_id();
+        let male = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "Widget", "Rect" => rect(), "P" => page_id, "Parent" => sex, "AS" => "M", "AP" => states("M") });
+        let female = document.add_object(dictionary! { "Type" => "Annot", "Subtype" => "Widget", "Rect" => rect(), "P" => page_id, "Parent" => sex, "AS" => "Off", "AP" => states("F") });
+        document.objects.insert(sex, Object::Dictionary(dictionary! { "T" => Object::string_literal("sex"), "FT" => "Btn", "Ff" => 1 << 15 | 1 << 14, "V" => "M", "Kids" => vec![male.into(), female.into()] }));
+        let choice = |document: &mut Document, name: &str, flags: i64, options: Vec<Object>| document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Widget", "Rect" => rect(), "P" => page_id, "AP" => dictionary! { "N" => blank },
+            "T" => Object::string_literal(name), "FT" => "Ch", "Ff" => flags, "Opt" => options,
+        });
+        let pair = |value: &str, label: &str| Object::Array(vec![lopdf::text_string(value), lopdf::text_string(label)]);
+        let region = choice(&mut document, "region", 1 << 17, vec![pair("77", "Москва"), pair("78", "Санкт-Петербург")]);
+        let lang = choice(&mut document, "lang", 0, vec![Object::string_literal("ru"), Object::string_literal("en")]);
+        let city = choice(&mut document, "city", 1 << 17 | 1 << 18, vec![lopdf::text_string("Москва")]);
+        let fields = [agree, sex, region, lang, city];
+        let annots: Vec<Object> = vec![agree.into(), male.into(), female.into(), region.into(), lang.into(), city.into()];
+        document.objects.insert(page_id, Object::Dictionary(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Annots" => annots }));
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let form = dictionary! { "Fields" => fields.iter().map(|f| Object::Reference(*f)).collect::<Vec<Object>>() };
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "AcroForm" => form });
+        document.trailer.set("Root", catalog);
+        (document, vec![agree, male, female, sex, region, lang, city])
+    }
+
+    fn fill(document: Document, values: &[(&str, &str)]) -> Result<Document, Error>
+    {
+        let values: HashMap<String, String> = values.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let names: Vec<String> = values.keys().cloned().collect();
+        update_fields(&save(document), &values, &names, "form.pdf").map(|pdf| Document::load_mem(&pdf).unwrap())
+    }
+
+    fn name(document: &Document, id: ObjectId, key: &[u8]) -> Vec<u8>
+    {
+        document.get_dictionary(id).unwrap().get(key).unwrap().as_name().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_fill_buttons()
+    {
+        let (document, ids) = choice_sample();
+        let (agree, male, female, sex) = (ids[0], ids[1], ids[2], ids[3]);
+        let filled = fill(document, &[("agree", "да"), ("sex", "F")]).unwrap();
+        //флажок отмечается своим включенным состоянием, а не стандартным Yes
+        assert_eq!((name(&filled, agree, b"V"), name(&filled, agree, b"AS")), (b"On".to_vec(), b"On".to_vec()));
+        //значение группы пишется в поле, выбранная кнопка включается, остальные выключаются
+        assert_eq!(name(&filled, sex, b"V"), b"F");
+        assert_eq!((name(&filled, male, b"AS"), name(&filled, female, b"AS")), (b"Off".to_vec(), b"F".to_vec()));
+        let unchecked = fill(filled, &[("agree", "нет"), ("sex", "/M")]).unwrap();
+        assert_eq!((name(&unchecked, agree, b"V"), name(&unchecked, agree, b"AS")), (b"Off".to_vec(), b"Off".to_vec()));
+        assert_eq!((name(&unchecked, sex, b"V"), name(&unchecked, male, b"AS"), name(&unchecked, female, b"AS")), (b"M".to_vec(), b"M".to_vec(), b"Off".to_vec()));
+        assert!(matches!(fill(unchecked, &[("sex", "X")]), Err(Error::FormValueNotAllowed(value, field, _)) if value == "X" && field == "sex"));
+    }
+
+    #[test]
+    fn test_fill_choices()
+    {
+        let (document, ids) = choice_sample();
+        let (region, lang, city) = (ids[4], ids[5], ids[6]);
+        let filled = fill(document, &[("region", "Москва"), ("lang", "en"), ("city", "Казань")]).unwrap();
+        let field = |id: ObjectId| filled.get_dictionary(id).unwrap().clone();
+        //выбор по подписи записывает экспортное значение
+        assert_eq!(field(region).get(b"V").unwrap().as_str().unwrap(), b"77");
+        assert_eq!(field(region).get(b"I").unwrap().as_array().unwrap(), &vec![Object::Integer(0)]);
+        assert!(!field(region).has(b"AP"));
+        assert_eq!(field(lang).get(b"V").unwrap().as_str().unwrap(), b"en");
+        assert_eq!(field(lang).get(b"I").unwrap().as_array().unwrap(), &vec![Object::Integer(1)]);
+        //свое значение допустимо только в выпадающем списке с правом ввода
+        assert_eq!(lopdf::decode_text_string(field(city).get(b"V").unwrap()).unwrap(), "Казань");
+        assert!(!field(city).has(b"I"));
+        let filled = fill(filled, &[("region", "78")]).unwrap();
+        assert_eq!(filled.get_dictionary(region).unwrap().get(b"V").unwrap().as_str().unwrap(), b"78");
+        assert!(matches!(fill(filled.clone(), &[("region", "Казань")]), Err(Error::FormValueNotAllowed(_, field, _)) if field == "region"));
+        assert!(matches!(fill(filled, &[("lang", "de")]), Err(Error::FormValueNotAllowed(_, field, _)) if field == "lang"));
+    }
+
+    #[test]
+    fn test_stash_annotations()
+    {
+        let (document, _, widget) = sample();
+        let annots = |pdf: &[u8]| -> Vec<Object>
+        {
+            let document = Document::load_mem(pdf).unwrap();
+            let page_id = document.page_iter().next().unwrap();
+            document.get_dictionary(page_id).unwrap().get(b"Annots").unwrap().as_array().unwrap().clone()
+        };
+        let pdf = save(document);
+        let note = annots(&pdf)[2].clone();
+        let stashed = stash_annotations(&pdf).unwrap();
+        assert_eq!(annots(&stashed).len(), 2);
+        assert!(!annots(&stashed).contains(&note) && annots(&stashed).contains(&Object::Reference(widget)));
+        let restored = annots(&restore_annotations(&stashed).unwrap());
+        assert_eq!(restored.len(), 3);
+        assert!(restored.contains(&note));
+    }
+}
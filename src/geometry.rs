@@ -0,0 +1,87 @@
+use pdfium_render::prelude::{PdfPage, PdfPoints, PdfRect, PdfRenderConfig, PdfiumError};
+use serde::Serialize;
+
+///Прямоугольник на странице в пунктах pdf, начало координат - левый нижний угол страницы
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PageRect
+{
+    pub left: f32,
+    pub bottom: f32,
+    pub right: f32,
+    pub top: f32,
+}
+impl PageRect
+{
+    pub fn new(left: f32, bottom: f32, right: f32, top: f32) -> Self
+    {
+        Self { left: left.min(right), bottom: bottom.min(top), right: left.max(right), top: bottom.max(top) }
+    }
+    pub fn width(&self) -> f32
+    {
+        self.right - self.left
+    }
+    pub fn height(&self) -> f32
+    {
+        self.top - self.bottom
+    }
+    pub(crate) fn from_pdf(rect: &PdfRect) -> Self
+    {
+        Self::new(rect.left().value, rect.bottom().value, rect.right().value, rect.top().value)
+    }
+}
+
+///Прямоугольник в пикселях отрендеренной страницы, начало координат - левый верхний угол изображения
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PixelRect
+{
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+impl PixelRect
+{
+    ///Прямоугольник, охватывающий все точки
+    pub(crate) fn bounding(points: &[(i32, i32)]) -> Self
+    {
+        let min_x = points.iter().map(|p| p.0).min().unwrap_or(0);
+        let max_x = points.iter().map(|p| p.0).max().unwrap_or(0);
+        let min_y = points.iter().map(|p| p.1).min().unwrap_or(0);
+        let max_y = points.iter().map(|p| p.1).max().unwrap_or(0);
+        Self { x: min_x, y: min_y, width: max_x - min_x, height: max_y - min_y }
+    }
+    ///Перевод прямоугольника страницы в пиксели рендера с той же конфигурацией, что и у `PdfService`
+    ///учитывается масштаб и поворот альбомных страниц, поэтому переводятся все четыре угла
+    pub(crate) fn from_page_rect(page: &PdfPage, rect: &PageRect, config: &PdfRenderConfig) -> Result<Self, PdfiumError>
+    {
+        let corners = [(rect.left, rect.bottom), (rect.left, rect.top), (rect.right, rect.bottom), (rect.right, rect.top)];
+        let mut points = Vec::with_capacity(corners.len());
+        for (x, y) in corners
+        {
+            points.push(page.points_to_pixels(PdfPoints::new(x), PdfPoints::new(y), config)?);
+        }
+        Ok(Self::bounding(&points))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{PageRect, PixelRect};
+
+    #[test]
+    fn test_bounding_rotated_corners()
+    {
+        //при повороте страницы углы меняются местами, прямоугольник все равно нормализуется
+        let rect = PixelRect::bounding(&[(120, 40), (120, 10), (30, 40), (30, 10)]);
+        assert_eq!(rect, PixelRect { x: 30, y: 10, width: 90, height: 30 });
+    }
+
+    #[test]
+    fn test_page_rect_normalized()
+    {
+        let rect = PageRect::new(100.0, 700.0, 50.0, 650.0);
+        assert_eq!((rect.left, rect.bottom, rect.right, rect.top), (50.0, 650.0, 100.0, 700.0));
+        assert_eq!(rect.width(), 50.0);
+    }
+}
@@ -0,0 +1,743 @@
+use std::{io::Cursor, path::{Path, PathBuf}};
+use image::{codecs::jpeg::JpegDecoder, metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader, ImageBuffer, Luma, Rgb, Rgba, LumaA};
+use lopdf::{dictionary, Document, Object, Stream, StringFormat};
+use tiff::tags::{CompressionMethod, PhotometricInterpretation, Tag};
+use crate::{bitmap, error::Error};
+
+///Разрешение по умолчанию для размера страницы `PdfPageSize::Fit`
+const DEFAULT_DPI: f32 = 300.0;
+const POINTS_PER_INCH: f32 = 72.0;
+
+///Размер страниц pdf, создаваемого из изображений
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PdfPageSize
+{
+    ///Страница по размеру изображения (с учетом dpi) плюс поля
+    #[default]
+    Fit,
+    A4,
+    Letter,
+    ///Размер в пунктах pdf
+    Custom { width: f32, height: f32 },
+}
+impl PdfPageSize
+{
+    fn points(&self) -> Option<(f32, f32)>
+    {
+        match self
+        {
+            PdfPageSize::Fit => None,
+            PdfPageSize::A4 => Some((595.28, 841.89)),
+            PdfPageSize::Letter => Some((612.0, 792.0)),
+            PdfPageSize::Custom { width, height } => Some((*width, *height))
+        }
+    }
+}
+
+enum ImageInput
+{
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+    Image(DynamicImage),
+}
+
+///Создание pdf из изображений: одно изображение на страницу, в порядке добавления
+///jpeg встраивается как есть без перекодирования, png и tiff (все страницы tiff) - без потерь, 16 бит на канал сохраняются
+pub struct ImagePdfBuilder
+{
+    inputs: Vec<ImageInput>,
+    page_size: PdfPageSize,
+    margin: f32,
+    dpi: Option<f32>,
+}
+impl Default for ImagePdfBuilder
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+impl ImagePdfBuilder
+{
+    pub fn new() -> Self
+    {
+        Self { inputs: Vec::new(), page_size: PdfPageSize::default(), margin: 0.0, dpi: None }
+    }
+    ///Размер страниц, для фиксированного размера ориентация страницы подбирается под изображение
+    pub fn set_page_size(mut self, page_size: PdfPageSize) -> Self
+    {
+        self.page_size = page_size;
+        self
+    }
+    ///Поля страницы в пунктах pdf
+    pub fn set_margin(mut self, margin: f32) -> Self
+    {
+        self.margin = margin.max(0.0);
+        self
+    }
+    ///Разрешение изображений: задает их физический размер на странице
+    ///без него `Fit` считает изображения в 300 dpi, а на страницах фиксированного размера изображение растягивается на всю область внутри полей
+    pub fn set_dpi(mut self, dpi: f32) -> Self
+    {
+        self.dpi = Some(dpi);
+        self
+    }
+    ///Файл jpeg, png или tiff
+    pub fn add_file<P: AsRef<Path>>(mut self, path: P) -> Self
+    {
+        self.inputs.push(ImageInput::Path(path.as_ref().to_owned()));
+        self
+    }
+    ///Файл jpeg, png или tiff в памяти
+    pub fn add_bytes(mut self, bytes: Vec<u8>) -> Self
+    {
+        self.inputs.push(ImageInput::Bytes(bytes));
+        self
+    }
+    pub fn add_image(mut self, image: DynamicImage) -> Self
+    {
+        self.inputs.push(ImageInput::Image(image));
+        self
+    }
+    pub fn images_count(&self) -> usize
+    {
+        self.inputs.len()
+    }
+
+    pub async fn build(self) -> Result<Vec<u8>, Error>
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::task::spawn_blocking(move ||
+        {
+            let _ = sender.send(self.build_blocking());
+        });
+        receiver.await.unwrap_or_else(|_| Err(Error::ChannelError("создание pdf из изображений".to_owned())))
+    }
+
+    ///То же что и `build`, но результат сохраняется в файл, который потом можно открыть через `PdfService`
+    pub async fn save_to_file<P: AsRef<Path>>(self, output: P) -> Result<(), Error>
+    {
+        let pdf = self.build().await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    fn build_blocking(&self) -> Result<Vec<u8>, Error>
+    {
+        if self.inputs.is_empty()
+        {
+            return Err(Error::NoneImageSource);
+        }
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let mut kids: Vec<Object> = Vec::new();
+        for (index, input) in self.inputs.iter().enumerate()
+        {
+            for image in prepare(input, index)?
+            {
+                let (display_width, display_height) = image.display_size();
+                let layout = layout(self.page_size, self.margin, self.dpi, display_width, display_height);
+                let matrix = image_matrix(image.orientation, layout.x, layout.y, layout.width, layout.height);
+                let content = format!("q {} cm /Im0 Do Q", matrix.iter().map(|v| format!("{:.4}", v)).collect::<Vec<String>>().join(" "));
+                let image_id = document.add_object(image.stream);
+                let content_id = document.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+                let page = document.add_object(dictionary!
+                {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), Object::Real(layout.page_width), Object::Real(layout.page_height)],
+                    "Resources" => dictionary! { "XObject" => dictionary! { "Im0" => image_id } },
+                    "Contents" => content_id
+                });
+                kids.push(page.into());
+            }
+        }
+        let count = kids.len() as i64;
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => count }));
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+///Изображение, готовое к записи в pdf
+struct PreparedImage
+{
+    width: u32,
+    height: u32,
+    ///Поворот из exif, для jpeg применяется матрицей на странице, чтобы не перекодировать изображение
+    orientation: Orientation,
+    stream: Stream,
+}
+impl PreparedImage
+{
+    fn display_size(&self) -> (u32, u32)
+    {
+        match self.orientation
+        {
+            Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH => (self.height, self.width),
+            _ => (self.width, self.height)
+        }
+    }
+}
+
+fn prepare(input: &ImageInput, index: usize) -> Result<Vec<PreparedImage>, Error>
+{
+    match input
+    {
+        ImageInput::Image(image) => Ok(vec![from_pixels(image.clone())]),
+        ImageInput::Bytes(bytes) => prepare_bytes(bytes, &format!("№{}", index + 1)),
+        ImageInput::Path(path) => prepare_bytes(&std::fs::read(path)?, &path.display().to_string())
+    }
+}
+
+fn prepare_bytes(bytes: &[u8], name: &str) -> Result<Vec<PreparedImage>, Error>
+{
+    match image::guess_format(bytes)
+    {
+        Ok(ImageFormat::Jpeg) => Ok(vec![from_jpeg(bytes)?]),
+        Ok(ImageFormat::Png) =>
+        {
+            let mut decoder = ImageReader::with_format(Cursor::new(bytes), ImageFormat::Png).into_decoder()?;
+            let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+            let mut image = DynamicImage::from_decoder(decoder)?;
+            image.apply_orientation(orientation);
+            Ok(vec![from_pixels(image)])
+        },
+        Ok(ImageFormat::Tiff) => from_tiff(bytes, name),
+        Ok(other) => Err(Error::UnsupportedImageInput(name.to_owned(), format!("{:?}", other))),
+        Err(_) => Err(Error::UnsupportedImageInput(name.to_owned(), "неизвестный".to_owned()))
+    }
+}
+
+///Jpeg встраивается без перекодирования, cmyk перекодируется, потому что у таких файлов часто инвертированные каналы
+fn from_jpeg(bytes: &[u8]) -> Result<PreparedImage, Error>
+{
+    let color_space = match jpeg_components(bytes)
+    {
+        Some(1) => "DeviceGray",
+        Some(3) => "DeviceRGB",
+        _ =>
+        {
+            let mut decoder = JpegDecoder::new(Cursor::new(bytes))?;
+            let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+            let mut image = DynamicImage::from_decoder(decoder)?;
+            image.apply_orientation(orientation);
+            return Ok(from_pixels(image));
+        }
+    };
+    let mut decoder = JpegDecoder::new(Cursor::new(bytes))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let (width, height) = decoder.dimensions();
+    let dict = dictionary!
+    {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => width as i64,
+        "Height" => height as i64,
+        "ColorSpace" => color_space,
+        "BitsPerComponent" => 8,
+        "Filter" => "DCTDecode"
+    };
+    Ok(PreparedImage { width, height, orientation, stream: Stream::new(dict, bytes.to_vec()).with_compression(false) })
+}
+
+///Количество компонентов из заголовка кадра (SOF) jpeg
+fn jpeg_components(bytes: &[u8]) -> Option<u8>
+{
+    let mut position = 2;
+    while position + 4 <= bytes.len()
+    {
+        if bytes[position] != 0xFF
+        {
+            return None;
+        }
+        let marker = bytes[position + 1];
+        //маркеры без длины
+        if marker == 0xFF || marker == 0x01 || (0xD0..=0xD7).contains(&marker)
+        {
+            position += if marker == 0xFF { 1 } else { 2 };
+            continue;
+        }
+        let length = u16::from_be_bytes([bytes[position + 2], bytes[position + 3]]) as usize;
+        let is_frame = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_frame
+        {
+            return bytes.get(position + 9).copied();
+        }
+        position += 2 + length;
+    }
+    None
+}
+
+///Все страницы tiff, поддерживаются 8 и 16 бит на канал в оттенках серого и rgb,
+///а также 1, 2 и 4 бита в оттенках серого (в том числе CCITT Group 4 из `export_tiff`) и палитра.
+///Обратный порядок бит (FillOrder = 2) поддерживается только для страниц, которые распаковываются здесь
+fn from_tiff(bytes: &[u8], name: &str) -> Result<Vec<PreparedImage>, Error>
+{
+    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes))?;
+    let mut images = Vec::new();
+    loop
+    {
+        let photometric = decoder.find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)?.and_then(PhotometricInterpretation::from_u16);
+        let bits = decoder.find_tag_unsigned_vec::<u16>(Tag::BitsPerSample)?.and_then(|b| b.first().copied()).unwrap_or(1);
+        let reversed = decoder.find_tag_unsigned::<u16>(Tag::FillOrder)? == Some(2);
+        if photometric == Some(PhotometricInterpretation::RGBPalette) || bits < 8
+        {
+            images.push(from_tiff_packed(&mut decoder, bytes, photometric, bits, reversed, name)?);
+        }
+        else if reversed
+        {
+            //tiff игнорирует FillOrder и декодировал бы перепутанные биты
+            return Err(Error::UnsupportedImageInput(name.to_owned(), format!("Tiff {} бит с обратным порядком бит (FillOrder = 2)", bits)));
+        }
+        else
+        {
+            let (width, height) = decoder.dimensions()?;
+            let color = decoder.colortype()?;
+            let image = match (color, decoder.read_image()?)
+            {
+                (tiff::ColorType::Gray(8), tiff::decoder::DecodingResult::U8(data)) => ImageBuffer::<Luma<u8>, _>::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+                (tiff::ColorType::Gray(16), tiff::decoder::DecodingResult::U16(data)) => ImageBuffer::<Luma<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageLuma16),
+                (tiff::ColorType::GrayA(8), tiff::decoder::DecodingResult::U8(data)) => ImageBuffer::<LumaA<u8>, _>::from_raw(width, height, data).map(DynamicImage::ImageLumaA8),
+                (tiff::ColorType::RGB(8), tiff::decoder::DecodingResult::U8(data)) => ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+                (tiff::ColorType::RGB(16), tiff::decoder::DecodingResult::U16(data)) => ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgb16),
+                (tiff::ColorType::RGBA(8), tiff::decoder::DecodingResult::U8(data)) => ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+                (tiff::ColorType::RGBA(16), tiff::decoder::DecodingResult::U16(data)) => ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data).map(DynamicImage::ImageRgba16),
+                _ => None
+            };
+            let Some(image) = image else { return Err(Error::UnsupportedImageInput(name.to_owned(), format!("Tiff {:?}", color))) };
+            images.push(from_pixels(image));
+        }
+        if !decoder.more_images()
+        {
+            break;
+        }
+        decoder.next_image()?;
+    }
+    Ok(images)
+}
+
+///Страница tiff меньше 8 бит на пиксель или с палитрой: tiff такие изображения не декодирует (или декодирует неверно),
+///поэтому полосы распаковываются здесь, а строки пикселей встраиваются в pdf как есть, с тем же количеством бит.
+///При `reversed` (FillOrder = 2) биты в каждом байте полосы идут от младшего к старшему и разворачиваются до распаковки
+fn from_tiff_packed(decoder: &mut tiff::decoder::Decoder<Cursor<&[u8]>>, bytes: &[u8], photometric: Option<PhotometricInterpretation>, bits: u16, reversed: bool, name: &str) -> Result<PreparedImage, Error>
+{
+    let unsupported = |what: String| Error::UnsupportedImageInput(name.to_owned(), format!("Tiff {}", what));
+    let (width, height) = decoder.dimensions()?;
+    let samples = decoder.find_tag_unsigned::<u16>(Tag::SamplesPerPixel)?.unwrap_or(1);
+    if samples != 1 || !matches!(bits, 1 | 2 | 4 | 8) || decoder.find_tag(Tag::TileWidth)?.is_some()
+    {
+        return Err(unsupported(format!("{:?} {} бит, каналов: {}", photometric, bits, samples)));
+    }
+    let compression = decoder.find_tag_unsigned::<u16>(Tag::Compression)?.map(CompressionMethod::from_u16_exhaustive).unwrap_or(CompressionMethod::None);
+    let offsets = decoder.find_tag_unsigned_vec::<u64>(Tag::StripOffsets)?.unwrap_or_default();
+    let counts = decoder.find_tag_unsigned_vec::<u64>(Tag::StripByteCounts)?.unwrap_or_default();
+    let rows_per_strip = decoder.find_tag_unsigned::<u32>(Tag::RowsPerStrip)?.unwrap_or(height).clamp(1, height.max(1));
+    let row_bytes = (width as usize * bits as usize).div_ceil(8);
+    let mut data = Vec::with_capacity(row_bytes * height as usize);
+    for (index, (offset, count)) in offsets.iter().zip(&counts).enumerate()
+    {
+        let broken = || unsupported("с поврежденными полосами".to_owned());
+        let strip = usize::try_from(*offset).ok().zip(usize::try_from(*count).ok())
+            .and_then(|(offset, count)| bytes.get(offset..offset.checked_add(count)?))
+            .ok_or_else(broken)?;
+        let reversed_strip: Vec<u8>;
+        let strip = if reversed
+        {
+            reversed_strip = strip.iter().map(|b| b.reverse_bits()).collect();
+            &reversed_strip[..]
+        }
+        else
+        {
+            strip
+        };
+        let rows = height.saturating_sub(index as u32 * rows_per_strip).min(rows_per_strip);
+        match compression
+        {
+            CompressionMethod::None => data.extend_from_slice(strip),
+            CompressionMethod::PackBits => unpack_bits(strip, &mut data),
+            CompressionMethod::LZW | CompressionMethod::Deflate | CompressionMethod::OldDeflate =>
+            {
+                //сжатие полос в tiff совпадает с фильтрами pdf
+                let filter = if compression == CompressionMethod::LZW { "LZWDecode" } else { "FlateDecode" };
+                let stream = Stream::new(dictionary! { "Filter" => filter }, strip.to_vec());
+                data.extend(stream.decompressed_content().map_err(|_| broken())?);
+            },
+            CompressionMethod::Fax4 if bits == 1 => decode_g4(strip, width, rows, &mut data).ok_or_else(broken)?,
+            other => return Err(unsupported(format!("со сжатием {:?}", other)))
+        }
+    }
+    data.resize(row_bytes * height as usize, 0);
+    let (color_space, decode) = match photometric
+    {
+        Some(PhotometricInterpretation::RGBPalette) =>
+        {
+            //ColorMap: сначала все красные, потом зеленые и синие, по 16 бит
+            let map = decoder.get_tag_u16_vec(Tag::ColorMap)?;
+            let colors = 1usize << bits;
+            if map.len() < colors * 3
+            {
+                return Err(unsupported("с неполной палитрой".to_owned()));
+            }
+            let lookup: Vec<u8> = (0..colors).flat_map(|i| [map[i], map[colors + i], map[colors * 2 + i]]).map(|v| (v >> 8) as u8).collect();
+            (Object::Array(vec!["Indexed".into(), "DeviceRGB".into(), (colors as i64 - 1).into(), Object::String(lookup, StringFormat::Hexadecimal)]), None)
+        },
+        Some(PhotometricInterpretation::WhiteIsZero) => ("DeviceGray".into(), Some(vec![1.into(), 0.into()])),
+        _ => ("DeviceGray".into(), None)
+    };
+    let mut dict = dictionary!
+    {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => width as i64,
+        "Height" => height as i64,
+        "ColorSpace" => color_space,
+        "BitsPerComponent" => bits as i64
+    };
+    if let Some(decode) = decode
+    {
+        dict.set("Decode", decode);
+    }
+    let mut stream = Stream::new(dict, data);
+    let _ = stream.compress();
+    Ok(PreparedImage { width, height, orientation: Orientation::NoTransforms, stream })
+}
+
+///Распаковка PackBits
+fn unpack_bits(input: &[u8], output: &mut Vec<u8>)
+{
+    let mut position = 0;
+    while position < input.len()
+    {
+        let header = input[position] as i8;
+        position += 1;
+        if header >= 0
+        {
+            let end = (position + header as usize + 1).min(input.len());
+            output.extend_from_slice(&input[position..end]);
+            position = end;
+        }
+        else if header != -128
+        {
+            if let Some(byte) = input.get(position)
+            {
+                output.extend(std::iter::repeat_n(*byte, 1 + header.unsigned_abs() as usize));
+            }
+            position += 1;
+        }
+    }
+}
+
+///Распаковка полосы CCITT Group 4 в строки по 1 биту, черный пиксель - единичный бит (как в tiff с WhiteIsZero)
+fn decode_g4(strip: &[u8], width: u32, rows: u32, output: &mut Vec<u8>) -> Option<()>
+{
+    let width = u16::try_from(width).ok()?;
+    let rows = u16::try_from(rows).ok()?;
+    fax::decoder::decode_g4(strip.iter().copied(), width, Some(rows), |line|
+    {
+        let mut row = vec![0u8; (width as usize).div_ceil(8)];
+        for (x, color) in fax::decoder::pels(line, width).enumerate()
+        {
+            if color == fax::Color::Black
+            {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        output.extend_from_slice(&row);
+    })
+}
+
+///Пиксели без потерь в FlateDecode, прозрачность накладывается на белый фон.
+///16 бит на канал встраиваются с `/BitsPerComponent 16`, остальное приводится к 8 битам
+fn from_pixels(image: DynamicImage) -> PreparedImage
+{
+    let (width, height) = (image.width(), image.height());
+    let (color_space, bits, data) = match image
+    {
+        DynamicImage::ImageLuma16(gray) => ("DeviceGray", 16, samples16(gray.as_raw(), 1, false)),
+        DynamicImage::ImageLumaA16(gray) => ("DeviceGray", 16, samples16(gray.as_raw(), 2, true)),
+        DynamicImage::ImageRgb16(rgb) => ("DeviceRGB", 16, samples16(rgb.as_raw(), 3, false)),
+        DynamicImage::ImageRgba16(rgb) => ("DeviceRGB", 16, samples16(rgb.as_raw(), 4, true)),
+        image =>
+        {
+            let image = if image.color().has_color() { image } else { DynamicImage::ImageLumaA8(image.into_luma_alpha8()) };
+            match bitmap::remove_alpha(image, bitmap::DEFAULT_BACKGROUND)
+            {
+                DynamicImage::ImageLuma8(gray) => ("DeviceGray", 8, gray.into_raw()),
+                other => ("DeviceRGB", 8, other.into_rgb8().into_raw())
+            }
+        }
+    };
+    let dict = dictionary!
+    {
+        "Type" => "XObject",
+        "Subtype" => "Image",
+        "Width" => width as i64,
+        "Height" => height as i64,
+        "ColorSpace" => color_space,
+        "BitsPerComponent" => bits
+    };
+    let mut stream = Stream::new(dict, data);
+    let _ = stream.compress();
+    PreparedImage { width, height, orientation: Orientation::NoTransforms, stream }
+}
+
+///Отсчеты 16 бит в байтах pdf (старший байт первым), прозрачность из последнего канала накладывается на белый фон
+fn samples16(samples: &[u16], channels: usize, alpha: bool) -> Vec<u8>
+{
+    let color = if alpha { channels - 1 } else { channels };
+    let mut data = Vec::with_capacity(samples.len() / channels * color * 2);
+    for pixel in samples.chunks_exact(channels)
+    {
+        let opacity = if alpha { pixel[color] as u32 } else { u16::MAX as u32 };
+        for (index, value) in pixel[..color].iter().enumerate()
+        {
+            let background = bitmap::DEFAULT_BACKGROUND[index.min(2)] as u32 * 257;
+            let value = (*value as u32 * opacity + background * (u16::MAX as u32 - opacity) + u16::MAX as u32 / 2) / u16::MAX as u32;
+            data.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+    }
+    data
+}
+
+///Размещение изображения на странице, все размеры в пунктах pdf
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout
+{
+    page_width: f32,
+    page_height: f32,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+fn layout(page_size: PdfPageSize, margin: f32, dpi: Option<f32>, width_px: u32, height_px: u32) -> Layout
+{
+    let (width_px, height_px) = (width_px.max(1) as f32, height_px.max(1) as f32);
+    let natural = |dpi: f32| (width_px / dpi * POINTS_PER_INCH, height_px / dpi * POINTS_PER_INCH);
+    let Some((mut page_width, mut page_height)) = page_size.points() else
+    {
+        let (width, height) = natural(dpi.filter(|d| *d > 0.0).unwrap_or(DEFAULT_DPI));
+        return Layout { page_width: width + margin * 2.0, page_height: height + margin * 2.0, x: margin, y: margin, width, height };
+    };
+    if (width_px > height_px) != (page_width > page_height)
+    {
+        std::mem::swap(&mut page_width, &mut page_height);
+    }
+    let area_width = (page_width - margin * 2.0).max(1.0);
+    let area_height = (page_height - margin * 2.0).max(1.0);
+    let fit = (area_width / width_px).min(area_height / height_px);
+    //с dpi изображение остается в своем физическом размере, если помещается на страницу
+    let scale = match dpi.filter(|d| *d > 0.0)
+    {
+        Some(dpi) => fit.min(POINTS_PER_INCH / dpi),
+        None => fit
+    };
+    let (width, height) = (width_px * scale, height_px * scale);
+    Layout { page_width, page_height, x: (page_width - width) / 2.0, y: (page_height - height) / 2.0, width, height }
+}
+
+///Матрица `cm`, которая переводит единичный квадрат изображения в область `x, y, width, height` с учетом поворота из exif
+///`width` и `height` - размеры уже повернутого изображения
+fn image_matrix(orientation: Orientation, x: f32, y: f32, width: f32, height: f32) -> [f32; 6]
+{
+    //положение повернутого изображения (s, t) в долях области как s0 + su * u + sv * v, где (u, v) - координаты в исходном изображении
+    let ((s0, su, sv), (t0, tu, tv)) = match orientation
+    {
+        Orientation::NoTransforms => ((0.0, 1.0, 0.0), (0.0, 0.0, 1.0)),
+        Orientation::Rotate90 => ((0.0, 0.0, 1.0), (1.0, -1.0, 0.0)),
+        Orientation::Rotate180 => ((1.0, -1.0, 0.0), (1.0, 0.0, -1.0)),
+        Orientation::Rotate270 => ((1.0, 0.0, -1.0), (0.0, 1.0, 0.0)),
+        Orientation::FlipHorizontal => ((1.0, -1.0, 0.0), (0.0, 0.0, 1.0)),
+        Orientation::FlipVertical => ((0.0, 1.0, 0.0), (1.0, 0.0, -1.0)),
+        Orientation::Rotate90FlipH => ((1.0, 0.0, -1.0), (1.0, -1.0, 0.0)),
+        Orientation::Rotate270FlipH => ((0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+    };
+    [width * su, height * tu, width * sv, height * tv, x + width * s0, y + height * t0]
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Cursor;
+    use image::{metadata::Orientation, DynamicImage, GrayImage, ImageBuffer, ImageFormat, Luma, RgbImage};
+    use lopdf::{Document, Object, Stream};
+    use tiff::{encoder::TiffEncoder, tags::Tag};
+    use crate::{dither::Binarization, error::Error, tiff_export::{write_page, TiffMode, TiffOptions}};
+    use super::{from_pixels, from_tiff, image_matrix, jpeg_components, layout, ImagePdfBuilder, PdfPageSize};
+
+    fn jpeg() -> Vec<u8>
+    {
+        let mut buffer = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20)).write_to(&mut buffer, ImageFormat::Jpeg).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_layout()
+    {
+        //по размеру изображения: 600x300 пикселей в 300 dpi это 2x1 дюйма
+        let fit = layout(PdfPageSize::Fit, 10.0, None, 600, 300);
+        assert_eq!((fit.page_width, fit.page_height, fit.x, fit.width, fit.height), (164.0, 92.0, 10.0, 144.0, 72.0));
+        //альбомное изображение на альбомной странице A4, растянуто по ширине с учетом полей
+        let a4 = layout(PdfPageSize::A4, 20.0, None, 2000, 1000);
+        assert!(a4.page_width > a4.page_height);
+        assert!((a4.width - (841.89 - 40.0)).abs() < 0.01);
+        assert!((a4.y - (595.28 - a4.height) / 2.0).abs() < 0.01);
+        //с dpi маленькое изображение не растягивается
+        let small = layout(PdfPageSize::Letter, 0.0, Some(72.0), 100, 200);
+        assert_eq!((small.width, small.height, small.x), (100.0, 200.0, 256.0));
+    }
+
+    #[test]
+    fn test_image_matrix()
+    {
+        let map = |m: [f32; 6], u: f32, v: f32| (m[0] * u + m[2] * v + m[4], m[1] * u + m[3] * v + m[5]);
+        assert_eq!(image_matrix(Orientation::NoTransforms, 10.0, 20.0, 100.0, 50.0), [100.0, 0.0, 0.0, 50.0, 10.0, 20.0]);
+        //поворот на 90 по часовой: левый верхний угол исходного изображения уходит в правый верхний
+        let rotated = image_matrix(Orientation::Rotate90, 0.0, 0.0, 100.0, 50.0);
+        assert_eq!(map(rotated, 0.0, 1.0), (100.0, 50.0));
+        assert_eq!(map(rotated, 0.0, 0.0), (0.0, 50.0));
+        let rotated = image_matrix(Orientation::Rotate270, 0.0, 0.0, 100.0, 50.0);
+        assert_eq!(map(rotated, 0.0, 1.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_build()
+    {
+        let jpeg = jpeg();
+        assert_eq!(jpeg_components(&jpeg), Some(3));
+        let builder = ImagePdfBuilder::new()
+            .set_page_size(PdfPageSize::A4)
+            .set_margin(36.0)
+            .add_bytes(jpeg.clone())
+            .add_image(DynamicImage::ImageRgb8(RgbImage::new(10, 30)));
+        let pdf = Document::load_mem(&builder.build_blocking().unwrap()).unwrap();
+        let pages = pdf.get_pages();
+        assert_eq!(pages.len(), 2);
+        let images: Vec<_> = pdf.objects.values().filter_map(|o| o.as_stream().ok()).filter(|s| s.dict.get(b"Subtype").and_then(|s| s.as_name()).ok() == Some(b"Image".as_slice())).collect();
+        let raw = images.iter().find(|s| s.dict.get(b"Filter").and_then(|f| f.as_name()).ok() == Some(b"DCTDecode".as_slice())).unwrap();
+        //jpeg встроен без перекодирования
+        assert_eq!(raw.content, jpeg);
+        assert!(ImagePdfBuilder::new().build_blocking().is_err());
+        assert!(ImagePdfBuilder::new().add_bytes(b"not an image".to_vec()).build_blocking().is_err());
+    }
+
+    ///Содержимое потока изображения, маленькие потоки могут остаться несжатыми
+    fn content(stream: &Stream) -> Vec<u8>
+    {
+        stream.decompressed_content().unwrap_or_else(|_| stream.content.clone())
+    }
+
+    #[test]
+    fn test_bilevel_tiff()
+    {
+        //левая половина черная, как после export_tiff в режиме Bilevel (CCITT Group 4)
+        let page = DynamicImage::ImageLuma8(GrayImage::from_fn(16, 4, |x, _| Luma([if x < 8 { 0 } else { 255 }])));
+        let options = TiffOptions { dpi: 200, mode: TiffMode::Bilevel(Binarization::Threshold(128)) };
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+            write_page(&mut encoder, &page, &options, 0, 2).unwrap();
+            write_page(&mut encoder, &page, &options, 1, 2).unwrap();
+        }
+        let images = from_tiff(buffer.get_ref(), "scan.tiff").unwrap();
+        assert_eq!(images.len(), 2);
+        let stream = &images[0].stream;
+        assert_eq!(stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 1);
+        assert_eq!(stream.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceGray");
+        //в tiff WhiteIsZero: единичный бит - черный
+        assert_eq!(stream.dict.get(b"Decode").unwrap().as_array().unwrap(), &vec![Object::Integer(1), Object::Integer(0)]);
+        assert_eq!(content(stream), [0xFF, 0x00].repeat(4));
+    }
+
+    #[test]
+    fn test_palette_tiff()
+    {
+        //4 бита на пиксель, 0 - красный, 1 - синий, строки сжаты PackBits
+        let mut color_map = [0u16; 16 * 3];
+        color_map[0] = 0xFFFF;
+        color_map[32 + 1] = 0xFFFF;
+        let data: [u8; 5] = [0x01, 0x01, 0x10, 0xFF, 0x11];
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+            let mut dir = encoder.new_directory().unwrap();
+            let offset = dir.write_data(&data[..]).unwrap();
+            dir.write_tag(Tag::ImageWidth, 4u32).unwrap();
+            dir.write_tag(Tag::ImageLength, 2u32).unwrap();
+            dir.write_tag(Tag::BitsPerSample, 4u16).unwrap();
+            dir.write_tag(Tag::Compression, 0x8005u16).unwrap();
+            dir.write_tag(Tag::PhotometricInterpretation, 3u16).unwrap();
+            dir.write_tag(Tag::StripOffsets, offset as u32).unwrap();
+            dir.write_tag(Tag::SamplesPerPixel, 1u16).unwrap();
+            dir.write_tag(Tag::RowsPerStrip, 2u32).unwrap();
+            dir.write_tag(Tag::StripByteCounts, data.len() as u32).unwrap();
+            dir.write_tag(Tag::ColorMap, &color_map[..]).unwrap();
+            dir.finish().unwrap();
+        }
+        let images = from_tiff(buffer.get_ref(), "palette.tiff").unwrap();
+        let stream = &images[0].stream;
+        let color_space = stream.dict.get(b"ColorSpace").unwrap().as_array().unwrap();
+        assert_eq!(color_space[0].as_name().unwrap(), b"Indexed");
+        assert_eq!(color_space[2].as_i64().unwrap(), 15);
+        assert_eq!(&color_space[3].as_str().unwrap()[..6], &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 4);
+        assert_eq!(content(stream), vec![0x01, 0x10, 0x11, 0x11]);
+    }
+
+    #[test]
+    fn test_16_bit_pixels()
+    {
+        let gray = from_pixels(DynamicImage::ImageLuma16(ImageBuffer::from_raw(2, 1, vec![0x1234u16, 0xFFFE]).unwrap()));
+        assert_eq!(gray.stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+        assert_eq!(gray.stream.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceGray");
+        assert_eq!(content(&gray.stream), vec![0x12, 0x34, 0xFF, 0xFE]);
+        //прозрачный пиксель становится белым, непрозрачный сохраняет все 16 бит
+        let rgba = from_pixels(DynamicImage::ImageRgba16(ImageBuffer::from_raw(2, 1, vec![0x0102u16, 0x0304, 0x0506, 0xFFFF, 0, 0, 0, 0]).unwrap()));
+        assert_eq!(rgba.stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 16);
+        assert_eq!(rgba.stream.dict.get(b"ColorSpace").unwrap().as_name().unwrap(), b"DeviceRGB");
+        assert_eq!(content(&rgba.stream), vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let rgb8 = from_pixels(DynamicImage::ImageRgb8(RgbImage::new(1, 1)));
+        assert_eq!(rgb8.stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 8);
+    }
+
+    ///Несжатая страница tiff с указанным FillOrder
+    fn fill_order_tiff(bits: u16, fill_order: u16, data: &[u8]) -> Vec<u8>
+    {
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+            let mut dir = encoder.new_directory().unwrap();
+            let offset = dir.write_data(data).unwrap();
+            dir.write_tag(Tag::ImageWidth, 8u32).unwrap();
+            dir.write_tag(Tag::ImageLength, 1u32).unwrap();
+            dir.write_tag(Tag::BitsPerSample, bits).unwrap();
+            dir.write_tag(Tag::Compression, 1u16).unwrap();
+            dir.write_tag(Tag::PhotometricInterpretation, 1u16).unwrap();
+            dir.write_tag(Tag::FillOrder, fill_order).unwrap();
+            dir.write_tag(Tag::StripOffsets, offset as u32).unwrap();
+            dir.write_tag(Tag::SamplesPerPixel, 1u16).unwrap();
+            dir.write_tag(Tag::RowsPerStrip, 1u32).unwrap();
+            dir.write_tag(Tag::StripByteCounts, data.len() as u32).unwrap();
+            dir.finish().unwrap();
+        }
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_tiff_fill_order()
+    {
+        //первый пиксель черный: при FillOrder = 2 он хранится в младшем бите
+        let msb = from_tiff(&fill_order_tiff(1, 1, &[0x7F]), "msb.tiff").unwrap();
+        let lsb = from_tiff(&fill_order_tiff(1, 2, &[0xFE]), "lsb.tiff").unwrap();
+        assert_eq!(content(&msb[0].stream), vec![0x7F]);
+        assert_eq!(content(&lsb[0].stream), vec![0x7F]);
+        let gray = fill_order_tiff(8, 2, &[0; 8]);
+        assert!(matches!(from_tiff(&gray, "gray.tiff"), Err(Error::UnsupportedImageInput(..))));
+    }
+}
@@ -0,0 +1,174 @@
+use image::DynamicImage;
+use pdfium_render::prelude::{PdfPage, PdfPageImageObject, PdfPageObject, PdfPageObjectCommon, PdfPageObjectsCommon, PdfPageObjectsIterator};
+use serde::Serialize;
+use crate::{bitmap, geometry::PageRect, PageImageFormat};
+
+///Изображение, встроенное в страницу
+#[derive(Debug, Clone, Serialize)]
+pub struct PageImage
+{
+    ///Номер изображения на странице (с 0), по нему изображение извлекается
+    pub index: usize,
+    ///Область изображения на странице в пунктах pdf
+    ///для изображений внутри form xobject - в координатах этой формы
+    pub rect: PageRect,
+    ///Размер изображения в пикселях как оно хранится в pdf
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u8,
+    ///Фильтры потока в порядке применения, например `DCTDecode` для jpeg или `JBIG2Decode`
+    pub filters: Vec<String>,
+    pub color_space: String,
+    ///Разрешение с учетом размера на странице
+    pub dpi: Option<(f32, f32)>,
+}
+impl PageImage
+{
+    ///Расширение файла, если сырой поток изображения (`image_raw`) это готовый файл известного формата
+    ///у потока JBIG2 нет заголовка файла и он часто ссылается на отдельный поток /JBIG2Globals,
+    ///поэтому отдельно он не открывается и такие изображения нужно брать через `image_pixels`
+    pub fn raw_extension(&self) -> Option<&'static str>
+    {
+        match self.filters.as_slice()
+        {
+            [filter] => match filter.as_str()
+            {
+                "DCTDecode" => Some("jpg"),
+                "JPXDecode" => Some("jp2"),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
+
+pub(crate) fn page_images(page: &PdfPage) -> Vec<PageImage>
+{
+    let mut images = Vec::new();
+    visit_images(page.objects().iter(), &mut |index, image|
+    {
+        images.push(image_info(index, image));
+        true
+    });
+    images
+}
+
+///Сырой поток изображения номер `index` как он хранится в pdf, без применения фильтров
+///`None` если на странице нет изображения с таким номером
+pub(crate) fn image_raw(page: &PdfPage, index: usize) -> Option<Result<Vec<u8>, pdfium_render::prelude::PdfiumError>>
+{
+    let mut result = None;
+    visit_images(page.objects().iter(), &mut |i, image|
+    {
+        if i == index
+        {
+            result = Some(image.get_raw_image_data());
+            return false;
+        }
+        true
+    });
+    result
+}
+
+///Декодированные пиксели изображения номер `index` в исходном разрешении, без маски и трансформаций страницы
+pub(crate) fn image_pixels(page: &PdfPage, index: usize) -> Option<Result<DynamicImage, pdfium_render::prelude::PdfiumError>>
+{
+    let mut result = None;
+    visit_images(page.objects().iter(), &mut |i, image|
+    {
+        if i == index
+        {
+            result = Some(decode_image(image));
+            return false;
+        }
+        true
+    });
+    result
+}
+
+fn decode_image(image: &PdfPageImageObject) -> Result<DynamicImage, pdfium_render::prelude::PdfiumError>
+{
+    let raw = image.get_raw_bitmap()?;
+    let (width, height) = (raw.width() as u32, raw.height() as u32);
+    //png сохраняет альфа канал, если он есть
+    bitmap::bitmap_to_image(raw.as_rgba_bytes(), width, height, raw.format().unwrap_or_default(), PageImageFormat::Png, bitmap::DEFAULT_BACKGROUND)
+        .ok_or(pdfium_render::prelude::PdfiumError::ImageError)
+}
+
+///Обход изображений страницы, включая вложенные в form xobject, в порядке содержимого страницы
+///обход прекращается, если `visitor` вернул `false`
+fn visit_images(objects: PdfPageObjectsIterator, visitor: &mut dyn FnMut(usize, &PdfPageImageObject) -> bool) -> bool
+{
+    let mut index = 0;
+    visit_images_from(objects, &mut index, visitor)
+}
+
+fn visit_images_from(objects: PdfPageObjectsIterator, index: &mut usize, visitor: &mut dyn FnMut(usize, &PdfPageImageObject) -> bool) -> bool
+{
+    for object in objects
+    {
+        match &object
+        {
+            PdfPageObject::Image(image) =>
+            {
+                let current = *index;
+                *index += 1;
+                if !visitor(current, image)
+                {
+                    return false;
+                }
+            },
+            PdfPageObject::XObjectForm(form) if !visit_images_from(form.iter(), index, visitor) => return false,
+            _ => ()
+        }
+    }
+    true
+}
+
+fn image_info(index: usize, image: &PdfPageImageObject) -> PageImage
+{
+    let rect = image.bounds().map(|b| PageRect::from_pdf(&b.to_rect())).unwrap_or(PageRect::new(0.0, 0.0, 0.0, 0.0));
+    let (width, height) = match (image.width(), image.height())
+    {
+        (Ok(w), Ok(h)) => (w as u32, h as u32),
+        _ => (0, 0)
+    };
+    let dpi = match (image.horizontal_dpi(), image.vertical_dpi())
+    {
+        (Ok(h), Ok(v)) if h > 0.0 && v > 0.0 => Some((h, v)),
+        _ => None
+    };
+    PageImage
+    {
+        index,
+        rect,
+        width,
+        height,
+        bits_per_pixel: image.bits_per_pixel().unwrap_or(0),
+        filters: image.filters().iter().map(|f| f.name().to_owned()).collect(),
+        color_space: image.color_space().map(|c| format!("{:?}", c)).unwrap_or_default(),
+        dpi,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::geometry::PageRect;
+    use super::PageImage;
+
+    fn image(filters: &[&str]) -> PageImage
+    {
+        PageImage { index: 0, rect: PageRect::new(0.0, 0.0, 595.0, 842.0), width: 2480, height: 3508, bits_per_pixel: 24, filters: filters.iter().map(|f| f.to_string()).collect(), color_space: "DeviceRGB".to_owned(), dpi: Some((300.0, 300.0)) }
+    }
+
+    #[test]
+    fn test_raw_extension()
+    {
+        assert_eq!(image(&["DCTDecode"]).raw_extension(), Some("jpg"));
+        assert_eq!(image(&["JBIG2Decode"]).raw_extension(), None);
+        //jpeg дополнительно сжатый flate это уже не готовый файл
+        assert_eq!(image(&["FlateDecode", "DCTDecode"]).raw_extension(), None);
+        assert_eq!(image(&[]).raw_extension(), None);
+    }
+}
@@ -1,12 +1,63 @@
 mod error;
 mod service;
+mod bitmap;
+mod encoder;
+mod dither;
+mod tiff_export;
+mod geometry;
+mod links;
+mod annotations;
+mod forms;
+mod images;
+mod attachments;
+mod signatures;
+mod assemble;
+mod editor;
+mod merge;
+mod image_pdf;
+mod watermark;
+mod redaction;
+mod pii;
+mod visual_diff;
+mod text_diff;
+mod phash;
+mod blank;
+mod classify;
+mod ocrmypdf;
+mod tesseract;
 pub use service::PdfService;
 pub use error::Error;
+pub use encoder::{BudgetImage, ByteBudget, EncoderSettings, JpegSettings, PngSettings, PngCompression, PngFilter, WebpSettings};
+#[cfg(feature = "avif")]
+pub use encoder::AvifSettings;
+pub use dither::{Binarization, ColorMode};
+pub use tiff_export::{TiffOptions, TiffMode, TiffCompression};
+pub use geometry::{PageRect, PixelRect};
+pub use links::{PageLink, LinkTarget};
+pub use annotations::{PageAnnotation, AnnotationKind, QuadPoints};
+pub use forms::{FormField, FormFieldKind};
+pub use images::PageImage;
+pub use attachments::Attachment;
+pub use signatures::Signature;
+pub use editor::{PageEditor, EditedPage, EditedPageSource};
+pub use merge::MergeSource;
+pub use image_pdf::{ImagePdfBuilder, PdfPageSize};
+pub use watermark::{Watermark, WatermarkPosition};
+pub use redaction::RedactionArea;
+pub use pii::{PiiScanner, PiiFinding, PiiKind};
+pub use visual_diff::{VisualDiff, VisualDiffOptions, PageDiff, PageDiffStatus};
+pub use text_diff::{TextDiff, TextChange, TextChangeKind, TextArea, DiffVersion};
+pub use phash::{PageHash, DocumentSignature, DuplicatePair};
+pub use classify::{PageClass, PageMetrics, PageClassification, DocumentClassification};
+pub use ocrmypdf::{OcrMyPdf, OcrMode, OcrProgress};
+pub use tesseract::{Tesseract, OcrWord, OcrPage, OcrDocument, PageText, TextSource};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PageImageFormat
 {
     Jpeg,
     Png,
-    Webp
+    Webp,
+    #[cfg(feature = "avif")]
+    Avif
 }
\ No newline at end of file
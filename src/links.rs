@@ -0,0 +1,163 @@
+use pdfium_render::prelude::{PdfActionType, PdfDestination, PdfDestinationViewSettings, PdfLink, PdfPage, PdfRenderConfig};
+use serde::Serialize;
+use crate::geometry::{PageRect, PixelRect};
+
+///Ссылка на странице
+#[derive(Debug, Clone, Serialize)]
+pub struct PageLink
+{
+    ///Область ссылки в пунктах pdf
+    pub rect: PageRect,
+    ///Область ссылки в пикселях изображения, которое отдает `convert_page` этого же `PdfService`
+    pub pixels: PixelRect,
+    pub target: LinkTarget,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LinkTarget
+{
+    ///Переход на страницу этого же документа (нумерация с 1), координаты и масштаб если они заданы
+    Internal { page: u32, x: Option<f32>, y: Option<f32>, zoom: Option<f32> },
+    ///Внешняя ссылка
+    External { uri: String },
+    ///Другие действия (запуск приложения, переход в другой документ и т.д.)
+    Other { action: String },
+}
+
+pub(crate) fn page_links(page: &PdfPage, config: &PdfRenderConfig) -> Vec<PageLink>
+{
+    let mut links = Vec::new();
+    for link in page.links().iter()
+    {
+        let Ok(rect) = link.rect() else { continue };
+        let rect = PageRect::from_pdf(&rect);
+        let pixels = match PixelRect::from_page_rect(page, &rect, config)
+        {
+            Ok(pixels) => pixels,
+            Err(e) =>
+            {
+                logger::error!("Ошибка перевода координат ссылки в пиксели: {}", e);
+                continue;
+            }
+        };
+        if let Some(target) = link_target(&link)
+        {
+            links.push(PageLink { rect, pixels, target });
+        }
+    }
+    links
+}
+
+fn link_target(link: &PdfLink) -> Option<LinkTarget>
+{
+    //FPDFLink_GetDest при отсутствии /Dest берет назначение из действия, в том числе из GoToR,
+    //и номер страницы чужого документа выглядел бы как внутренняя ссылка, поэтому сначала проверяется тип действия
+    let action = link.action();
+    match action.as_ref().map(|a| a.action_type())
+    {
+        None | Some(PdfActionType::GoToDestinationInSameDocument) =>
+        {
+            let destination = link.destination()
+                .or_else(|| action.as_ref()?.as_local_destination_action()?.destination().ok())?;
+            destination_target(&destination)
+        },
+        Some(PdfActionType::Uri) =>
+        {
+            let uri = action.as_ref()?.as_uri_action()?.uri().ok()?;
+            Some(LinkTarget::External { uri })
+        },
+        Some(other) => Some(LinkTarget::Other { action: format!("{:?}", other) })
+    }
+}
+
+fn destination_target(destination: &PdfDestination) -> Option<LinkTarget>
+{
+    let page = destination.page_index().ok()? as u32 + 1;
+    let (x, y, zoom) = match destination.view_settings()
+    {
+        Ok(PdfDestinationViewSettings::SpecificCoordinatesAndZoom(x, y, zoom)) => (x.map(|x| x.value), y.map(|y| y.value), zoom),
+        _ => (None, None, None)
+    };
+    Some(LinkTarget::Internal { page, x, y, zoom })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object, ObjectId};
+    use crate::PdfService;
+    use super::{destination_target, link_target, LinkTarget};
+
+    fn link(document: &mut Document, y: i64, target: (&str, Object)) -> Object
+    {
+        let mut annotation = dictionary! { "Type" => "Annot", "Subtype" => "Link", "Rect" => vec![50.into(), y.into(), 200.into(), (y + 20).into()] };
+        annotation.set(target.0, target.1);
+        document.add_object(annotation).into()
+    }
+
+    ///Первая страница со ссылками: URI, явное назначение, действие GoTo, именованное назначение и GoToR
+    fn sample() -> Vec<u8>
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let second: ObjectId = document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] });
+        let xyz = || Object::Array(vec![second.into(), "XYZ".into(), 100.into(), 700.into(), 2.into()]);
+        let fit = || Object::Array(vec![second.into(), "Fit".into()]);
+        let annotations = vec!
+        [
+            link(&mut document, 700, ("A", dictionary! { "S" => "URI", "URI" => Object::string_literal("https://example.ru/") }.into())),
+            link(&mut document, 650, ("Dest", xyz())),
+            link(&mut document, 600, ("A", dictionary! { "S" => "GoTo", "D" => fit() }.into())),
+            link(&mut document, 550, ("Dest", Object::string_literal("chapter"))),
+            link(&mut document, 500, ("A", dictionary! { "S" => "GoToR", "F" => Object::string_literal("other.pdf"), "D" => vec![1.into(), "Fit".into()] }.into())),
+        ];
+        let first = document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Annots" => annotations });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![first.into(), second.into()], "Count" => 2 }));
+        let names = dictionary! { "Dests" => dictionary! { "Names" => vec![Object::string_literal("chapter"), xyz()] } };
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "Names" => names });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_link_target()
+    {
+        let bytes = sample();
+        let pdfium = PdfService::get_instance().unwrap();
+        let document = pdfium.load_pdf_from_byte_slice(&bytes, None).unwrap();
+        let page = document.pages().get(0).unwrap();
+        let targets: Vec<Option<LinkTarget>> = page.links().iter().map(|l| link_target(&l)).collect();
+        let xyz = LinkTarget::Internal { page: 2, x: Some(100.0), y: Some(700.0), zoom: Some(2.0) };
+        assert_eq!(targets, vec!
+        [
+            Some(LinkTarget::External { uri: "https://example.ru/".to_owned() }),
+            Some(xyz.clone()),
+            Some(LinkTarget::Internal { page: 2, x: None, y: None, zoom: None }),
+            Some(xyz),
+            //страница 2 другого документа не должна стать внутренней ссылкой
+            Some(LinkTarget::Other { action: "GoToDestinationInRemoteDocument".to_owned() }),
+        ]);
+    }
+
+    #[test]
+    fn test_destination_target()
+    {
+        let bytes = sample();
+        let pdfium = PdfService::get_instance().unwrap();
+        let document = pdfium.load_pdf_from_byte_slice(&bytes, None).unwrap();
+        let page = document.pages().get(0).unwrap();
+        let links = page.links();
+        let explicit = links.get(1).unwrap().destination().unwrap();
+        assert_eq!(destination_target(&explicit), Some(LinkTarget::Internal { page: 2, x: Some(100.0), y: Some(700.0), zoom: Some(2.0) }));
+        //именованное назначение разрешается через дерево имен каталога
+        let named = links.get(3).unwrap().destination().unwrap();
+        assert_eq!(destination_target(&named), destination_target(&explicit));
+        //у ссылки с действием GoTo нет /Dest, назначение берется из действия
+        let action = links.get(2).unwrap().destination().unwrap();
+        assert_eq!(destination_target(&action), Some(LinkTarget::Internal { page: 2, x: None, y: None, zoom: None }));
+        assert!(links.get(0).unwrap().destination().is_none());
+    }
+}
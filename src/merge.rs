@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use lopdf::{dictionary, Document, Object};
+use pdfium_render::prelude::{PdfDocument, Pdfium};
+use crate::{assemble, error::Error};
+
+///Откуда берется документ для объединения
+#[derive(Debug, Clone)]
+enum MergeInput
+{
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+///Документ для объединения: файл или pdf в памяти, с необязательным выбором страниц и заголовком закладки
+#[derive(Debug, Clone)]
+pub struct MergeSource
+{
+    input: MergeInput,
+    pages: Option<Vec<u32>>,
+    title: Option<String>,
+}
+impl MergeSource
+{
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self
+    {
+        Self { input: MergeInput::Path(path.as_ref().to_owned()), pages: None, title: None }
+    }
+    pub fn from_bytes(bytes: Vec<u8>) -> Self
+    {
+        Self { input: MergeInput::Bytes(bytes), pages: None, title: None }
+    }
+    ///Страницы документа (номера с 1, в указанном порядке), по умолчанию берутся все страницы
+    pub fn set_pages(mut self, pages: &[u32]) -> Self
+    {
+        self.pages = Some(pages.to_vec());
+        self
+    }
+    ///Заголовок закладки документа, по умолчанию имя файла без расширения или `Документ N` для pdf из памяти
+    pub fn set_title(mut self, title: &str) -> Self
+    {
+        self.title = Some(title.to_owned());
+        self
+    }
+
+    ///Имя документа для сообщений об ошибках
+    fn name(&self, index: usize) -> String
+    {
+        match &self.input
+        {
+            MergeInput::Path(path) => path.display().to_string(),
+            MergeInput::Bytes(_) => format!("№{} (из памяти)", index + 1)
+        }
+    }
+    fn outline_title(&self, index: usize) -> String
+    {
+        if let Some(title) = &self.title
+        {
+            return title.clone();
+        }
+        match &self.input
+        {
+            MergeInput::Path(path) => path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| format!("Документ {}", index + 1)),
+            MergeInput::Bytes(_) => format!("Документ {}", index + 1)
+        }
+    }
+    fn load<'a>(&'a self, pdfium: &'a Pdfium) -> Result<PdfDocument<'a>, Error>
+    {
+        let document = match &self.input
+        {
+            MergeInput::Path(path) => pdfium.load_pdf_from_file(path, None)?,
+            MergeInput::Bytes(bytes) => pdfium.load_pdf_from_byte_slice(bytes, None)?
+        };
+        Ok(document)
+    }
+}
+
+///Объединение документов в один pdf в порядке `sources`, при `with_outline` на первую страницу каждого документа добавляется закладка
+pub(crate) fn merge(pdfium: &Pdfium, sources: &[MergeSource], with_outline: bool) -> Result<Vec<u8>, Error>
+{
+    if sources.is_empty()
+    {
+        return Err(Error::NoneMergeSource);
+    }
+    let mut document = pdfium.create_new_pdf()?;
+    let mut outline = Vec::with_capacity(sources.len());
+    for (index, source) in sources.iter().enumerate()
+    {
+        let loaded = source.load(pdfium)?;
+        let pages_count = loaded.pages().len() as u32;
+        let pages = match &source.pages
+        {
+            Some(pages) => pages.clone(),
+            None => (1..=pages_count).collect()
+        };
+        if pages.is_empty()
+        {
+            return Err(Error::NonePageSelect(source.name(index)));
+        }
+        if let Some(wrong) = pages.iter().find(|p| **p < 1 || **p > pages_count)
+        {
+            return Err(Error::WrongPageSelect(source.name(index), pages_count, *wrong));
+        }
+        let destination = document.pages().len();
+        document.pages_mut().copy_pages_from_document(&loaded, &assemble::page_range_string(&pages), destination)?;
+        outline.push((source.outline_title(index), destination as u32 + 1));
+    }
+    let bytes = document.save_to_bytes()?;
+    if with_outline
+    {
+        return Ok(add_outline(&bytes, &outline)?);
+    }
+    Ok(bytes)
+}
+
+///Запись закладок верхнего уровня (заголовок, номер страницы с 1) в сохраненный pdf `target`
+///существующие закладки заменяются, при открытии документа показывается панель закладок
+pub(crate) fn add_outline(target: &[u8], entries: &[(String, u32)]) -> Result<Vec<u8>, lopdf::Error>
+{
+    let mut document = Document::load_mem(target)?;
+    let pages = document.get_pages();
+    let outlines_id = document.new_object_id();
+    let ids: Vec<_> = entries.iter().map(|_| document.new_object_id()).collect();
+    for (index, (title, page)) in entries.iter().enumerate()
+    {
+        let Some(page_id) = pages.get(page) else { return Err(lopdf::Error::PageNumberNotFound(*page)) };
+        let mut item = dictionary!
+        {
+            "Title" => lopdf::text_string(title),
+            "Parent" => outlines_id,
+            "Dest" => vec![(*page_id).into(), Object::Name(b"Fit".to_vec())]
+        };
+        if index > 0
+        {
+            item.set("Prev", ids[index - 1]);
+        }
+        if let Some(next) = ids.get(index + 1)
+        {
+            item.set("Next", *next);
+        }
+        document.objects.insert(ids[index], Object::Dictionary(item));
+    }
+    let mut outlines = dictionary! { "Type" => "Outlines", "Count" => ids.len() as i64 };
+    if let (Some(first), Some(last)) = (ids.first(), ids.last())
+    {
+        outlines.set("First", *first);
+        outlines.set("Last", *last);
+    }
+    document.objects.insert(outlines_id, Object::Dictionary(outlines));
+    let catalog = document.catalog_mut()?;
+    catalog.set("Outlines", outlines_id);
+    catalog.set("PageMode", "UseOutlines");
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object};
+    use super::{add_outline, MergeSource};
+
+    fn document(pages_count: usize) -> Vec<u8>
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let kids: Vec<Object> = (0..pages_count)
+            .map(|_| document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()] }).into())
+            .collect();
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => pages_count as i64 }));
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_add_outline()
+    {
+        let entries = vec![("Сопроводительное письмо".to_owned(), 1), ("Приложение 1".to_owned(), 3)];
+        let merged = Document::load_mem(&add_outline(&document(4), &entries).unwrap()).unwrap();
+        let pages = merged.get_pages();
+        let catalog = merged.catalog().unwrap();
+        assert_eq!(catalog.get(b"PageMode").unwrap().as_name().unwrap(), b"UseOutlines");
+        let outlines = merged.get_dictionary(catalog.get(b"Outlines").unwrap().as_reference().unwrap()).unwrap();
+        assert_eq!(outlines.get(b"Count").unwrap().as_i64().unwrap(), 2);
+        let first = merged.get_dictionary(outlines.get(b"First").unwrap().as_reference().unwrap()).unwrap();
+        assert_eq!(lopdf::decode_text_string(first.get(b"Title").unwrap()).unwrap(), "Сопроводительное письмо");
+        let second_id = first.get(b"Next").unwrap().as_reference().unwrap();
+        assert_eq!(outlines.get(b"Last").unwrap().as_reference().unwrap(), second_id);
+        let second = merged.get_dictionary(second_id).unwrap();
+        let dest = second.get(b"Dest").unwrap().as_array().unwrap();
+        assert_eq!(dest[0].as_reference().unwrap(), pages[&3]);
+        assert!(add_outline(&document(1), &entries).is_err());
+    }
+
+    #[test]
+    fn test_outline_title()
+    {
+        assert_eq!(MergeSource::from_path("/archive/Письмо 12-34.pdf").outline_title(0), "Письмо 12-34");
+        assert_eq!(MergeSource::from_bytes(Vec::new()).outline_title(1), "Документ 2");
+        assert_eq!(MergeSource::from_bytes(Vec::new()).set_title("Приложение").outline_title(1), "Приложение");
+    }
+}
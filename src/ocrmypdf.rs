@@ -0,0 +1,414 @@
+use std::{path::{Path, PathBuf}, process::Stdio, time::Duration};
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, process::Command, sync::mpsc::Sender};
+use crate::{assemble, error::Error};
+
+///Сколько последних строк stderr попадает в текст ошибки
+const STDERR_TAIL: usize = 20;
+
+///Что делать со страницами, у которых уже есть текст
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OcrMode
+{
+    ///Страницы с текстом пропускаются (`--skip-text`)
+    #[default]
+    SkipText,
+    ///Старый невидимый текстовый слой заменяется новым, видимый текст не трогается (`--redo-ocr`)
+    RedoOcr,
+    ///Все страницы растеризуются и распознаются заново (`--force-ocr`)
+    ForceOcr,
+}
+impl OcrMode
+{
+    fn arg(&self) -> &'static str
+    {
+        match self
+        {
+            OcrMode::SkipText => "--skip-text",
+            OcrMode::RedoOcr => "--redo-ocr",
+            OcrMode::ForceOcr => "--force-ocr"
+        }
+    }
+}
+
+///Строка вывода ocrmypdf, для сообщений о конкретной странице указан ее номер
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OcrProgress
+{
+    pub page: Option<u32>,
+    pub message: String,
+}
+impl OcrProgress
+{
+    ///ocrmypdf начинает сообщения о странице с ее номера: `    3 page already has text! - skipping`
+    fn parse(line: &str) -> Self
+    {
+        let trimmed = line.trim();
+        let page = trimmed.split_whitespace().next().and_then(|n| n.parse().ok());
+        Self { page, message: trimmed.to_owned() }
+    }
+}
+
+///Как запускается ocrmypdf
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Runner
+{
+    Local(PathBuf),
+    ///`engine run --rm -i image ...`, файлы хоста контейнеру не видны, поэтому pdf передается через stdin и stdout
+    Container { engine: String, image: String },
+}
+
+///Добавление текстового слоя через ocrmypdf (локальная программа или контейнер из `ocr_mypdf.Containerfile`)
+#[derive(Debug, Clone)]
+pub struct OcrMyPdf
+{
+    runner: Runner,
+    languages: Vec<String>,
+    mode: OcrMode,
+    deskew: bool,
+    clean: bool,
+    rotate_pages: bool,
+    pages: Option<Vec<u32>>,
+    jobs: Option<u32>,
+    timeout: Duration,
+    extra_args: Vec<String>,
+}
+impl Default for OcrMyPdf
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            runner: Runner::Local(PathBuf::from("ocrmypdf")),
+            languages: vec!["rus".to_owned(), "eng".to_owned()],
+            mode: OcrMode::default(),
+            deskew: false,
+            clean: false,
+            rotate_pages: false,
+            pages: None,
+            jobs: None,
+            timeout: Duration::from_secs(600),
+            extra_args: Vec::new(),
+        }
+    }
+}
+impl OcrMyPdf
+{
+    ///ocrmypdf из PATH
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+    ///Запуск через контейнер, например `OcrMyPdf::container("podman", "localhost/ocrmypdf")`
+    pub fn container(engine: &str, image: &str) -> Self
+    {
+        Self { runner: Runner::Container { engine: engine.to_owned(), image: image.to_owned() }, ..Self::default() }
+    }
+    ///Путь к программе ocrmypdf (или совместимой заглушке)
+    pub fn set_program<P: AsRef<Path>>(mut self, program: P) -> Self
+    {
+        self.runner = Runner::Local(program.as_ref().to_owned());
+        self
+    }
+    ///Языки распознавания tesseract, по умолчанию `rus` и `eng`
+    pub fn set_languages(mut self, languages: &[&str]) -> Self
+    {
+        self.languages = languages.iter().map(|l| l.to_string()).collect();
+        self
+    }
+    pub fn set_mode(mut self, mode: OcrMode) -> Self
+    {
+        self.mode = mode;
+        self
+    }
+    ///Выравнивание перекошенных сканов (`--deskew`)
+    pub fn set_deskew(mut self, deskew: bool) -> Self
+    {
+        self.deskew = deskew;
+        self
+    }
+    ///Очистка от шума перед распознаванием через unpaper (`--clean`), в результат попадает исходное изображение
+    pub fn set_clean(mut self, clean: bool) -> Self
+    {
+        self.clean = clean;
+        self
+    }
+    ///Поворот страниц по ориентации текста (`--rotate-pages`)
+    pub fn set_rotate_pages(mut self, rotate: bool) -> Self
+    {
+        self.rotate_pages = rotate;
+        self
+    }
+    ///Распознавать только эти страницы (номера с 1), например из `DocumentClassification::needs_ocr_pages`
+    pub fn set_pages(mut self, pages: &[u32]) -> Self
+    {
+        self.pages = Some(pages.to_vec());
+        self
+    }
+    ///Количество параллельных заданий (`--jobs`), по умолчанию по числу ядер
+    pub fn set_jobs(mut self, jobs: u32) -> Self
+    {
+        self.jobs = Some(jobs);
+        self
+    }
+    ///Максимальное время работы, после него процесс завершается, по умолчанию 10 минут
+    pub fn set_timeout(mut self, timeout: Duration) -> Self
+    {
+        self.timeout = timeout;
+        self
+    }
+    ///Дополнительные аргументы ocrmypdf, добавляются перед входным и выходным файлом
+    pub fn set_extra_args(mut self, args: &[&str]) -> Self
+    {
+        self.extra_args = args.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    ///Распознавание файла `input` с записью результата в `output`, строки вывода ocrmypdf отправляются в `progress`
+    pub async fn run_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q, progress: Option<Sender<OcrProgress>>) -> Result<PathBuf, Error>
+    {
+        let output = output.as_ref().to_owned();
+        match &self.runner
+        {
+            Runner::Local(_) =>
+            {
+                let args = [input.as_ref().as_os_str().to_string_lossy().into_owned(), output.as_os_str().to_string_lossy().into_owned()];
+                self.execute(&args, None, progress).await?;
+            },
+            Runner::Container { .. } =>
+            {
+                let pdf = tokio::fs::read(input).await?;
+                let result = self.execute(&["-".to_owned(), "-".to_owned()], Some(pdf), progress).await?;
+                tokio::fs::write(&output, result).await?;
+            }
+        }
+        Ok(output)
+    }
+
+    ///Распознавание pdf в памяти, pdf передается через stdin, результат читается из stdout
+    pub async fn run_bytes(&self, pdf: Vec<u8>, progress: Option<Sender<OcrProgress>>) -> Result<Vec<u8>, Error>
+    {
+        self.execute(&["-".to_owned(), "-".to_owned()], Some(pdf), progress).await
+    }
+
+    fn args(&self) -> Vec<String>
+    {
+        let mut args = vec!["--language".to_owned(), self.languages.join("+"), self.mode.arg().to_owned()];
+        let flags = [(self.deskew, "--deskew"), (self.clean, "--clean"), (self.rotate_pages, "--rotate-pages")];
+        args.extend(flags.iter().filter(|(on, _)| *on).map(|(_, flag)| flag.to_string()));
+        if let Some(pages) = &self.pages
+        {
+            args.push("--pages".to_owned());
+            args.push(assemble::page_range_string(pages));
+        }
+        if let Some(jobs) = self.jobs
+        {
+            args.push("--jobs".to_owned());
+            args.push(jobs.to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    fn command(&self) -> Command
+    {
+        match &self.runner
+        {
+            Runner::Local(program) => Command::new(program),
+            Runner::Container { engine, image } =>
+            {
+                let mut command = Command::new(engine);
+                command.args(["run", "--rm", "-i", image]);
+                command
+            }
+        }
+    }
+
+    fn program_name(&self) -> String
+    {
+        match &self.runner
+        {
+            Runner::Local(program) => program.display().to_string(),
+            Runner::Container { engine, image } => format!("{} ({})", engine, image)
+        }
+    }
+
+    ///Запуск ocrmypdf с общими аргументами и `files` в конце, возвращается stdout
+    async fn execute(&self, files: &[String], stdin: Option<Vec<u8>>, progress: Option<Sender<OcrProgress>>) -> Result<Vec<u8>, Error>
+    {
+        let mut command = self.command();
+        command.args(self.args()).args(files);
+        run_command(command, self.program_name(), stdin, self.timeout, progress, exit_code_description).await
+    }
+}
+
+///Запуск программы распознавания: `stdin` передается на вход, строки stderr отправляются в `progress`,
+///по истечении `timeout` процесс завершается, при ненулевом коде в ошибку попадают последние строки stderr
+pub(crate) async fn run_command(mut command: Command, program: String, stdin: Option<Vec<u8>>, timeout: Duration, progress: Option<Sender<OcrProgress>>, describe: fn(i32) -> &'static str) -> Result<Vec<u8>, Error>
+{
+    command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn().map_err(|e| Error::OcrStartError(program.clone(), e.to_string()))?;
+    let stdin_task = match (child.stdin.take(), stdin)
+    {
+        (Some(mut pipe), Some(input)) => Some(tokio::spawn(async move
+        {
+            //программа может завершиться с ошибкой, не дочитав вход, такая ошибка записи не важна
+            let _ = pipe.write_all(&input).await;
+        })),
+        _ => None
+    };
+    let mut stdout = child.stdout.take();
+    let stdout_task = tokio::spawn(async move
+    {
+        let mut buffer = Vec::new();
+        if let Some(stdout) = stdout.as_mut()
+        {
+            let _ = stdout.read_to_end(&mut buffer).await;
+        }
+        buffer
+    });
+    let stderr = child.stderr.take();
+    let stderr_task = tokio::spawn(async move
+    {
+        let mut tail: Vec<String> = Vec::new();
+        let Some(stderr) = stderr else { return tail };
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await
+        {
+            if line.trim().is_empty()
+            {
+                continue;
+            }
+            if let Some(progress) = &progress
+            {
+                let _ = progress.send(OcrProgress::parse(&line)).await;
+            }
+            if tail.len() == STDERR_TAIL
+            {
+                tail.remove(0);
+            }
+            tail.push(line);
+        }
+        tail
+    });
+    let status = match tokio::time::timeout(timeout, child.wait()).await
+    {
+        Ok(status) => status?,
+        Err(_) =>
+        {
+            let _ = child.kill().await;
+            return Err(Error::OcrTimeout(program, timeout.as_secs()));
+        }
+    };
+    if let Some(task) = stdin_task
+    {
+        let _ = task.await;
+    }
+    let stdout = stdout_task.await.unwrap_or_default();
+    let tail = stderr_task.await.unwrap_or_default();
+    if !status.success()
+    {
+        let code = status.code().unwrap_or(-1);
+        return Err(Error::OcrFailed(code, describe(code).to_owned(), tail.join("\n")));
+    }
+    Ok(stdout)
+}
+
+///Описание кодов завершения ocrmypdf
+fn exit_code_description(code: i32) -> &'static str
+{
+    match code
+    {
+        1 => "неверные аргументы",
+        2 => "входной файл не является корректным pdf",
+        3 => "не установлена необходимая программа (tesseract, ghostscript, unpaper)",
+        4 => "полученный pdf некорректен",
+        5 => "нет доступа к файлу",
+        6 => "в pdf уже есть текст",
+        7 => "ошибка дочернего процесса",
+        8 => "pdf зашифрован",
+        9 => "неверная конфигурация tesseract (нет языкового пакета?)",
+        10 => "не удалось преобразовать в PDF/A",
+        130 => "прервано",
+        _ => "неизвестная ошибка"
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests
+{
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf, time::Duration};
+    use crate::Error;
+    use super::{OcrMode, OcrMyPdf, OcrProgress};
+
+    ///Заглушка ocrmypdf: записывает аргументы, пишет прогресс в stderr и копирует вход в выход
+    fn stub(name: &str, body: &str) -> (PathBuf, PathBuf)
+    {
+        let dir = std::env::temp_dir().join(format!("pdf_viewer_ocr_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = dir.join("args");
+        let script = dir.join("ocrmypdf");
+        let text = format!("#!/bin/sh\necho \"$@\" > '{}'\nfor arg; do input=$output; output=$arg; done\n{}\n", args.display(), body);
+        std::fs::write(&script, text).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (script, args)
+    }
+
+    const COPY: &str = "echo 'Start processing 2 pages concurrently' >&2\n\
+        echo '    1 page already has text! - skipping all processing on this page' >&2\n\
+        echo '    2 [tesseract] lots of diacritics - possibly poor OCR' >&2\n\
+        if [ \"$input\" = \"-\" ]; then cat; else cat \"$input\" > \"$output\"; fi";
+
+    #[tokio::test]
+    async fn test_run_file()
+    {
+        let (script, args) = stub("file", COPY);
+        let input = script.with_file_name("input.pdf");
+        let output = script.with_file_name("output.pdf");
+        std::fs::write(&input, b"%PDF-1.7 test").unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(16);
+        let ocr = OcrMyPdf::new().set_program(&script).set_deskew(true).set_mode(OcrMode::RedoOcr).set_pages(&[1, 2, 3, 7]);
+        let result = ocr.run_file(&input, &output, Some(sender)).await.unwrap();
+        assert_eq!(std::fs::read(result).unwrap(), b"%PDF-1.7 test");
+        let args = std::fs::read_to_string(args).unwrap();
+        assert_eq!(args.trim(), format!("--language rus+eng --redo-ocr --deskew --pages 1-3,7 {} {}", input.display(), output.display()));
+        let mut progress = Vec::new();
+        while let Some(line) = receiver.recv().await
+        {
+            progress.push(line);
+        }
+        assert_eq!(progress.len(), 3);
+        assert_eq!(progress[0].page, None);
+        assert_eq!(progress[1], OcrProgress { page: Some(1), message: "1 page already has text! - skipping all processing on this page".to_owned() });
+        assert_eq!(progress[2].page, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_run_bytes()
+    {
+        let (script, args) = stub("bytes", COPY);
+        let ocr = OcrMyPdf::new().set_program(&script).set_languages(&["rus"]).set_clean(true);
+        let pdf = vec![7u8; 200_000];
+        assert_eq!(ocr.run_bytes(pdf.clone(), None).await.unwrap(), pdf);
+        assert_eq!(std::fs::read_to_string(args).unwrap().trim(), "--language rus --skip-text --clean - -");
+    }
+
+    #[tokio::test]
+    async fn test_errors()
+    {
+        let (script, _) = stub("failed", "echo 'EncryptedPdfError: Input PDF is encrypted' >&2\nexit 8");
+        match OcrMyPdf::new().set_program(&script).run_bytes(Vec::new(), None).await
+        {
+            Err(Error::OcrFailed(code, _, stderr)) => assert_eq!((code, stderr.as_str()), (8, "EncryptedPdfError: Input PDF is encrypted")),
+            other => panic!("{:?}", other)
+        }
+        let (script, _) = stub("timeout", "sleep 5");
+        let ocr = OcrMyPdf::new().set_program(&script).set_timeout(Duration::from_millis(200));
+        assert!(matches!(ocr.run_bytes(Vec::new(), None).await, Err(Error::OcrTimeout(_, _))));
+        let missing = OcrMyPdf::new().set_program("/nonexistent/ocrmypdf");
+        assert!(matches!(missing.run_bytes(Vec::new(), None).await, Err(Error::OcrStartError(_, _))));
+    }
+}
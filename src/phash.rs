@@ -0,0 +1,253 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+use image::{imageops::{self, FilterType}, GrayImage};
+use pdfium_render::prelude::{PdfDocument, PdfRenderConfig};
+use serde::Serialize;
+use crate::{bitmap, error::Error, PageImageFormat};
+
+///Размер рендера страницы для хеширования, мелкие детали все равно теряются при уменьшении
+const RENDER_SIZE: i32 = 256;
+///Размер изображения для дискретного косинусного преобразования pHash
+const DCT_SIZE: usize = 32;
+
+///Перцептивные хеши страницы: dHash (градиенты яркости) и pHash (низкие частоты DCT)
+///пересканированная или пережатая страница дает хеши, отличающиеся на несколько бит
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PageHash
+{
+    pub page: u32,
+    pub dhash: u64,
+    pub phash: u64,
+}
+impl PageHash
+{
+    ///Количество различающихся бит обоих хешей, от 0 до 128
+    pub fn distance(&self, other: &PageHash) -> u32
+    {
+        (self.dhash ^ other.dhash).count_ones() + (self.phash ^ other.phash).count_ones()
+    }
+    ///Схожесть страниц от 0 до 1
+    pub fn similarity(&self, other: &PageHash) -> f32
+    {
+        1.0 - self.distance(other) as f32 / 128.0
+    }
+}
+
+///Подпись документа: хеши всех страниц по порядку
+///в строковом виде `dhash:phash` в hex через `;`, можно хранить в базе и восстанавливать через `parse`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DocumentSignature
+{
+    pub pages: Vec<PageHash>,
+}
+impl DocumentSignature
+{
+    ///Схожесть документов от 0 до 1: для каждой страницы берется самая похожая страница другого документа,
+    ///поэтому перестановка страниц почти не влияет на результат, а лишние страницы его снижают
+    pub fn similarity(&self, other: &DocumentSignature) -> f32
+    {
+        if self.pages.is_empty() || other.pages.is_empty()
+        {
+            return if self.pages.len() == other.pages.len() { 1.0 } else { 0.0 };
+        }
+        let best = |from: &[PageHash], to: &[PageHash]| -> f32
+        {
+            from.iter().map(|a| to.iter().map(|b| a.similarity(b)).fold(0.0, f32::max)).sum()
+        };
+        (best(&self.pages, &other.pages) + best(&other.pages, &self.pages)) / (self.pages.len() + other.pages.len()) as f32
+    }
+}
+impl Display for DocumentSignature
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        let pages: Vec<String> = self.pages.iter().map(|p| format!("{:016x}:{:016x}", p.dhash, p.phash)).collect();
+        write!(f, "{}", pages.join(";"))
+    }
+}
+impl FromStr for DocumentSignature
+{
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut pages = Vec::new();
+        for (index, page) in s.split(';').filter(|p| !p.is_empty()).enumerate()
+        {
+            let wrong = || Error::SignatureParseError(s.to_owned());
+            let (dhash, phash) = page.split_once(':').ok_or_else(wrong)?;
+            pages.push(PageHash
+            {
+                page: index as u32 + 1,
+                dhash: u64::from_str_radix(dhash, 16).map_err(|_| wrong())?,
+                phash: u64::from_str_radix(phash, 16).map_err(|_| wrong())?,
+            });
+        }
+        Ok(Self { pages })
+    }
+}
+
+///Пара похожих документов
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicatePair
+{
+    pub first: PathBuf,
+    pub second: PathBuf,
+    pub similarity: f32,
+}
+
+///Подпись загруженного документа
+pub(crate) fn signature(document: &PdfDocument, path: &str) -> Result<DocumentSignature, Error>
+{
+    let config = PdfRenderConfig::new().set_target_width(RENDER_SIZE).set_maximum_height(RENDER_SIZE);
+    let mut pages = Vec::with_capacity(document.pages().len() as usize);
+    for (index, page) in document.pages().iter().enumerate()
+    {
+        let page_number = index as u32 + 1;
+        let bitmap = page.render_with_config(&config)?;
+        let image = bitmap::bitmap_to_image(bitmap.as_rgba_bytes(), bitmap.width() as u32, bitmap.height() as u32, bitmap.format().unwrap_or_default(), PageImageFormat::Jpeg, bitmap::DEFAULT_BACKGROUND)
+            .ok_or(Error::ExtractDynamicImageError(path.to_owned(), page_number))?
+            .into_luma8();
+        pages.push(PageHash { page: page_number, dhash: dhash(&image), phash: phash(&image) });
+    }
+    Ok(DocumentSignature { pages })
+}
+
+///Пары документов со схожестью не ниже `threshold`, самые похожие первыми
+pub(crate) fn duplicates(signatures: &[(PathBuf, DocumentSignature)], threshold: f32) -> Vec<DuplicatePair>
+{
+    let mut pairs = Vec::new();
+    for (index, (first, first_signature)) in signatures.iter().enumerate()
+    {
+        for (second, second_signature) in &signatures[index + 1..]
+        {
+            let similarity = first_signature.similarity(second_signature);
+            if similarity >= threshold
+            {
+                pairs.push(DuplicatePair { first: first.clone(), second: second.clone(), similarity });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    pairs
+}
+
+///Бит равен 1, если пиксель светлее соседа справа (изображение 9x8)
+fn dhash(image: &GrayImage) -> u64
+{
+    let small = imageops::resize(image, 9, 8, FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8
+    {
+        for x in 0..8
+        {
+            hash = (hash << 1) | (small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0]) as u64;
+        }
+    }
+    hash
+}
+
+///Бит равен 1, если коэффициент DCT из области 8x8 низких частот больше медианы (постоянная составляющая не учитывается)
+fn phash(image: &GrayImage) -> u64
+{
+    let small = imageops::resize(image, DCT_SIZE as u32, DCT_SIZE as u32, FilterType::Triangle);
+    let pixels: Vec<f32> = small.pixels().map(|p| p.0[0] as f32).collect();
+    let coefficients = dct_low(&pixels);
+    let mut sorted: Vec<f32> = coefficients[1..].to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coefficients.iter().fold(0u64, |hash, c| (hash << 1) | (*c > median) as u64)
+}
+
+///Двумерное DCT-II изображения DCT_SIZE x DCT_SIZE, возвращаются только коэффициенты 8x8 низких частот
+fn dct_low(pixels: &[f32]) -> Vec<f32>
+{
+    let n = DCT_SIZE;
+    let cos: Vec<f32> = (0..8 * n).map(|i|
+    {
+        let (u, x) = (i / n, i % n);
+        (std::f32::consts::PI * (2 * x + 1) as f32 * u as f32 / (2 * n) as f32).cos()
+    }).collect();
+    //сначала по строкам, затем по столбцам
+    let mut rows = vec![0.0; n * 8];
+    for y in 0..n
+    {
+        for u in 0..8
+        {
+            rows[y * 8 + u] = (0..n).map(|x| pixels[y * n + x] * cos[u * n + x]).sum();
+        }
+    }
+    let mut result = vec![0.0; 64];
+    for v in 0..8
+    {
+        for u in 0..8
+        {
+            result[v * 8 + u] = (0..n).map(|y| rows[y * 8 + u] * cos[v * n + y]).sum();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+    use image::{GrayImage, Luma};
+    use super::{dhash, duplicates, phash, DocumentSignature, PageHash};
+
+    ///Страница с "текстом": полосы разной длины
+    fn page(shift: u32, noise: bool) -> GrayImage
+    {
+        GrayImage::from_fn(200, 280, |x, y|
+        {
+            let line = (y + shift) / 14;
+            let ink = (y + shift) % 14 < 7 && x > 20 && x < 60 + (line * 37 % 120);
+            let value: u8 = if ink { 30 } else { 245 };
+            let noise = if noise { ((x * 7 + y * 13) % 11) as u8 } else { 0 };
+            Luma([value.saturating_sub(noise)])
+        })
+    }
+
+    fn hash(image: &GrayImage, page: u32) -> PageHash
+    {
+        PageHash { page, dhash: dhash(image), phash: phash(image) }
+    }
+
+    #[test]
+    fn test_rescan_is_similar()
+    {
+        let original = hash(&page(0, false), 1);
+        let rescan = hash(&page(1, true), 1);
+        let other = hash(&GrayImage::from_fn(200, 280, |x, y| Luma([if (x / 25 + y / 35) % 2 == 0 { 20 } else { 240 }])), 1);
+        assert!(original.distance(&rescan) <= 12, "{}", original.distance(&rescan));
+        assert!(original.similarity(&other) < 0.75, "{}", original.similarity(&other));
+        assert!(original.similarity(&rescan) > original.similarity(&other));
+    }
+
+    #[test]
+    fn test_signature_string()
+    {
+        let signature = DocumentSignature { pages: vec![PageHash { page: 1, dhash: 0xff00, phash: 1 }, PageHash { page: 2, dhash: u64::MAX, phash: 0 }] };
+        let text = signature.to_string();
+        assert_eq!(text, "000000000000ff00:0000000000000001;ffffffffffffffff:0000000000000000");
+        assert_eq!(text.parse::<DocumentSignature>().unwrap(), signature);
+        assert!("12:zz".parse::<DocumentSignature>().is_err());
+    }
+
+    #[test]
+    fn test_duplicates()
+    {
+        let a = PageHash { page: 1, dhash: 0, phash: 0 };
+        let b = PageHash { page: 2, dhash: u64::MAX, phash: u64::MAX };
+        let signatures = vec!
+        [
+            (PathBuf::from("a.pdf"), DocumentSignature { pages: vec![a, b] }),
+            //те же страницы в другом порядке
+            (PathBuf::from("b.pdf"), DocumentSignature { pages: vec![b, a] }),
+            (PathBuf::from("c.pdf"), DocumentSignature { pages: vec![b] }),
+        ];
+        let pairs = duplicates(&signatures, 0.9);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].first.to_str(), pairs[0].second.to_str(), pairs[0].similarity), (Some("a.pdf"), Some("b.pdf"), 1.0));
+        //у c.pdf нет одной страницы: 2 из 3 страниц пары совпадают
+        assert!((signatures[0].1.similarity(&signatures[2].1) - 2.0 / 3.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+use once_cell::sync::Lazy;
+use pdfium_render::prelude::{PdfPage, PdfRenderConfig};
+use regex::Regex;
+use serde::Serialize;
+use crate::{error::Error, geometry::{PageRect, PixelRect}, RedactionArea};
+
+///Вид персональных данных
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiKind
+{
+    Inn,
+    Snils,
+    Passport,
+    Phone,
+    Email,
+    ///Номер банковской карты
+    BankCard,
+    ///Номер расчетного счета (20 цифр)
+    BankAccount,
+    ///Правило, добавленное через `PiiScanner::add_rule`, с его названием
+    Custom(String),
+}
+
+///Найденные персональные данные
+#[derive(Debug, Clone, Serialize)]
+pub struct PiiFinding
+{
+    pub kind: PiiKind,
+    ///Найденный текст как он извлечен со страницы
+    pub value: String,
+    pub page: u32,
+    ///Области по строкам в пунктах pdf
+    pub rects: Vec<PageRect>,
+    ///Те же области в пикселях изображения из `convert_page`
+    pub pixels: Vec<PixelRect>,
+    ///Контрольная сумма проверена (ИНН, СНИЛС, карта, счет при найденном на странице БИК),
+    ///для форматов без контрольной суммы `false`
+    pub validated: bool,
+}
+impl PiiFinding
+{
+    ///Области для `PdfService::redact`
+    pub fn redaction_areas(&self) -> Vec<RedactionArea>
+    {
+        self.rects.iter().map(|rect| RedactionArea::new(self.page, *rect)).collect()
+    }
+}
+
+///БИК российских банков начинается с 04, по нему проверяется ключ расчетного счета
+static BIK: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b04\d{7}\b").expect("бик"));
+
+#[derive(Clone)]
+enum Check
+{
+    None,
+    Inn,
+    Snils,
+    Luhn,
+    Account,
+    ///Серия и номер паспорта без контекста неотличимы от любых 10 цифр, поэтому перед ними должно быть слово "паспорт" или "серия"
+    PassportContext,
+    Custom(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+#[derive(Clone)]
+struct PiiRule
+{
+    kind: PiiKind,
+    regex: Regex,
+    check: Check,
+}
+
+///Поиск персональных данных в тексте страниц
+///встроенные правила: e-mail, СНИЛС, ИНН, карта, расчетный счет, паспорт, телефон; совпадения, которые пересекаются
+///с уже найденными по предыдущим правилам, пропускаются
+#[derive(Clone)]
+pub struct PiiScanner
+{
+    rules: Vec<PiiRule>,
+}
+impl Default for PiiScanner
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+impl PiiScanner
+{
+    pub fn new() -> Self
+    {
+        let rule = |kind, pattern: &str, check| PiiRule { kind, regex: Regex::new(pattern).expect("встроенное правило"), check };
+        Self
+        {
+            rules: vec!
+            [
+                rule(PiiKind::Email, r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+", Check::None),
+                rule(PiiKind::Snils, r"\b\d{3}[- ]?\d{3}[- ]?\d{3}[- ]?\d{2}\b", Check::Snils),
+                rule(PiiKind::Inn, r"\b(?:\d{10}|\d{12})\b", Check::Inn),
+                rule(PiiKind::BankCard, r"\b(?:\d{4}[ -]?){3}\d{4}\b", Check::Luhn),
+                rule(PiiKind::BankAccount, r"\b\d{20}\b", Check::Account),
+                rule(PiiKind::Passport, r"\b\d{2} ?\d{2} ?(?:№ ?)?\d{6}\b", Check::PassportContext),
+                rule(PiiKind::Phone, r"(?:\+7|\b8)[ -]?\(?\d{3}\)?[ -]?\d{3}[ -]?\d{2}[ -]?\d{2}\b", Check::None),
+            ]
+        }
+    }
+    ///Дополнительное правило поиска по регулярному выражению
+    pub fn add_rule(mut self, name: &str, pattern: &str) -> Result<Self, Error>
+    {
+        self.rules.push(PiiRule { kind: PiiKind::Custom(name.to_owned()), regex: Regex::new(pattern)?, check: Check::None });
+        Ok(self)
+    }
+    ///Дополнительное правило с проверкой найденного текста, совпадения, для которых `validator` вернул `false`, отбрасываются
+    pub fn add_rule_with_validator<F>(mut self, name: &str, pattern: &str, validator: F) -> Result<Self, Error>
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static
+    {
+        self.rules.push(PiiRule { kind: PiiKind::Custom(name.to_owned()), regex: Regex::new(pattern)?, check: Check::Custom(Arc::new(validator)) });
+        Ok(self)
+    }
+    ///Отключение встроенного правила
+    pub fn without(mut self, kind: PiiKind) -> Self
+    {
+        self.rules.retain(|r| r.kind != kind);
+        self
+    }
+
+    ///Поиск в тексте, результат - вид, границы совпадения в байтах и признак проверенной контрольной суммы, по порядку в тексте
+    fn scan_text(&self, text: &str) -> Vec<(PiiKind, usize, usize, bool)>
+    {
+        let biks: Vec<String> = BIK.find_iter(text).map(|m| m.as_str().to_owned()).collect();
+        let mut found: Vec<(PiiKind, usize, usize, bool)> = Vec::new();
+        for rule in &self.rules
+        {
+            for m in rule.regex.find_iter(text)
+            {
+                if found.iter().any(|(_, start, end, _)| m.start() < *end && *start < m.end())
+                {
+                    continue;
+                }
+                let digits: String = m.as_str().chars().filter(|c| c.is_ascii_digit()).collect();
+                let validated = match &rule.check
+                {
+                    Check::None => Some(false),
+                    Check::Inn => inn_valid(&digits).then_some(true),
+                    Check::Snils => snils_valid(&digits).then_some(true),
+                    Check::Luhn => luhn_valid(&digits).then_some(true),
+                    Check::Account => Some(biks.iter().any(|bik| account_valid(bik, &digits))),
+                    Check::PassportContext =>
+                    {
+                        let before: String = text[..m.start()].chars().rev().take(40).collect::<Vec<char>>().into_iter().rev().collect();
+                        let before = before.to_lowercase();
+                        (before.contains("паспорт") || before.contains("серия")).then_some(false)
+                    },
+                    Check::Custom(validator) => validator(m.as_str()).then_some(false)
+                };
+                if let Some(validated) = validated
+                {
+                    found.push((rule.kind.clone(), m.start(), m.end(), validated));
+                }
+            }
+        }
+        found.sort_by_key(|f| f.1);
+        found
+    }
+}
+
+///Поиск на странице с переводом найденного текста в области на странице
+pub(crate) fn page_findings(scanner: &PiiScanner, page: &PdfPage, page_number: u32, config: &PdfRenderConfig) -> Result<Vec<PiiFinding>, pdfium_render::prelude::PdfiumError>
+{
+    //текст собирается из символов, чтобы смещения в строке совпадали с областями символов
+    let mut text = String::new();
+    let mut boxes: Vec<(usize, Option<PageRect>)> = Vec::new();
+    for c in page.text()?.chars().iter()
+    {
+        let Some(ch) = c.unicode_char() else { continue };
+        let rect = if ch.is_whitespace() { None } else { c.loose_bounds().ok().map(|b| PageRect::from_pdf(&b)) };
+        boxes.push((text.len(), rect));
+        text.push(ch);
+    }
+    let mut findings = Vec::new();
+    for (kind, start, end, validated) in scanner.scan_text(&text)
+    {
+        let rects = line_rects(boxes.iter().filter(|(offset, _)| *offset >= start && *offset < end).filter_map(|(_, rect)| *rect));
+        let mut pixels = Vec::with_capacity(rects.len());
+        for rect in &rects
+        {
+            pixels.push(PixelRect::from_page_rect(page, rect, config)?);
+        }
+        findings.push(PiiFinding { kind, value: text[start..end].to_owned(), page: page_number, rects, pixels, validated });
+    }
+    Ok(findings)
+}
+
+///Объединение областей символов в одну область на строку
+pub(crate) fn line_rects(chars: impl Iterator<Item = PageRect>) -> Vec<PageRect>
+{
+    let mut lines: Vec<PageRect> = Vec::new();
+    for rect in chars
+    {
+        match lines.last_mut()
+        {
+            //символ на той же строке, если по высоте он перекрывается с ней больше чем наполовину
+            Some(line) if (line.top.min(rect.top) - line.bottom.max(rect.bottom)) > rect.height().min(line.height()) / 2.0 =>
+            {
+                *line = PageRect::new(line.left.min(rect.left), line.bottom.min(rect.bottom), line.right.max(rect.right), line.top.max(rect.top));
+            },
+            _ => lines.push(rect)
+        }
+    }
+    lines
+}
+
+fn digits(value: &str) -> Vec<u32>
+{
+    value.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn weighted(digits: &[u32], weights: &[u32]) -> u32
+{
+    digits.iter().zip(weights).map(|(d, w)| d * w).sum()
+}
+
+fn inn_valid(value: &str) -> bool
+{
+    let d = digits(value);
+    match d.len()
+    {
+        10 => weighted(&d, &[2, 4, 10, 3, 5, 9, 4, 6, 8]) % 11 % 10 == d[9],
+        12 => weighted(&d, &[7, 2, 4, 10, 3, 5, 9, 4, 6, 8]) % 11 % 10 == d[10]
+            && weighted(&d, &[3, 7, 2, 4, 10, 3, 5, 9, 4, 6, 8]) % 11 % 10 == d[11],
+        _ => false
+    }
+}
+
+fn snils_valid(value: &str) -> bool
+{
+    let d = digits(value);
+    if d.len() != 11
+    {
+        return false;
+    }
+    let sum = weighted(&d, &[9, 8, 7, 6, 5, 4, 3, 2, 1]);
+    let check = match sum
+    {
+        0..=99 => sum,
+        100 | 101 => 0,
+        _ => (sum % 101) % 100
+    };
+    check == d[9] * 10 + d[10]
+}
+
+fn luhn_valid(value: &str) -> bool
+{
+    let d = digits(value);
+    if d.len() < 13
+    {
+        return false;
+    }
+    let sum: u32 = d.iter().rev().enumerate().map(|(i, digit)|
+    {
+        if i % 2 == 1 { let doubled = digit * 2; if doubled > 9 { doubled - 9 } else { doubled } } else { *digit }
+    }).sum();
+    sum.is_multiple_of(10)
+}
+
+///Контрольный ключ расчетного счета по трем последним цифрам БИК
+fn account_valid(bik: &str, account: &str) -> bool
+{
+    let d = digits(&format!("{}{}", &bik[bik.len().saturating_sub(3)..], account));
+    if d.len() != 23
+    {
+        return false;
+    }
+    let sum: u32 = d.iter().zip([7, 1, 3].iter().cycle()).map(|(d, w)| d * w % 10).sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::geometry::PageRect;
+    use super::{account_valid, inn_valid, line_rects, luhn_valid, snils_valid, PiiKind, PiiScanner};
+
+    #[test]
+    fn test_checksums()
+    {
+        assert!(inn_valid("7707083893"));
+        assert!(!inn_valid("7707083894"));
+        assert!(inn_valid("500100732259"));
+        assert!(snils_valid("112-233-445 95"));
+        assert!(!snils_valid("112-233-445 96"));
+        assert!(luhn_valid("4111 1111 1111 1111"));
+        assert!(!luhn_valid("4111 1111 1111 1112"));
+        assert!(account_valid("044525225", "40702810938000012345"));
+        assert!(!account_valid("044525225", "40702810038000012345"));
+    }
+
+    fn kinds(scanner: &PiiScanner, text: &str) -> Vec<(PiiKind, String, bool)>
+    {
+        scanner.scan_text(text).into_iter().map(|(kind, start, end, validated)| (kind, text[start..end].to_owned(), validated)).collect()
+    }
+
+    #[test]
+    fn test_scan_text()
+    {
+        let text = "Иванов И.И., ИНН 500100732259, СНИЛС 112-233-445 95, паспорт серия 45 12 № 345678,\n\
+            тел. +7 (495) 123-45-67, ivanov@example.ru, счет 40702810938000012345 БИК 044525225, договор 1234567890";
+        let found = kinds(&PiiScanner::new(), text);
+        assert_eq!(found, vec!
+        [
+            (PiiKind::Inn, "500100732259".to_owned(), true),
+            (PiiKind::Snils, "112-233-445 95".to_owned(), true),
+            (PiiKind::Passport, "45 12 № 345678".to_owned(), false),
+            (PiiKind::Phone, "+7 (495) 123-45-67".to_owned(), false),
+            (PiiKind::Email, "ivanov@example.ru".to_owned(), false),
+            (PiiKind::BankAccount, "40702810938000012345".to_owned(), true),
+        ]);
+        //10 цифр без контекста паспорта и с неверной контрольной суммой ИНН не находятся
+        assert!(kinds(&PiiScanner::new(), "номер 1234567890").is_empty());
+    }
+
+    #[test]
+    fn test_custom_rule()
+    {
+        let scanner = PiiScanner::new()
+            .without(PiiKind::Email)
+            .add_rule_with_validator("полис", r"\b\d{16}\b", |v| v.starts_with('7')).unwrap();
+        let found = kinds(&scanner, "полис ОМС 7700000000000000, полис 1000000000000000, ivanov@example.ru");
+        assert_eq!(found, vec![(PiiKind::Custom("полис".to_owned()), "7700000000000000".to_owned(), false)]);
+        assert!(PiiScanner::new().add_rule("ошибка", "(").is_err());
+    }
+
+    #[test]
+    fn test_line_rects()
+    {
+        let chars = vec![PageRect::new(10.0, 100.0, 16.0, 110.0), PageRect::new(16.0, 101.0, 22.0, 110.0), PageRect::new(10.0, 86.0, 16.0, 96.0)];
+        assert_eq!(line_rects(chars.into_iter()), vec![PageRect::new(10.0, 100.0, 22.0, 110.0), PageRect::new(10.0, 86.0, 16.0, 96.0)]);
+    }
+}
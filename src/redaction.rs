@@ -0,0 +1,519 @@
+use std::collections::HashSet;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use lopdf::{Document, Object, ObjectId};
+use pdfium_render::prelude::{PdfBitmap, PdfBitmapFormat, PdfColor, PdfDocument, PdfMatrix, PdfPage, PdfPageAnnotationCommon, PdfPageContentRegenerationStrategy, PdfPageImageObject, PdfPageObject, PdfPageObjectCommon, PdfPageObjectsCommon, PdfPageRenderRotation, PdfPoints, PdfRect, PdfRenderConfig, PdfiumError};
+use serde::Serialize;
+use crate::{bitmap, geometry::{PageRect, PixelRect}, PageImageFormat};
+
+///Разрешение, с которым растрируются остатки объектов, частично попавших под закрываемую область
+const PATCH_DPI: f32 = 300.0;
+
+///Закрываемая область на странице (номер страницы с 1, прямоугольник в пунктах pdf)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RedactionArea
+{
+    pub page: u32,
+    pub rect: PageRect,
+}
+impl RedactionArea
+{
+    pub fn new(page: u32, rect: PageRect) -> Self
+    {
+        Self { page, rect }
+    }
+}
+
+///Удаление содержимого страницы под областями `rects`:
+///- пиксели изображений под областями закрашиваются черным в самом изображении
+///- аннотации, текстовые, векторные и составные объекты, которые задевают область, удаляются целиком,
+///  а их видимая часть вне области возвращается на страницу растровой заплаткой (текст в ней уже не выделяется)
+///- объекты, область которых pdfium не отдает, тоже удаляются, а заплаткой становится вся страница
+///- поверх областей рисуются черные прямоугольники
+pub(crate) fn redact_page<'a>(document: &PdfDocument<'a>, page: &mut PdfPage<'a>, rects: &[PageRect]) -> Result<(), PdfiumError>
+{
+    if rects.is_empty()
+    {
+        return Ok(());
+    }
+    page.set_content_regeneration_strategy(PdfPageContentRegenerationStrategy::Manual);
+    for index in (0..page.annotations().len()).rev()
+    {
+        let annotation = page.annotations().get(index)?;
+        //аннотация без читаемой области удаляется: под ней может быть то, что закрывается
+        if annotation.bounds().map(|b| intersects_any(&PageRect::from_pdf(&b), rects)).unwrap_or(true)
+        {
+            page.annotations_mut().delete_annotation(annotation)?;
+        }
+    }
+    let mut images = Vec::new();
+    let mut removed = Vec::new();
+    //объекты без читаемой области удаляются: неизвестно, задевают ли они закрываемую область
+    let mut unbounded = Vec::new();
+    {
+        let text = page.text()?;
+        for index in 0..page.objects().len()
+        {
+            let object = page.objects().get(index)?;
+            let Ok(bounds) = object.bounds() else
+            {
+                unbounded.push(index);
+                continue;
+            };
+            let bounds = PageRect::from_pdf(&bounds.to_rect());
+            if !intersects_any(&bounds, rects)
+            {
+                continue;
+            }
+            match &object
+            {
+                PdfPageObject::Image(_) => images.push(index),
+                //рамка текстового объекта бывает шире самих символов, удаляется только текст, символы которого задеты
+                PdfPageObject::Text(text_object) =>
+                {
+                    let chars = text.chars_for_object(text_object)?;
+                    let touched = chars.is_empty() || chars.iter().any(|c| c.loose_bounds().map(|b| intersects_any(&PageRect::from_pdf(&b), rects)).unwrap_or(true));
+                    if touched
+                    {
+                        removed.push((index, bounds));
+                    }
+                },
+                _ => removed.push((index, bounds))
+            }
+        }
+    }
+    //заплатки рендерятся до удаления объектов, с уже закрашенными областями
+    let page_rect = PageRect::from_pdf(&page.boundaries().bounding().map(|b| b.bounds).unwrap_or(page.page_size()));
+    let areas = patch_areas(&removed, !unbounded.is_empty(), &page_rect);
+    let patches = if areas.is_empty() { Vec::new() } else { render_patches(page, rects, &areas)? };
+    for index in &images
+    {
+        let mut object = page.objects().get(*index)?;
+        if let PdfPageObject::Image(image) = &mut object
+        {
+            redact_image(document, image, rects)?;
+        }
+    }
+    let mut indexes: Vec<usize> = removed.iter().map(|(index, _)| *index).chain(unbounded).collect();
+    indexes.sort_unstable();
+    for index in indexes.iter().rev()
+    {
+        page.objects_mut().remove_object_at_index(*index)?;
+    }
+    for (rect, patch) in patches
+    {
+        let mut object = PdfPageImageObject::new(document)?;
+        set_pixels(&mut object, document, &patch)?;
+        object.apply_matrix(PdfMatrix::new(rect.width(), 0.0, 0.0, rect.height(), rect.left, rect.bottom))?;
+        page.objects_mut().add_image_object(object)?;
+    }
+    for rect in rects
+    {
+        let rect = PdfRect::new(PdfPoints::new(rect.bottom), PdfPoints::new(rect.left), PdfPoints::new(rect.top), PdfPoints::new(rect.right));
+        page.objects_mut().create_path_object_rect(rect, None, None, Some(PdfColor::BLACK))?;
+    }
+    page.regenerate_content()
+}
+
+///Области заплаток: области удаляемых объектов, а если удаляются объекты без известной области - вся страница,
+///которая тогда закрывает и остальные заплатки
+fn patch_areas(removed: &[(usize, PageRect)], has_unbounded: bool, page_rect: &PageRect) -> Vec<PageRect>
+{
+    if has_unbounded
+    {
+        return vec![*page_rect];
+    }
+    removed.iter().map(|(_, bounds)| *bounds).collect()
+}
+
+///Растровые копии областей `areas` с закрашенными областями редактирования
+fn render_patches(page: &mut PdfPage, rects: &[PageRect], areas: &[PageRect]) -> Result<Vec<(PageRect, DynamicImage)>, PdfiumError>
+{
+    //страница рендерится без собственного поворота, чтобы пиксели совпадали с осями координат страницы
+    let rotation = page.rotation()?;
+    page.set_rotation(PdfPageRenderRotation::None);
+    let patches = unrotated_patches(page, rects, areas);
+    page.set_rotation(rotation);
+    patches
+}
+
+fn unrotated_patches(page: &PdfPage, rects: &[PageRect], areas: &[PageRect]) -> Result<Vec<(PageRect, DynamicImage)>, PdfiumError>
+{
+    let config = PdfRenderConfig::new()
+        .scale_page_by_factor(PATCH_DPI / 72.0)
+        .render_annotations(false)
+        .render_form_data(false);
+    let rendered = page.render_with_config(&config)?;
+    let mut raster = bitmap::bitmap_to_image(rendered.as_rgba_bytes(), rendered.width() as u32, rendered.height() as u32, rendered.format().unwrap_or_default(), PageImageFormat::Png, bitmap::DEFAULT_BACKGROUND)
+        .ok_or(PdfiumError::ImageError)?;
+    //видимая область страницы (CropBox), за ее пределами пикселей рендера нет
+    let page_rect = PageRect::from_pdf(&page.boundaries().bounding().map(|b| b.bounds).unwrap_or(page.page_size()));
+    for rect in rects
+    {
+        fill(&mut raster, &PixelRect::from_page_rect(page, rect, &config)?);
+    }
+    let mut patches = Vec::with_capacity(areas.len());
+    for bounds in areas
+    {
+        let Some(bounds) = intersection(bounds, &page_rect) else { continue };
+        let pixels = PixelRect::from_page_rect(page, &bounds, &config)?;
+        let (x, y) = (pixels.x.max(0) as u32, pixels.y.max(0) as u32);
+        let width = (pixels.width.max(1) as u32).min(raster.width().saturating_sub(x));
+        let height = (pixels.height.max(1) as u32).min(raster.height().saturating_sub(y));
+        if width == 0 || height == 0
+        {
+            continue;
+        }
+        patches.push((bounds, raster.crop_imm(x, y, width, height)));
+    }
+    Ok(patches)
+}
+
+///Закрашивание пикселей изображения, которые на странице попадают в области редактирования
+fn redact_image(document: &PdfDocument, image: &mut PdfPageImageObject, rects: &[PageRect]) -> Result<(), PdfiumError>
+{
+    let mut pixels =
+    {
+        let raw = image.get_raw_bitmap()?;
+        bitmap::bitmap_to_image(raw.as_rgba_bytes(), raw.width() as u32, raw.height() as u32, raw.format().unwrap_or_default(), PageImageFormat::Png, bitmap::DEFAULT_BACKGROUND)
+            .ok_or(PdfiumError::ImageError)?
+    };
+    let matrix = image.matrix()?;
+    if mask_pixels(&mut pixels, [matrix.a(), matrix.b(), matrix.c(), matrix.d(), matrix.e(), matrix.f()], rects)
+    {
+        set_pixels(image, document, &pixels)?;
+    }
+    Ok(())
+}
+
+///Закрашивание черным пикселей, центр которых после преобразования `matrix` (единичный квадрат изображения в координаты страницы) попадает в области
+///возвращает `true`, если изображение изменилось
+fn mask_pixels(image: &mut DynamicImage, matrix: [f32; 6], rects: &[PageRect]) -> bool
+{
+    let (width, height) = image.dimensions();
+    let [a, b, c, d, e, f] = matrix;
+    let mut changed = false;
+    for py in 0..height
+    {
+        //строка 0 изображения находится сверху, то есть при v = 1
+        let v = 1.0 - (py as f32 + 0.5) / height as f32;
+        for px in 0..width
+        {
+            let u = (px as f32 + 0.5) / width as f32;
+            let (x, y) = (a * u + c * v + e, b * u + d * v + f);
+            if rects.iter().any(|r| x >= r.left && x <= r.right && y >= r.bottom && y <= r.top)
+            {
+                image.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn fill(image: &mut DynamicImage, rect: &PixelRect)
+{
+    let x_end = (rect.x + rect.width).clamp(0, image.width() as i32) as u32;
+    let y_end = (rect.y + rect.height).clamp(0, image.height() as i32) as u32;
+    for y in rect.y.max(0) as u32..y_end
+    {
+        for x in rect.x.max(0) as u32..x_end
+        {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+}
+
+///Запись пикселей в объект изображения pdf (без image_api у pdfium-render нет готового метода)
+fn set_pixels(object: &mut PdfPageImageObject, document: &PdfDocument, image: &DynamicImage) -> Result<(), PdfiumError>
+{
+    let (width, height) = image.dimensions();
+    //pdfium ожидает порядок каналов BGRA
+    let mut buffer = image.to_rgba8().into_raw();
+    for pixel in buffer.chunks_exact_mut(4)
+    {
+        pixel.swap(0, 2);
+    }
+    //SAFETY: буфер ровно на width * height пикселей BGRA без выравнивания строк и живет дольше битмапа,
+    //pdfium копирует данные в объект изображения внутри set_bitmap
+    let bitmap = unsafe { PdfBitmap::from_bytes(width as i32, height as i32, PdfBitmapFormat::BGRA, &mut buffer, document.bindings())? };
+    object.set_bitmap(&bitmap)
+}
+
+fn intersects_any(rect: &PageRect, rects: &[PageRect]) -> bool
+{
+    rects.iter().any(|r| intersection(rect, r).is_some())
+}
+
+fn intersection(a: &PageRect, b: &PageRect) -> Option<PageRect>
+{
+    let (left, bottom, right, top) = (a.left.max(b.left), a.bottom.max(b.bottom), a.right.min(b.right), a.top.min(b.top));
+    if left < right && bottom < top
+    {
+        Some(PageRect::new(left, bottom, right, top))
+    }
+    else
+    {
+        None
+    }
+}
+
+///Области по страницам в порядке страниц
+pub(crate) fn by_page(areas: &[RedactionArea]) -> std::collections::BTreeMap<u32, Vec<PageRect>>
+{
+    let mut pages = std::collections::BTreeMap::new();
+    for area in areas
+    {
+        pages.entry(area.page).or_insert_with(Vec::new).push(area.rect);
+    }
+    pages
+}
+
+///Удаление объектов, на которые больше никто не ссылается: pdfium сохраняет в файл и старые потоки содержимого,
+///в которых остается удаленный текст, а поля форм удаленных виджетов остаются в /AcroForm вместе со значениями
+pub(crate) fn prune(pdf: &[u8]) -> Result<Vec<u8>, lopdf::Error>
+{
+    let mut document = lopdf::Document::load_mem(pdf)?;
+    remove_orphaned_fields(&mut document)?;
+    document.prune_objects();
+    let mut buffer = Vec::new();
+    document.save_to(&mut buffer)?;
+    Ok(buffer)
+}
+
+///Удаление из /AcroForm полей, у которых не осталось виджетов на страницах, и ссылок на удаленные виджеты из /Kids
+fn remove_orphaned_fields(document: &mut Document) -> Result<(), lopdf::Error>
+{
+    let mut annotations = HashSet::new();
+    for page_id in document.get_pages().into_values()
+    {
+        let Ok(annots) = document.get_dictionary(page_id)?.get(b"Annots") else { continue };
+        if let Ok((_, Object::Array(annots))) = document.dereference(annots)
+        {
+            annotations.extend(annots.iter().filter_map(|a| a.as_reference().ok()));
+        }
+    }
+    let form_id = document.catalog()?.get(b"AcroForm").and_then(Object::as_reference).ok();
+    let form = match form_id
+    {
+        Some(id) => document.get_dictionary(id),
+        None => document.catalog()?.get(b"AcroForm").and_then(Object::as_dict)
+    };
+    let Ok(form) = form else { return Ok(()) };
+    let fields = form.get(b"Fields").and_then(Object::as_array).cloned().unwrap_or_default();
+    let order = form.get(b"CO").and_then(Object::as_array).cloned().ok();
+    let mut orphaned = Vec::new();
+    let fields: Vec<Object> = fields.into_iter()
+        .filter(|field| field.as_reference().is_ok_and(|id| keep_field(document, id, &annotations, &mut orphaned)))
+        .collect();
+    //на всякий случай значения удаленных полей стираются, даже если на них осталась ссылка
+    for id in &orphaned
+    {
+        if let Ok(field) = document.get_dictionary_mut(*id)
+        {
+            field.remove(b"V");
+        }
+    }
+    let form = match form_id
+    {
+        Some(id) => document.get_dictionary_mut(id)?,
+        None => document.catalog_mut()?.get_mut(b"AcroForm")?.as_dict_mut()?
+    };
+    form.set("Fields", fields);
+    if let Some(order) = order
+    {
+        form.set("CO", order.into_iter().filter(|f| f.as_reference().map_or(true, |id| !orphaned.contains(&id))).collect::<Vec<Object>>());
+    }
+    Ok(())
+}
+
+///Остается ли поле: виджет - если он есть на странице, поле с дочерними элементами - если остался хотя бы один из них,
+///поле без виджетов и дочерних элементов (только значение) остается
+fn keep_field(document: &mut Document, id: ObjectId, annotations: &HashSet<ObjectId>, orphaned: &mut Vec<ObjectId>) -> bool
+{
+    let Ok(field) = document.get_dictionary(id) else { return false };
+    let kids: Vec<ObjectId> = field.get(b"Kids").and_then(Object::as_array).map(|k| k.iter().filter_map(|k| k.as_reference().ok()).collect()).unwrap_or_default();
+    let is_widget = field.get(b"Subtype").and_then(Object::as_name).is_ok_and(|s| s == b"Widget");
+    let keep = if kids.is_empty()
+    {
+        !is_widget || annotations.contains(&id)
+    }
+    else
+    {
+        let kept: Vec<Object> = kids.into_iter().filter(|kid| keep_field(document, *kid, annotations, orphaned)).map(Object::Reference).collect();
+        let keep = !kept.is_empty();
+        if let Ok(field) = document.get_dictionary_mut(id)
+        {
+            field.set("Kids", kept);
+        }
+        keep
+    };
+    if !keep
+    {
+        orphaned.push(id);
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{DynamicImage, GenericImageView, RgbImage};
+    use lopdf::{dictionary, Document, Object, Stream};
+    use crate::{geometry::PageRect, PdfService};
+    use super::{intersection, mask_pixels, patch_areas, prune, redact_page};
+
+    #[test]
+    fn test_mask_pixels()
+    {
+        //изображение 10x10 пикселей на странице в квадрате 100..200 пунктов
+        let mut image = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, image::Rgb([255, 255, 255])));
+        let changed = mask_pixels(&mut image, [100.0, 0.0, 0.0, 100.0, 100.0, 100.0], &[PageRect::new(100.0, 150.0, 130.0, 200.0)]);
+        assert!(changed);
+        //левая верхняя четверть по высоте и три столбца по ширине
+        assert_eq!(image.get_pixel(0, 0)[0], 0);
+        assert_eq!(image.get_pixel(2, 4)[0], 0);
+        assert_eq!(image.get_pixel(3, 0)[0], 255);
+        assert_eq!(image.get_pixel(0, 5)[0], 255);
+        assert!(!mask_pixels(&mut image, [100.0, 0.0, 0.0, 100.0, 100.0, 100.0], &[PageRect::new(0.0, 0.0, 50.0, 50.0)]));
+    }
+
+    #[test]
+    fn test_patch_areas()
+    {
+        let page = PageRect::new(0.0, 0.0, 595.0, 842.0);
+        let removed = [(3, PageRect::new(10.0, 10.0, 100.0, 30.0)), (7, PageRect::new(50.0, 400.0, 300.0, 420.0))];
+        assert_eq!(patch_areas(&removed, false, &page), vec![removed[0].1, removed[1].1]);
+        //объект без области мог быть где угодно, поэтому его видимая часть возвращается заплаткой на всю страницу
+        assert_eq!(patch_areas(&removed, true, &page), vec![page]);
+        assert_eq!(patch_areas(&[], true, &page), vec![page]);
+        assert!(patch_areas(&[], false, &page).is_empty());
+    }
+
+    #[test]
+    fn test_intersection()
+    {
+        let a = PageRect::new(0.0, 0.0, 100.0, 100.0);
+        assert_eq!(intersection(&a, &PageRect::new(50.0, 50.0, 150.0, 150.0)), Some(PageRect::new(50.0, 50.0, 100.0, 100.0)));
+        assert_eq!(intersection(&a, &PageRect::new(100.0, 0.0, 150.0, 100.0)), None);
+    }
+
+    #[test]
+    fn test_prune_removes_orphaned_content()
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let content = document.add_object(Stream::new(dictionary! {}, b"q Q".to_vec()));
+        let orphan = document.add_object(Stream::new(dictionary! {}, b"BT (secret) Tj ET".to_vec()));
+        let page = document.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Contents" => content });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page.into()], "Count" => 1 }));
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        let pruned = prune(&buffer).unwrap();
+        assert!(!pruned.windows(6).any(|w| w == b"secret"));
+        assert!(Document::load_mem(&pruned).unwrap().get_object(orphan).is_err());
+    }
+
+    fn contains(pdf: &[u8], text: &[u8]) -> bool
+    {
+        pdf.windows(text.len()).any(|w| w == text)
+    }
+
+    #[test]
+    fn test_prune_removes_orphaned_fields()
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.new_object_id();
+        let widget = |parent: Option<lopdf::ObjectId>|
+        {
+            let mut widget = dictionary! { "Type" => "Annot", "Subtype" => "Widget", "Rect" => vec![0.into(), 0.into(), 10.into(), 10.into()], "P" => page_id };
+            if let Some(parent) = parent
+            {
+                widget.set("Parent", parent);
+            }
+            widget
+        };
+        //поле, совмещенное с виджетом, которого уже нет на странице
+        let mut passport = widget(None);
+        passport.set("T", Object::string_literal("passport"));
+        passport.set("FT", "Tx");
+        passport.set("V", Object::string_literal("4512 345678"));
+        let passport = document.add_object(passport);
+        //поле с двумя виджетами, один из которых удален
+        let phone = document.new_object_id();
+        let kept = widget(Some(phone));
+        let kept = document.add_object(kept);
+        let deleted = widget(Some(phone));
+        let deleted = document.add_object(deleted);
+        document.objects.insert(phone, Object::Dictionary(dictionary! { "T" => Object::string_literal("phone"), "FT" => "Tx", "V" => Object::string_literal("+7 495"), "Kids" => vec![kept.into(), deleted.into()] }));
+        //поле без виджетов
+        let hidden = document.add_object(dictionary! { "T" => Object::string_literal("hidden"), "FT" => "Tx", "V" => Object::string_literal("1") });
+        document.objects.insert(page_id, Object::Dictionary(dictionary! { "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()], "Annots" => vec![kept.into()] }));
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let form = dictionary! { "Fields" => vec![passport.into(), phone.into(), hidden.into()], "CO" => vec![passport.into()] };
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "AcroForm" => form });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        let pruned = prune(&buffer).unwrap();
+        assert!(!contains(&pruned, b"4512 345678"));
+        let pruned = Document::load_mem(&pruned).unwrap();
+        let form = pruned.catalog().unwrap().get(b"AcroForm").unwrap().as_dict().unwrap();
+        assert_eq!(form.get(b"Fields").unwrap().as_array().unwrap(), &vec![Object::Reference(phone), Object::Reference(hidden)]);
+        assert!(form.get(b"CO").unwrap().as_array().unwrap().is_empty());
+        assert_eq!(pruned.get_dictionary(phone).unwrap().get(b"Kids").unwrap().as_array().unwrap(), &vec![Object::Reference(kept)]);
+        assert!(pruned.get_object(deleted).is_err());
+    }
+
+    ///Страница с двумя строками текста и текстовым полем между ними
+    fn sample_page() -> Vec<u8>
+    {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.new_object_id();
+        let font = document.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Helvetica", "Encoding" => "WinAnsiEncoding" });
+        let content = document.add_object(Stream::new(dictionary! {}, b"BT /F1 14 Tf 50 700 Td (SECRET 12345) Tj ET BT /F1 14 Tf 50 400 Td (Public text) Tj ET".to_vec()));
+        let appearance = document.add_object(Stream::new(dictionary! { "Type" => "XObject", "Subtype" => "Form", "BBox" => vec![0.into(), 0.into(), 250.into(), 20.into()], "Resources" => dictionary! { "Font" => dictionary! { "Helv" => font } } }, b"/Tx BMC BT /Helv 12 Tf 2 5 Td (Passport 4512) Tj ET EMC".to_vec()));
+        let field = document.add_object(dictionary!
+        {
+            "Type" => "Annot", "Subtype" => "Widget", "Rect" => vec![50.into(), 600.into(), 300.into(), 620.into()], "P" => page_id,
+            "T" => Object::string_literal("passport"), "FT" => "Tx", "V" => Object::string_literal("Passport 4512"), "AP" => dictionary! { "N" => appearance },
+        });
+        document.objects.insert(page_id, Object::Dictionary(dictionary!
+        {
+            "Type" => "Page", "Parent" => pages_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } }, "Contents" => content, "Annots" => vec![field.into()],
+        }));
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => vec![page_id.into()], "Count" => 1 }));
+        let form = document.add_object(dictionary! { "Fields" => vec![field.into()], "DA" => Object::string_literal("/Helv 12 Tf 0 g"), "DR" => dictionary! { "Font" => dictionary! { "Helv" => font } } });
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "AcroForm" => form });
+        document.trailer.set("Root", catalog);
+        let mut buffer = Vec::new();
+        document.save_to(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_redact_page()
+    {
+        let pdfium = PdfService::get_instance().unwrap();
+        let original = sample_page();
+        let document = pdfium.load_pdf_from_byte_slice(&original, None).unwrap();
+        let mut page = document.pages().get(0).unwrap();
+        redact_page(&document, &mut page, &[PageRect::new(40.0, 590.0, 320.0, 720.0)]).unwrap();
+        let redacted = prune(&document.save_to_bytes().unwrap()).unwrap();
+        assert!(!contains(&redacted, b"SECRET") && !contains(&redacted, b"Passport"));
+        let reloaded = pdfium.load_pdf_from_byte_slice(&redacted, None).unwrap();
+        let page = reloaded.pages().get(0).unwrap();
+        let text = page.text().unwrap().all();
+        assert!(text.contains("Public text"), "{}", text);
+        assert!(!text.contains("SECRET") && !text.contains("12345"), "{}", text);
+        assert!(page.annotations().is_empty());
+        assert!(crate::forms::form_fields(&reloaded).is_empty());
+        let fields = Document::load_mem(&redacted).unwrap();
+        let form = fields.catalog().unwrap().get(b"AcroForm").and_then(|f| fields.dereference(f)).unwrap().1.as_dict().unwrap();
+        assert!(form.get(b"Fields").unwrap().as_array().unwrap().is_empty());
+    }
+}
@@ -1,20 +1,26 @@
-use std::{io::Cursor, path::{Path, PathBuf}, sync::Arc};
+use std::{collections::HashMap, io::Cursor, path::{Path, PathBuf}, sync::Arc};
 use crate::{Error, PageImageFormat};
 use super::error;
 use futures::{stream::FuturesOrdered, StreamExt};
-use image::{DynamicImage, GrayImage, ImageFormat, RgbImage, RgbaImage};
+use image::{DynamicImage, Rgb};
 use logger::error;
 use tokio::runtime::Handle;
-use pdfium_render::prelude::{PdfBitmapFormat, PdfPageRenderRotation, PdfRenderConfig, Pdfium};
+use pdfium_render::prelude::{PdfDocument, PdfPage, PdfPageRenderRotation, PdfRenderConfig, Pdfium};
+use crate::{annotations, assemble, blank, classify, attachments, bitmap, dither, editor, encoder, forms, images, links, merge, phash, pii, redaction, signatures, tesseract, text_diff, tiff_export, visual_diff, Attachment, ColorMode, DocumentClassification, DocumentSignature, DuplicatePair, FormField, MergeSource, OcrDocument, OcrPage, PageText, PiiFinding, PiiScanner, RedactionArea, PageAnnotation, PageEditor, PageImage, Signature, PageLink, BudgetImage, ByteBudget, EncoderSettings, DiffVersion, PixelRect, Tesseract, TextDiff, TextSource, TiffOptions, VisualDiff, VisualDiffOptions, Watermark};
 //use pdfium_render::prelude::*;
+#[derive(Clone)]
 pub struct PdfService 
 {
     config: Arc<PdfRenderConfig>,
     path: PathBuf,
+    background: Rgb<u8>,
+    encoder_settings: EncoderSettings,
+    color_mode: ColorMode,
+    watermark: Option<Arc<Watermark>>,
 }
 impl PdfService
 {
-    fn get_path(&self) -> &str
+    pub(crate) fn get_path(&self) -> &str
     {
         self.path.to_str().unwrap_or("")
     }
@@ -26,10 +32,48 @@ impl PdfService
             .set_target_width(w)
             .set_maximum_height(h)
             .rotate_if_landscape(PdfPageRenderRotation::Degrees90, true)),
-            path: path.as_ref().to_owned()
+            path: path.as_ref().to_owned(),
+            background: bitmap::DEFAULT_BACKGROUND,
+            encoder_settings: EncoderSettings::default(),
+            color_mode: ColorMode::default(),
+            watermark: None
         }
     }
-    fn get_instance() -> Result<Pdfium, error::Error> 
+    ///Цветовой режим (оттенки серого, черно-белый с псевдосмешением и т.д.)
+    pub fn set_color_mode(mut self, color_mode: ColorMode) -> Self
+    {
+        self.color_mode = color_mode;
+        self
+    }
+    ///Настройки кодировщиков (качество jpeg, сжатие png, webp с потерями и т.д.)
+    pub fn set_encoder_settings(mut self, settings: EncoderSettings) -> Self
+    {
+        self.encoder_settings = settings;
+        self
+    }
+    ///Цвет фона, на который накладываются прозрачные области страницы для форматов без альфа канала (jpeg)
+    ///по умолчанию белый
+    pub fn set_background(mut self, r: u8, g: u8, b: u8) -> Self
+    {
+        self.background = Rgb([r, g, b]);
+        self
+    }
+    ///Водяной знак, который накладывается на все изображения страниц после рендера и до кодирования,
+    ///исходный pdf не меняется
+    pub fn set_watermark(mut self, watermark: Watermark) -> Self
+    {
+        self.watermark = Some(Arc::new(watermark));
+        self
+    }
+    pub(crate) fn render_config(&self) -> Arc<PdfRenderConfig>
+    {
+        Arc::clone(&self.config)
+    }
+    pub(crate) fn background(&self) -> Rgb<u8>
+    {
+        self.background
+    }
+    pub(crate) fn get_instance() -> Result<Pdfium, error::Error> 
     {
         let dirs = ["./libs/", "libs/"];
         let binding_result = 
@@ -49,13 +93,595 @@ impl PdfService
        
     }
     
+    ///Выполнение операции над загруженным документом в отдельном потоке
+    async fn with_document<T, F>(&self, operation: F) -> Result<T, error::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&PdfDocument, &str) -> Result<T, error::Error> + Send + 'static
+    {
+        self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let document = pdfium.load_pdf_from_file(path, None)?;
+            operation(&document, path_str)
+        }).await
+    }
+
+    ///Выполнение операции с библиотекой pdfium в отдельном потоке,
+    ///для операций, которым кроме исходного документа нужны новые (извлечение и объединение страниц)
+    pub(crate) async fn with_pdfium<T, F>(&self, operation: F) -> Result<T, error::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Pdfium, &Path, &str) -> Result<T, error::Error> + Send + 'static
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let path = self.path.clone();
+        let path_str = self.get_path().to_owned();
+        tokio::task::spawn_blocking(move ||
+        {
+            let result = Self::get_instance().and_then(|pdfium| operation(&pdfium, &path, &path_str));
+            let _ = sender.send(result);
+        });
+        match receiver.await
+        {
+            Ok(result) => result,
+            Err(_) => Err(error::Error::ChannelError(self.get_path().to_owned()))
+        }
+    }
+
+    ///Выполнение операции над структурой pdf (словари, потоки) в отдельном потоке,
+    ///для данных, которые pdfium не отдает
+    async fn with_raw_document<T, F>(&self, operation: F) -> Result<T, error::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&lopdf::Document, &str) -> Result<T, error::Error> + Send + 'static
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let path = self.path.clone();
+        let path_str = self.get_path().to_owned();
+        tokio::task::spawn_blocking(move ||
+        {
+            let result = lopdf::Document::load(&path)
+                .map_err(error::Error::from)
+                .and_then(|document| operation(&document, &path_str));
+            let _ = sender.send(result);
+        });
+        match receiver.await
+        {
+            Ok(result) => result,
+            Err(_) => Err(error::Error::ChannelError(self.get_path().to_owned()))
+        }
+    }
+
+    ///Получение страницы по номеру (с 1) с проверкой что такая страница есть
+    fn get_page<'a>(document: &PdfDocument<'a>, page_number: u32, path: &str) -> Result<PdfPage<'a>, error::Error>
+    {
+        let pages_count = document.pages().len() as u32;
+        if page_number < 1 || page_number > pages_count
+        {
+            return Err(error::Error::WrongPageSelect(path.to_owned(), pages_count, page_number));
+        }
+        Ok(document.pages().get((page_number - 1) as u16)?)
+    }
+
+    ///Ссылки на странице с областями в пунктах pdf и в пикселях изображения, которое отдает `convert_page`
+    pub async fn links(&self, page_number: u32) -> Result<Vec<PageLink>, error::Error>
+    {
+        let config = Arc::clone(&self.config);
+        self.with_document(move |document, path|
+        {
+            let page = Self::get_page(document, page_number, path)?;
+            Ok(links::page_links(&page, &config))
+        }).await
+    }
+
+    ///Аннотации на странице (заметки, выделения, штампы) с областями в пунктах pdf и в пикселях изображения из `convert_page`
+    pub async fn annotations(&self, page_number: u32) -> Result<Vec<PageAnnotation>, error::Error>
+    {
+        let config = Arc::clone(&self.config);
+        self.with_document(move |document, path|
+        {
+            let page = Self::get_page(document, page_number, path)?;
+            Ok(annotations::page_annotations(&page, &config))
+        }).await
+    }
+
+    ///Поля формы AcroForm всех страниц
+    pub async fn form_fields(&self) -> Result<Vec<FormField>, error::Error>
+    {
+        self.with_document(|document, _| Ok(forms::form_fields(document))).await
+    }
+
+    ///Заполнение полей формы (имя поля -> значение) и сохранение заполненного pdf в байты, исходный файл не меняется
+    ///флажки отмечаются значениями `true`, `yes`, `on`, `1`, `да`, переключатели выбираются по имени состояния кнопки, списки - по значению или подписи варианта
+    ///при `flatten` поля переносятся в содержимое страниц и отображаются в любом просмотрщике и в `convert_page`,
+    ///остальные аннотации (комментарии, выделения, ссылки) не переносятся
+    pub async fn fill_form(&self, values: &HashMap<String, String>, flatten: bool) -> Result<Vec<u8>, error::Error>
+    {
+        let values = values.clone();
+        self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let document = pdfium.load_pdf_from_file(path, None)?;
+            let filled = forms::fill_form(&document, &values, path_str)?;
+            let pdf = forms::update_fields(&document.save_to_bytes()?, &values, &filled, path_str)?;
+            if !flatten
+            {
+                return Ok(pdf);
+            }
+            //комментарии и другие аннотации кроме полей остаются аннотациями
+            let pdf = forms::stash_annotations(&pdf)?;
+            let document = pdfium.load_pdf_from_byte_slice(&pdf, None)?;
+            forms::flatten(&document)?;
+            Ok(forms::restore_annotations(&document.save_to_bytes()?)?)
+        }).await
+    }
+
+    ///То же что и `fill_form`, но результат сохраняется в файл
+    pub async fn fill_form_to_file<P: AsRef<Path>>(&self, values: &HashMap<String, String>, flatten: bool, output: P) -> Result<(), error::Error>
+    {
+        let pdf = self.fill_form(values, flatten).await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    ///Изображения, встроенные в страницу: положение, размер в пикселях, фильтры и цветовое пространство
+    pub async fn page_images(&self, page_number: u32) -> Result<Vec<PageImage>, error::Error>
+    {
+        self.with_document(move |document, path|
+        {
+            let page = Self::get_page(document, page_number, path)?;
+            Ok(images::page_images(&page))
+        }).await
+    }
+
+    ///Сырой поток изображения `index` (см. `page_images`) без перерендера и пересжатия,
+    ///для сканов с фильтром `DCTDecode` это исходный jpeg файл
+    pub async fn image_raw(&self, page_number: u32, index: usize) -> Result<Vec<u8>, error::Error>
+    {
+        self.with_document(move |document, path|
+        {
+            let page = Self::get_page(document, page_number, path)?;
+            let count = images::page_images(&page).len();
+            let raw = images::image_raw(&page, index).ok_or(error::Error::WrongImageSelect(path.to_owned(), page_number, count, index))?;
+            Ok(raw?)
+        }).await
+    }
+
+    ///Декодированные пиксели изображения `index` (см. `page_images`) в исходном разрешении
+    pub async fn image_pixels(&self, page_number: u32, index: usize) -> Result<DynamicImage, error::Error>
+    {
+        self.with_document(move |document, path|
+        {
+            let page = Self::get_page(document, page_number, path)?;
+            let count = images::page_images(&page).len();
+            let pixels = images::image_pixels(&page, index).ok_or(error::Error::WrongImageSelect(path.to_owned(), page_number, count, index))?;
+            Ok(pixels?)
+        }).await
+    }
+
+    ///Вложенные файлы: вложения документа и файлы, прикрепленные аннотациями к страницам
+    pub async fn attachments(&self) -> Result<Vec<Attachment>, error::Error>
+    {
+        self.with_raw_document(|document, _| Ok(attachments::attachments(document))).await
+    }
+
+    ///Содержимое вложения `index` (см. `attachments`)
+    pub async fn attachment_bytes(&self, index: usize) -> Result<Vec<u8>, error::Error>
+    {
+        self.with_raw_document(move |document, path|
+        {
+            let count = attachments::attachments(document).len();
+            attachments::read(document, index, path).ok_or(error::Error::WrongAttachmentSelect(path.to_owned(), count, index))?
+        }).await
+    }
+
+    ///Сохранение вложения `index` в файл `output`
+    pub async fn save_attachment<P: AsRef<Path>>(&self, index: usize, output: P) -> Result<(), error::Error>
+    {
+        let bytes = self.attachment_bytes(index).await?;
+        tokio::fs::write(output, bytes).await?;
+        Ok(())
+    }
+
+    ///Сохранение всех вложений в каталог `dir` под их именами (см. `Attachment::file_name`),
+    ///при совпадении имен к имени добавляется номер вложения, возвращаются пути сохраненных файлов
+    ///ссылки на внешние файлы (см. `Attachment::embedded`) пропускаются, содержимого у них в pdf нет
+    pub async fn save_attachments<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<PathBuf>, error::Error>
+    {
+        let list = self.attachments().await?;
+        tokio::fs::create_dir_all(dir.as_ref()).await?;
+        let mut saved: Vec<PathBuf> = Vec::with_capacity(list.len());
+        for attachment in list.into_iter().filter(|a| a.embedded)
+        {
+            let mut output = dir.as_ref().join(attachment.file_name());
+            if saved.contains(&output)
+            {
+                output = dir.as_ref().join(format!("{}_{}", attachment.index, attachment.file_name()));
+            }
+            self.save_attachment(attachment.index, &output).await?;
+            saved.push(output);
+        }
+        Ok(saved)
+    }
+
+    ///Электронные подписи документа с проверкой, дописывалось ли что-то в файл после подписания
+    ///сама криптографическая подпись не проверяется, только структура файла
+    pub async fn signatures(&self) -> Result<Vec<Signature>, error::Error>
+    {
+        self.with_raw_document(|document, path|
+        {
+            let file_len = std::fs::metadata(path)?.len() as usize;
+            Ok(signatures::signatures(document, file_len))
+        }).await
+    }
+
+    ///Новый pdf из выбранных страниц (номера с 1, в указанном порядке) через импорт страниц pdfium:
+    ///векторное содержимое, текст и шрифты сохраняются, при `keep_metadata` копируются метаданные исходного файла
+    pub async fn extract_pages(&self, pages: &[u32], keep_metadata: bool) -> Result<Vec<u8>, error::Error>
+    {
+        if pages.is_empty()
+        {
+            return Err(error::Error::NonePageSelect(self.get_path().to_owned()));
+        }
+        let pages = pages.to_vec();
+        self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let source = pdfium.load_pdf_from_file(path, None)?;
+            let pages_count = source.pages().len() as u32;
+            if let Some(wrong) = pages.iter().find(|p| **p < 1 || **p > pages_count)
+            {
+                return Err(error::Error::WrongPageSelect(path_str.to_owned(), pages_count, *wrong));
+            }
+            let mut document = pdfium.create_new_pdf()?;
+            document.pages_mut().copy_pages_from_document(&source, &assemble::page_range_string(&pages), 0)?;
+            let bytes = document.save_to_bytes()?;
+            if keep_metadata
+            {
+                let raw = lopdf::Document::load(path)?;
+                return Ok(assemble::copy_metadata(&raw, &bytes)?);
+            }
+            Ok(bytes)
+        }).await
+    }
+
+    ///То же что и `extract_pages`, но результат сохраняется в файл
+    pub async fn extract_pages_to_file<P: AsRef<Path>>(&self, pages: &[u32], keep_metadata: bool, output: P) -> Result<(), error::Error>
+    {
+        let pdf = self.extract_pages(pages, keep_metadata).await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    ///Номера пустых страниц (с 1) по рендеру в низком разрешении: страница пустая, если доля краски без полей
+    ///и одиночных точек не больше `threshold` (например 0.001) и в текстовом слое почти нет символов
+    pub async fn blank_pages(&self, threshold: f32) -> Result<Vec<u32>, error::Error>
+    {
+        self.with_document(move |document, path_str|
+        {
+            let mut blank = Vec::new();
+            for (index, page) in document.pages().iter().enumerate()
+            {
+                let page_number = index as u32 + 1;
+                if blank::page_ink(&page, path_str, page_number)?.is_blank(threshold)
+                {
+                    blank.push(page_number);
+                }
+            }
+            Ok(blank)
+        }).await
+    }
+
+    ///Новый pdf без пустых страниц (см. `blank_pages`), метаданные сохраняются
+    ///если пустые все страницы, возвращается ошибка `AllPagesBlank`
+    pub async fn remove_blank_pages(&self, threshold: f32) -> Result<Vec<u8>, error::Error>
+    {
+        let blank = self.blank_pages(threshold).await?;
+        let pages_count = Self::get_pages_count(&self.path).await? as u32;
+        let pages = blank::kept_pages(pages_count, &blank, self.get_path())?;
+        self.extract_pages(&pages, true).await
+    }
+
+    ///То же что и `remove_blank_pages`, но результат сохраняется в файл
+    pub async fn remove_blank_pages_to_file<P: AsRef<Path>>(&self, threshold: f32, output: P) -> Result<(), error::Error>
+    {
+        let pdf = self.remove_blank_pages(threshold).await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    ///Классификация страниц по объектам: созданные в редакторе, сканы, сканы с текстовым слоем и смешанные,
+    ///с показателями каждой страницы и списком страниц, которым нужен OCR
+    pub async fn classify_pages(&self) -> Result<DocumentClassification, error::Error>
+    {
+        self.with_document(|document, _|
+        {
+            let mut pages = Vec::with_capacity(document.pages().len() as usize);
+            for (index, page) in document.pages().iter().enumerate()
+            {
+                pages.push(classify::classify_page(&page, index as u32 + 1)?);
+            }
+            Ok(DocumentClassification::new(pages))
+        }).await
+    }
+
+    ///Распознавание страницы через tesseract: страница рендерится с разрешением из `tesseract`,
+    ///области слов переводятся обратно в пункты pdf, новый pdf не создается
+    pub async fn ocr_page(&self, page_number: u32, tesseract: &Tesseract) -> Result<OcrPage, error::Error>
+    {
+        let dpi = tesseract.dpi();
+        let rendered = self.with_document(move |document, path_str|
+        {
+            let page = Self::get_page(document, page_number, path_str)?;
+            tesseract::render(&page, dpi, path_str, page_number)
+        }).await?;
+        tesseract.recognize(rendered, page_number).await
+    }
+
+    ///Распознавание нескольких страниц по очереди, результат можно выгрузить в hOCR или ALTO
+    pub async fn ocr_pages(&self, pages: &[u32], tesseract: &Tesseract) -> Result<OcrDocument, error::Error>
+    {
+        let mut document = OcrDocument { pages: Vec::with_capacity(pages.len()) };
+        for page_number in pages
+        {
+            document.pages.push(self.ocr_page(*page_number, tesseract).await?);
+        }
+        Ok(document)
+    }
+
+    ///Текст страницы из текстового слоя, а если странице нужен OCR (скан без текста, смешанная страница
+    ///или текст извлекается мусором) и передан `fallback`, то распознанный tesseract
+    pub async fn page_text(&self, page_number: u32, fallback: Option<&Tesseract>) -> Result<PageText, error::Error>
+    {
+        let Some(tesseract) = fallback else
+        {
+            let text = self.with_document(move |document, path_str| Ok(Self::get_page(document, page_number, path_str)?.text()?.all())).await?;
+            return Ok(PageText { page: page_number, text, source: TextSource::TextLayer, ocr: None });
+        };
+        let dpi = tesseract.dpi();
+        let source = self.with_document(move |document, path_str|
+        {
+            let page = Self::get_page(document, page_number, path_str)?;
+            if classify::classify_page(&page, page_number)?.needs_ocr
+            {
+                Ok(tesseract::PageSource::Scan(tesseract::render(&page, dpi, path_str, page_number)?))
+            }
+            else
+            {
+                Ok(tesseract::PageSource::Text(page.text()?.all()))
+            }
+        }).await?;
+        match source
+        {
+            tesseract::PageSource::Scan(rendered) =>
+            {
+                let ocr = tesseract.recognize(rendered, page_number).await?;
+                Ok(PageText { page: page_number, text: ocr.text(), source: TextSource::Ocr, ocr: Some(ocr) })
+            },
+            tesseract::PageSource::Text(text) => Ok(PageText { page: page_number, text, source: TextSource::TextLayer, ocr: None })
+        }
+    }
+
+    ///Сеанс редактирования страниц (поворот, перемещение, удаление, вставка пустых страниц)
+    ///рендер из сеанса учитывает изменения до сохранения, исходный файл не меняется
+    pub async fn edit(&self) -> Result<PageEditor, error::Error>
+    {
+        let pages_count = self.with_document(|document, _| Ok(document.pages().len() as u32)).await?;
+        Ok(editor::PageEditor::new(self.clone(), pages_count))
+    }
+
+    ///Объединение нескольких pdf (файлов или pdf в памяти) в один документ в порядке `sources`
+    ///у каждого документа можно выбрать страницы, при `with_outline` на первую страницу каждого документа добавляется закладка
+    pub async fn merge(sources: Vec<MergeSource>, with_outline: bool) -> Result<Vec<u8>, error::Error>
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::task::spawn_blocking(move ||
+        {
+            let result = Self::get_instance().and_then(|pdfium| merge::merge(&pdfium, &sources, with_outline));
+            let _ = sender.send(result);
+        });
+        match receiver.await
+        {
+            Ok(result) => result,
+            Err(_) => Err(error::Error::ChannelError("объединение pdf".to_owned()))
+        }
+    }
+
+    ///То же что и `merge`, но результат сохраняется в файл
+    pub async fn merge_to_file<P: AsRef<Path>>(sources: Vec<MergeSource>, with_outline: bool, output: P) -> Result<(), error::Error>
+    {
+        let pdf = Self::merge(sources, with_outline).await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    ///Новый pdf, в котором содержимое под областями `areas` удалено, а не просто закрыто:
+    ///текст и векторные объекты, задетые областью, удаляются, пиксели изображений закрашиваются,
+    ///сверху рисуются черные прямоугольники, в файл не попадают старые потоки содержимого
+    pub async fn redact(&self, areas: &[RedactionArea]) -> Result<Vec<u8>, error::Error>
+    {
+        let areas = areas.to_vec();
+        self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let document = pdfium.load_pdf_from_file(path, None)?;
+            for (page_number, rects) in redaction::by_page(&areas)
+            {
+                let mut page = Self::get_page(&document, page_number, path_str)?;
+                redaction::redact_page(&document, &mut page, &rects)?;
+            }
+            let bytes = document.save_to_bytes()?;
+            Ok(redaction::prune(&bytes)?)
+        }).await
+    }
+
+    ///То же что и `redact`, но результат сохраняется в файл
+    pub async fn redact_to_file<P: AsRef<Path>>(&self, areas: &[RedactionArea], output: P) -> Result<(), error::Error>
+    {
+        let pdf = self.redact(areas).await?;
+        tokio::fs::write(output, pdf).await?;
+        Ok(())
+    }
+
+    ///Рендер страницы так, как она будет выглядеть после `redact`, исходный файл не меняется
+    pub async fn redaction_preview(&self, areas: &[RedactionArea], page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, error::Error>
+    {
+        let rects: Vec<_> = areas.iter().filter(|a| a.page == page_number).map(|a| a.rect).collect();
+        let config = self.render_config();
+        let background = self.background;
+        let image = self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let document = pdfium.load_pdf_from_file(path, None)?;
+            let mut page = Self::get_page(&document, page_number, path_str)?;
+            redaction::redact_page(&document, &mut page, &rects)?;
+            Self::page_to_image(&page, &config, image_format, background, path_str, page_number)
+        }).await?;
+        self.gen_image(image, page_number, image_format).await
+    }
+
+    ///Поиск персональных данных в тексте всех страниц, области найденного можно сразу передать в `redact`
+    ///у сканов без текстового слоя ничего не найдется
+    pub async fn find_pii(&self, scanner: &PiiScanner) -> Result<Vec<PiiFinding>, error::Error>
+    {
+        let scanner = scanner.clone();
+        let config = Arc::clone(&self.config);
+        self.with_document(move |document, _|
+        {
+            let mut findings = Vec::new();
+            for (index, page) in document.pages().iter().enumerate()
+            {
+                findings.extend(pii::page_findings(&scanner, &page, index as u32 + 1, &config)?);
+            }
+            Ok(findings)
+        }).await
+    }
+
+    ///Визуальное сравнение с другой версией документа: одинаковые номера страниц рендерятся в одном масштабе
+    ///и сравниваются попиксельно, при разном количестве страниц лишние отмечаются как добавленные или удаленные
+    pub async fn compare<P: AsRef<Path>>(&self, other: P, options: VisualDiffOptions) -> Result<VisualDiff, error::Error>
+    {
+        let other = other.as_ref().to_owned();
+        self.with_pdfium(move |pdfium, path, path_str|
+        {
+            let first = pdfium.load_pdf_from_file(path, None)?;
+            let second = pdfium.load_pdf_from_file(&other, None)?;
+            visual_diff::compare(&first, &second, &options, path_str)
+        }).await
+    }
+
+    ///Сравнение текста с другой версией документа по словам, каждое изменение содержит области в обеих версиях
+    ///у сканов без текстового слоя изменений не найдется
+    pub async fn text_diff<P: AsRef<Path>>(&self, other: P) -> Result<TextDiff, error::Error>
+    {
+        let other = other.as_ref().to_owned();
+        self.with_pdfium(move |pdfium, path, _|
+        {
+            let first = pdfium.load_pdf_from_file(path, None)?;
+            let second = pdfium.load_pdf_from_file(&other, None)?;
+            Ok(text_diff::compare(&first, &second)?)
+        }).await
+    }
+
+    ///Рендер страницы с подсветкой изменений из `text_diff`, вызывается у сервиса той версии, которую нужно показать:
+    ///для первой версии красным подсвечивается удаленный текст, для второй зеленым добавленный
+    pub async fn text_diff_page(&self, diff: &TextDiff, version: DiffVersion, page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, error::Error>
+    {
+        let rects = diff.areas(version, page_number);
+        let color = match version
+        {
+            DiffVersion::First => text_diff::REMOVED_COLOR,
+            DiffVersion::Second => text_diff::INSERTED_COLOR
+        };
+        let config = self.render_config();
+        let background = self.background;
+        let image = self.with_document(move |document, path_str|
+        {
+            let page = Self::get_page(document, page_number, path_str)?;
+            let mut pixels = Vec::with_capacity(rects.len());
+            for rect in &rects
+            {
+                pixels.push(PixelRect::from_page_rect(&page, rect, &config)?);
+            }
+            let image = Self::page_to_image(&page, &config, image_format, background, path_str, page_number)?;
+            Ok(text_diff::highlight(image, &pixels, color))
+        }).await?;
+        self.gen_image(image, page_number, image_format).await
+    }
+
+    ///Перцептивная подпись документа (dHash и pHash каждой страницы) для поиска дубликатов,
+    ///в том числе пересканированных или пережатых, побайтное совпадение не требуется
+    pub async fn signature(&self) -> Result<DocumentSignature, error::Error>
+    {
+        self.with_document(phash::signature).await
+    }
+
+    ///Схожесть с другим документом от 0 до 1 по перцептивным подписям
+    pub async fn duplicate_similarity<P: AsRef<Path>>(&self, other: P) -> Result<f32, error::Error>
+    {
+        let other = PdfService::new(other, 0, 0).signature().await?;
+        Ok(self.signature().await?.similarity(&other))
+    }
+
+    ///Поиск похожих документов среди pdf в директории (без вложенных), возвращаются пары со схожестью не ниже `threshold`,
+    ///файлы, которые не удалось открыть, пропускаются с записью в лог
+    pub async fn find_duplicates<P: AsRef<Path>>(dir: P, threshold: f32) -> Result<Vec<DuplicatePair>, error::Error>
+    {
+        let dir = dir.as_ref().to_owned();
+        let dir_str = dir.display().to_string();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::task::spawn_blocking(move ||
+        {
+            let result = Self::get_instance().and_then(|pdfium|
+            {
+                let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)?
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf")))
+                    .collect();
+                files.sort();
+                let mut signatures = Vec::with_capacity(files.len());
+                for file in files
+                {
+                    let path_str = file.display().to_string();
+                    let signature = pdfium.load_pdf_from_file(&file, None)
+                        .map_err(error::Error::from)
+                        .and_then(|document| phash::signature(&document, &path_str));
+                    match signature
+                    {
+                        Ok(signature) => signatures.push((file, signature)),
+                        Err(e) => error!("Файл {} пропущен при поиске дубликатов: {}", path_str, e)
+                    }
+                }
+                Ok(phash::duplicates(&signatures, threshold))
+            });
+            let _ = sender.send(result);
+        });
+        match receiver.await
+        {
+            Ok(result) => result,
+            Err(_) => Err(error::Error::ChannelError(dir_str))
+        }
+    }
+
     ///Извлечение изображения из pdf и выдача в формате строки base64
     pub async fn convert_page(&self, page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, error::Error> 
+    {
+        let image = self.render_page(page_number, image_format).await?;
+        self.gen_image(image, page_number, image_format).await
+    }
+
+    ///Извлечение изображения из pdf с ограничением размера результата в байтах
+    ///подбирается качество, а если его не хватает - уменьшаются размеры изображения
+    pub async fn convert_page_with_budget(&self, page_number: u32, image_format: PageImageFormat, budget: ByteBudget) -> Result<BudgetImage, error::Error> 
+    {
+        let image = self.render_page(page_number, image_format).await?;
+        self.gen_image_with_budget(image, page_number, image_format, budget).await
+    }
+
+    ///Рендер страницы в изображение с набором каналов, подходящим для выбранного формата
+    async fn render_page(&self, page_number: u32, image_format: PageImageFormat) -> Result<DynamicImage, error::Error> 
     {
         let (sender, receiver) = tokio::sync::oneshot::channel();
         let config = Arc::clone(&self.config);
         let path = self.path.clone();
         let path_str = self.get_path().to_owned();
+        let background = self.background;
         tokio::task::spawn_blocking(move ||
         {
             let pdfium = Self::get_instance();
@@ -80,46 +706,28 @@ impl PdfService
             }
             let page_index = (page_number -1) as usize;
             let page = document.pages().iter().nth(page_index).unwrap();
-            let current_page =  page.render_with_config(&config);
-            if current_page.is_err()
-            {
-                let _ = sender.send(Err(error::Error::PdfiumError(current_page.err().unwrap())));
-                return;
-            }
-            let current_page = current_page.unwrap();
-            let bytes = current_page.as_rgba_bytes();
-            let width = current_page.width() as u32;
-            let height = current_page.height() as u32;
-            let image = match current_page.format().unwrap_or_default() 
-            {
-                PdfBitmapFormat::Gray => 
-                {
-                    GrayImage::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8)
-                }
-                _ => 
-                {
-                    match &image_format
-                    {
-                        PageImageFormat::Jpeg =>  RgbImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
-                        _ => RgbaImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
-                    }
-                }
-            };
-            let _ = sender.send(image.ok_or(Error::ExtractDynamicImageError(path_str, page_number)));
+            let image = Self::page_to_image(&page, &config, image_format, background, &path_str, page_number);
+            let _ = sender.send(image);
         });
 
-        if let Ok(page) = receiver.await
+        match receiver.await
         {
-            let image = page?;
-            let png = self.gen_image(image, page_number, image_format).await?;
-            return Ok(png);
-        }
-        else 
-        {
-            return Err(error::Error::ChannelError(self.get_path().to_owned()));
+            Ok(page) => page,
+            Err(_) => Err(error::Error::ChannelError(self.get_path().to_owned()))
         }
     }
 
+    ///Рендер загруженной страницы в изображение с набором каналов, подходящим для выбранного формата
+    pub(crate) fn page_to_image(page: &PdfPage, config: &PdfRenderConfig, image_format: PageImageFormat, background: Rgb<u8>, path: &str, page_number: u32) -> Result<DynamicImage, error::Error>
+    {
+        let current_page = page.render_with_config(config)?;
+        let bytes = current_page.as_rgba_bytes();
+        let width = current_page.width() as u32;
+        let height = current_page.height() as u32;
+        let image = bitmap::bitmap_to_image(bytes, width, height, current_page.format().unwrap_or_default(), image_format, background);
+        image.ok_or(Error::ExtractDynamicImageError(path.to_owned(), page_number))
+    }
+
     pub async fn convert_pages_test(&self, pages: &[u32], image_format: PageImageFormat) -> Result<Vec<Vec<u8>>, error::Error> 
     {
         
@@ -128,6 +736,7 @@ impl PdfService
         let path = self.path.clone();
         let path_str = self.get_path().to_owned();
         let pages_vec = pages.to_vec();
+        let background = self.background;
         let current = Handle::current();
         if pages.is_empty()
         {
@@ -175,21 +784,7 @@ impl PdfService
                     let bytes = current_page_bitmap.as_rgba_bytes();
                     let width = current_page_bitmap.width() as u32;
                     let height = current_page_bitmap.height() as u32;
-                    let image = match current_page_bitmap.format().unwrap_or_default() 
-                    {
-                        PdfBitmapFormat::Gray => 
-                        {
-                            GrayImage::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8)
-                        }
-                        _ => 
-                        {
-                            match &image_format
-                            {
-                                PageImageFormat::Jpeg =>  RgbImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
-                                _ => RgbaImage::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
-                            }
-                        }
-                    };
+                    let image = bitmap::bitmap_to_image(bytes, width, height, current_page_bitmap.format().unwrap_or_default(), image_format, background);
                     if let Some(image) = image
                     {
                         let _ = sender.send(Ok((image, p))).await;
@@ -236,6 +831,82 @@ impl PdfService
         }
         ordered
     }
+    ///Рендер выбранных страниц в один многостраничный tiff с точным разрешением `options.dpi`
+    pub async fn export_tiff(&self, pages: &[u32], options: TiffOptions) -> Result<Vec<u8>, error::Error>
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let path = self.path.clone();
+        let path_str = self.get_path().to_owned();
+        let pages_vec = pages.to_vec();
+        let background = self.background;
+        let watermark = self.watermark.clone();
+        if pages.is_empty()
+        {
+            return Err(error::Error::NonePageSelect(path_str));
+        }
+        tokio::task::spawn_blocking(move ||
+        {
+            let pdfium = Self::get_instance();
+            if pdfium.is_err()
+            {
+                let _ = sender.send(Err(pdfium.err().unwrap()));
+                return;
+            }
+            let pdfium = pdfium.unwrap();
+            let document = pdfium.load_pdf_from_file(&path, None);
+            if document.is_err()
+            {
+                let _ = sender.send(Err(error::Error::PdfiumError(document.err().unwrap())));
+                return;
+            }
+            let document = document.unwrap();
+            let pages_count = document.pages().len() as u32;
+            if let Some(wrong) = pages_vec.iter().find(|p| **p < 1 || **p > pages_count)
+            {
+                let _ = sender.send(Err(error::Error::WrongPageSelect(path_str, pages_count, *wrong)));
+                return;
+            }
+            //без поворота альбомных страниц, размер в пикселях должен точно соответствовать dpi
+            let config = PdfRenderConfig::new().scale_page_by_factor(options.dpi.max(1) as f32 / 72.0);
+            let mut buffer = Cursor::new(Vec::new());
+            let result = (|| -> Result<(), error::Error>
+            {
+                let mut encoder = tiff::encoder::TiffEncoder::new(&mut buffer)?;
+                let total = pages_vec.len() as u16;
+                for (i, p) in pages_vec.iter().enumerate()
+                {
+                    let page = document.pages().get((p - 1) as u16)?;
+                    let bitmap = page.render_with_config(&config)?;
+                    let bytes = bitmap.as_rgba_bytes();
+                    let image = bitmap::bitmap_to_image(bytes, bitmap.width() as u32, bitmap.height() as u32, bitmap.format().unwrap_or_default(), PageImageFormat::Png, background)
+                        .ok_or_else(|| Error::ExtractDynamicImageError(path_str.clone(), *p))?;
+                    let image = match &watermark
+                    {
+                        Some(watermark) => watermark.apply(image),
+                        None => image
+                    };
+                    let image = bitmap::remove_alpha(image, background);
+                    tiff_export::write_page(&mut encoder, &image, &options, i as u16, total)?;
+                }
+                Ok(())
+            })();
+            let _ = sender.send(result.map(|_| buffer.into_inner()));
+        });
+        match receiver.await
+        {
+            Ok(tiff) => tiff,
+            Err(_) => Err(error::Error::ChannelError(self.get_path().to_owned()))
+        }
+    }
+
+    ///То же что и `export_tiff`, но результат сохраняется в файл
+    pub async fn export_tiff_to_file<P: AsRef<Path>>(&self, pages: &[u32], options: TiffOptions, output: P) -> Result<(), error::Error>
+    {
+        let tiff = self.export_tiff(pages, options).await?;
+        tokio::fs::write(output, tiff).await?;
+        Ok(())
+    }
+
      ///Извлечение изображения из pdf
      pub async fn get_pages_count<P: AsRef<Path>>(path: P) -> Result<u16, error::Error> 
      {
@@ -272,109 +943,37 @@ impl PdfService
         }
     }
 
-    // Извлечение страницы из pdf и преобразование ее в формат rgba8 pdf и выдача страницы в виде массива байт
-    async fn gen_image(&self, dyn_image: DynamicImage, page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, error::Error>
+    // Приведение страницы к набору каналов выбранного формата и выдача страницы в виде массива байт
+    pub(crate) async fn gen_image(&self, dyn_image: DynamicImage, page_number: u32, image_format: PageImageFormat) -> Result<Vec<u8>, error::Error>
     {
         let (sender, receiver) = tokio::sync::oneshot::channel();
-        let current = Handle::current();
         let path = self.get_path().to_owned();
+        let background = self.background;
+        let settings = self.encoder_settings;
+        let color_mode = self.color_mode;
+        let watermark = self.watermark.clone();
         tokio::task::spawn_blocking(move || 
         {
-            current.block_on(
-            async move
+            let dyn_image = match watermark
             {
-                //бферизацию тут не используем, так как с io не работаем
-                let mut buffer = std::io::BufWriter::new(Cursor::new(vec![]));
-                //Изначально изображение в rgba поэтому для rgba берем as_rgba8 для остальных, например jpeg - dyn_image.to_rgb8()
-                let converted = match image_format
-                {
-                    PageImageFormat::Png =>
-                    {
-                        if let Some(rgba8) = dyn_image.as_rgba8()
-                        {
-                            let res = rgba8.write_to(&mut buffer, ImageFormat::Png);
-                            if res.is_err()
-                            {
-                                logger::error!("{}", res.as_ref().err().unwrap());
-                                Err(error::Error::ImageConvertingError(page_number as u32, path.clone(), "png".to_owned()))
-                            }
-                            else 
-                            {
-                                Ok(())
-                            }
-                        }
-                        else 
-                        {
-                            logger::error!("Изображение не может быть представлено как rgba8, необходима конвертация");
-                            Err(error::Error::Rgba8ConvertError(path.clone(), page_number as u32))
-                        }
-                    }, 
-                    PageImageFormat::Jpeg => 
-                    {
-                        if let Some(rgb) = dyn_image.as_rgb8()
-                        {
-                            let jpeg_quality = 90;
-                            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, jpeg_quality);
-                            let res = rgb.write_with_encoder(encoder);
-                            if res.is_err()
-                            {
-                                logger::error!("{}", res.as_ref().err().unwrap());
-                                Err(error::Error::ImageConvertingError(page_number as u32, path.clone(), "jpeg".to_owned()))
-                            }
-                            else 
-                            {
-                                Ok(())
-                            }
-                        }
-                        else 
-                        {
-                            logger::error!("Изображение не может быть представлено как rgb8, необходима конвертация");
-                            Err(error::Error::Rgba8ConvertError(path.clone(), page_number as u32))
-                        }
-                        
-                    },
-                    PageImageFormat::Webp => 
-                    {
-                        if let Some(rgba) = dyn_image.as_rgba8()
-                        {
-                            let webp_encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buffer);
-                            let res = rgba.write_with_encoder(webp_encoder);
-                            if res.is_err()
-                            {
-                                logger::error!("{}", res.as_ref().err().unwrap());
-                                Err(error::Error::ImageConvertingError(page_number as u32, path.clone(), "webp".to_owned()))
-                            }
-                            else 
-                            {
-                                Ok(())
-                            }
-                        }
-                        else 
-                        {
-                            logger::error!("Изображение не может быть представлено как rgb8, необходима конвертация");
-                            Err(error::Error::Rgba8ConvertError(path.clone(), page_number as u32))
-                        }
-                    }
-                };
-
-                if let Ok(_) = converted
+                Some(watermark) => watermark.apply(dyn_image),
+                None => dyn_image
+            };
+            let image = dither::apply_color_mode(dyn_image, color_mode, background);
+            //jpeg не поддерживает альфа канал, поэтому прозрачность накладывается на фон, остальные форматы принимают изображение как есть
+            let image = bitmap::fit_to_format(image, image_format, background);
+            match encoder::encode_page(&image, image_format, &settings, color_mode)
+            {
+                Ok(buff) =>
                 {
-                    let flash = buffer.into_inner();
-                    if let Ok(f) = flash
-                    {
-                        let buff = f.into_inner();
-                        let _ = sender.send(Ok(buff));
-                    }
-                    else 
-                    {
-                        let _ = sender.send(Err(error::Error::WriteBufferError(path.clone(), page_number as u32)));
-                    }
-                }
-                else 
+                    let _ = sender.send(Ok(buff));
+                },
+                Err(e) =>
                 {
-                    let _ = sender.send(Err(converted.err().unwrap()));
+                    logger::error!("{}", e);
+                    let _ = sender.send(Err(error::Error::ImageConvertingError(page_number, path, image_format.name().to_owned())));
                 }
-            })
+            }
         });
         if let Ok(image) = receiver.await
         {
@@ -385,6 +984,48 @@ impl PdfService
             return Err(error::Error::ChannelError(self.get_path().to_owned()));
         }
     }
+
+    // Кодирование страницы с подбором качества и размеров под ограничение в байтах
+    async fn gen_image_with_budget(&self, dyn_image: DynamicImage, page_number: u32, image_format: PageImageFormat, budget: ByteBudget) -> Result<BudgetImage, error::Error>
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let path = self.get_path().to_owned();
+        let background = self.background;
+        let settings = self.encoder_settings;
+        let color_mode = self.color_mode;
+        let watermark = self.watermark.clone();
+        tokio::task::spawn_blocking(move || 
+        {
+            let dyn_image = match watermark
+            {
+                Some(watermark) => watermark.apply(dyn_image),
+                None => dyn_image
+            };
+            let image = dither::apply_color_mode(dyn_image, color_mode, background);
+            let image = bitmap::fit_to_format(image, image_format, background);
+            match encoder::encode_within_budget(&image, image_format, &settings, color_mode, &budget)
+            {
+                Ok(Some(budget_image)) =>
+                {
+                    let _ = sender.send(Ok(budget_image));
+                },
+                Ok(None) =>
+                {
+                    let _ = sender.send(Err(error::Error::ByteBudgetExceeded(page_number, path, budget.max_bytes)));
+                },
+                Err(e) =>
+                {
+                    logger::error!("{}", e);
+                    let _ = sender.send(Err(error::Error::ImageConvertingError(page_number, path, image_format.name().to_owned())));
+                }
+            }
+        });
+        match receiver.await
+        {
+            Ok(image) => image,
+            Err(_) => Err(error::Error::ChannelError(self.get_path().to_owned()))
+        }
+    }
 }
 
 
@@ -0,0 +1,325 @@
+use lopdf::{Dictionary, Document, Object};
+use serde::Serialize;
+
+///Электронная подпись pdf
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Signature
+{
+    pub index: usize,
+    ///Полное имя поля подписи
+    pub field_name: Option<String>,
+    ///Имя подписанта из словаря подписи, а если его нет - CN сертификата подписанта
+    pub signer: Option<String>,
+    ///Время подписания в формате pdf, например `D:20240131120000+03'00'`
+    pub signing_time: Option<String>,
+    pub reason: Option<String>,
+    pub location: Option<String>,
+    ///Формат подписи, например `adbe.pkcs7.detached` или `ETSI.CAdES.detached`
+    pub sub_filter: Option<String>,
+    ///Подписанные участки файла: смещение и длина
+    pub byte_ranges: Vec<(usize, usize)>,
+    ///Подпись покрывает весь файл, после подписания в файл ничего не дописывалось
+    pub covers_whole_file: bool,
+    ///Количество байт, дописанных после подписанной части (инкрементальные обновления)
+    pub appended_bytes: usize,
+}
+impl Signature
+{
+    ///Документ изменялся после этой подписи
+    ///для всех подписей, кроме последней, это ожидаемо, если изменения - это следующие подписи
+    pub fn is_modified_after_signing(&self) -> bool
+    {
+        !self.covers_whole_file
+    }
+}
+
+///Максимальная глубина дерева полей, защита от зацикленных ссылок
+const MAX_FIELD_DEPTH: usize = 32;
+
+///Подписи из полей формы документа, `file_len` - размер файла в байтах
+pub(crate) fn signatures(document: &Document, file_len: usize) -> Vec<Signature>
+{
+    let mut fields = Vec::new();
+    let root_fields = document.catalog().ok()
+        .and_then(|c| deref(document, c.get(b"AcroForm").ok()?)?.as_dict().ok())
+        .and_then(|f| deref(document, f.get(b"Fields").ok()?)?.as_array().ok());
+    if let Some(root_fields) = root_fields
+    {
+        for field in root_fields
+        {
+            collect_signature_fields(document, field, None, 0, &mut fields);
+        }
+    }
+    fields.into_iter().enumerate().map(|(index, (field_name, value))|
+    {
+        let byte_ranges = byte_ranges(value);
+        let signed_end = byte_ranges.iter().map(|(offset, len)| offset + len).max().unwrap_or(0);
+        let covers_whole_file = byte_ranges.first().map(|r| r.0) == Some(0) && signed_end == file_len;
+        let contents = value.get(b"Contents").ok().and_then(|c| c.as_str().ok());
+        Signature
+        {
+            index,
+            field_name,
+            signer: text(document, value, b"Name").or_else(|| contents.and_then(cms_signer_name)),
+            signing_time: text(document, value, b"M"),
+            reason: text(document, value, b"Reason"),
+            location: text(document, value, b"Location"),
+            sub_filter: value.get(b"SubFilter").ok().and_then(|s| s.as_name_str().ok()).map(|s| s.to_owned()),
+            byte_ranges,
+            covers_whole_file,
+            appended_bytes: file_len.saturating_sub(signed_end),
+        }
+    }).collect()
+}
+
+fn collect_signature_fields<'a>(document: &'a Document, field: &'a Object, parent: Option<&str>, depth: usize, out: &mut Vec<(Option<String>, &'a Dictionary)>)
+{
+    if depth > MAX_FIELD_DEPTH
+    {
+        return;
+    }
+    let Some(field) = deref(document, field).and_then(|f| f.as_dict().ok()) else { return };
+    let name = match (parent, text(document, field, b"T"))
+    {
+        (Some(parent), Some(name)) => Some(format!("{}.{}", parent, name)),
+        (None, Some(name)) => Some(name),
+        (parent, None) => parent.map(|p| p.to_owned())
+    };
+    let is_signature = field.get(b"FT").ok().and_then(|t| t.as_name().ok()) == Some(b"Sig".as_slice());
+    if let Some(value) = field.get(b"V").ok().and_then(|v| deref(document, v)).and_then(|v| v.as_dict().ok())
+        && is_signature && value.has(b"ByteRange")
+    {
+        out.push((name.clone(), value));
+    }
+    if let Some(kids) = field.get(b"Kids").ok().and_then(|k| deref(document, k)).and_then(|k| k.as_array().ok())
+    {
+        for kid in kids
+        {
+            collect_signature_fields(document, kid, name.as_deref(), depth + 1, out);
+        }
+    }
+}
+
+fn byte_ranges(value: &Dictionary) -> Vec<(usize, usize)>
+{
+    let Some(numbers) = value.get(b"ByteRange").ok().and_then(|r| r.as_array().ok()) else { return Vec::new() };
+    numbers.chunks_exact(2)
+        .filter_map(|pair| Some((pair[0].as_i64().ok()?.max(0) as usize, pair[1].as_i64().ok()?.max(0) as usize)))
+        .collect()
+}
+
+fn deref<'a>(document: &'a Document, object: &'a Object) -> Option<&'a Object>
+{
+    document.dereference(object).ok().map(|(_, o)| o)
+}
+
+fn text(document: &Document, dict: &Dictionary, key: &[u8]) -> Option<String>
+{
+    lopdf::decode_text_string(deref(document, dict.get(key).ok()?)?).ok().filter(|s| !s.is_empty())
+}
+
+///Элемент DER: тег, содержимое и оставшиеся после элемента байты
+fn der(data: &[u8]) -> Option<(u8, &[u8], &[u8])>
+{
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (len, data) = if first < 0x80
+    {
+        (first as usize, data)
+    }
+    else
+    {
+        //неопределенная длина (0x80) бывает только в BER, такие подписи не разбираются
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || data.len() < count
+        {
+            return None;
+        }
+        (data[..count].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize), &data[count..])
+    };
+    if data.len() < len
+    {
+        return None;
+    }
+    Some((tag, &data[..len], &data[len..]))
+}
+
+///Элементы последовательности или набора
+fn der_items(mut data: &[u8]) -> Vec<(u8, &[u8])>
+{
+    let mut items = Vec::new();
+    while let Some((tag, content, rest)) = der(data)
+    {
+        items.push((tag, content));
+        data = rest;
+    }
+    items
+}
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+///OID commonName 2.5.4.3
+const OID_COMMON_NAME: [u8; 3] = [0x55, 0x04, 0x03];
+
+///CN сертификата подписанта из CMS (PKCS#7) подписи
+///сертификат выбирается по серийному номеру из SignerInfo, если не нашелся - берется первый
+fn cms_signer_name(contents: &[u8]) -> Option<String>
+{
+    //ContentInfo { contentType, [0] SignedData }
+    let (_, content_info, _) = der(contents)?;
+    let explicit = der_items(content_info).into_iter().find(|(tag, _)| *tag == TAG_CONTEXT_0)?.1;
+    let (_, signed_data, _) = der(explicit)?;
+    let items = der_items(signed_data);
+    let certificates: Vec<&[u8]> = items.iter().find(|(tag, _)| *tag == TAG_CONTEXT_0)
+        .map(|(_, certs)| der_items(certs).into_iter().filter(|(tag, _)| *tag == TAG_SEQUENCE).map(|(_, c)| c).collect())
+        .unwrap_or_default();
+    let signer_serial = items.iter().rev().find(|(tag, _)| *tag == TAG_SET)
+        .and_then(|(_, infos)| der_items(infos).into_iter().next())
+        .and_then(|(_, info)| der_items(info).into_iter().nth(1))
+        .filter(|(tag, _)| *tag == TAG_SEQUENCE)
+        .and_then(|(_, sid)| der_items(sid).into_iter().nth(1))
+        .map(|(_, serial)| serial);
+    let parsed: Vec<(&[u8], Option<String>)> = certificates.into_iter().filter_map(certificate_serial_and_cn).collect();
+    parsed.iter().find(|(serial, _)| Some(*serial) == signer_serial)
+        .or(parsed.first())
+        .and_then(|(_, cn)| cn.clone())
+}
+
+///Серийный номер и CN владельца сертификата
+fn certificate_serial_and_cn(certificate: &[u8]) -> Option<(&[u8], Option<String>)>
+{
+    let (_, tbs) = der_items(certificate).into_iter().next()?;
+    let mut fields = der_items(tbs).into_iter().peekable();
+    //необязательная версия [0]
+    if fields.peek().map(|(tag, _)| *tag) == Some(TAG_CONTEXT_0)
+    {
+        fields.next();
+    }
+    let (tag, serial) = fields.next()?;
+    if tag != TAG_INTEGER
+    {
+        return None;
+    }
+    //алгоритм подписи, издатель, срок действия, владелец
+    let (_, subject) = fields.nth(3)?;
+    Some((serial, common_name(subject)))
+}
+
+///CN из Name (SEQUENCE OF SET OF AttributeTypeAndValue)
+fn common_name(name: &[u8]) -> Option<String>
+{
+    for (_, rdn) in der_items(name)
+    {
+        for (_, attribute) in der_items(rdn)
+        {
+            let items = der_items(attribute);
+            if let [(_, oid), (tag, value)] = items.as_slice() && *oid == OID_COMMON_NAME
+            {
+                return der_string(*tag, value);
+            }
+        }
+    }
+    None
+}
+
+fn der_string(tag: u8, value: &[u8]) -> Option<String>
+{
+    match tag
+    {
+        //BMPString
+        0x1e => String::from_utf16(&value.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<u16>>()).ok(),
+        //UTF8String, PrintableString, T61String, IA5String
+        0x0c | 0x13 | 0x14 | 0x16 => Some(String::from_utf8_lossy(value).into_owned()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use lopdf::{dictionary, Document, Object, StringFormat};
+    use super::{cms_signer_name, signatures};
+
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8>
+    {
+        let mut out = vec![tag];
+        if content.len() < 0x80
+        {
+            out.push(content.len() as u8);
+        }
+        else
+        {
+            out.extend_from_slice(&[0x82, (content.len() >> 8) as u8, content.len() as u8]);
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn name(cn: &str) -> Vec<u8>
+    {
+        let attribute = [tlv(0x06, &[0x55, 0x04, 0x03]), tlv(0x0c, cn.as_bytes())].concat();
+        tlv(0x30, &tlv(0x31, &tlv(0x30, &attribute)))
+    }
+
+    fn certificate(serial: u8, issuer: &str, subject: &str) -> Vec<u8>
+    {
+        let tbs = [tlv(0xa0, &tlv(0x02, &[2])), tlv(0x02, &[serial]), tlv(0x30, &[]), name(issuer), tlv(0x30, &[]), name(subject)].concat();
+        tlv(0x30, &[tlv(0x30, &tbs), tlv(0x30, &[]), tlv(0x03, &[0])].concat())
+    }
+
+    ///SignedData с двумя сертификатами, подписант - второй
+    fn cms() -> Vec<u8>
+    {
+        let certificates = tlv(0xa0, &[certificate(1, "Root CA", "Удостоверяющий центр"), certificate(7, "Удостоверяющий центр", "Иванов Иван Иванович")].concat());
+        let sid = tlv(0x30, &[name("Удостоверяющий центр"), tlv(0x02, &[7])].concat());
+        let signer_info = tlv(0x30, &[tlv(0x02, &[1]), sid].concat());
+        let signed_data = tlv(0x30, &[tlv(0x02, &[1]), tlv(0x31, &[]), tlv(0x30, &[]), certificates, tlv(0x31, &signer_info)].concat());
+        let mut contents = tlv(0x30, &[tlv(0x06, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02]), tlv(0xa0, &signed_data)].concat());
+        //место под подпись в pdf дополняется нулями
+        contents.resize(contents.len() + 64, 0);
+        contents
+    }
+
+    #[test]
+    fn test_cms_signer_name()
+    {
+        assert_eq!(cms_signer_name(&cms()).as_deref(), Some("Иванов Иван Иванович"));
+        assert_eq!(cms_signer_name(&[0x30, 0x80, 0x00]), None);
+    }
+
+    #[test]
+    fn test_signature_fields()
+    {
+        let mut document = Document::with_version("1.7");
+        let value = document.add_object(dictionary!
+        {
+            "Type" => "Sig",
+            "SubFilter" => "adbe.pkcs7.detached",
+            "M" => Object::string_literal("D:20240131120000+03'00'"),
+            "Reason" => lopdf::text_string("Согласовано"),
+            "ByteRange" => vec![0.into(), 100.into(), 200.into(), 300.into()],
+            "Contents" => Object::String(cms(), StringFormat::Hexadecimal),
+        });
+        let field = document.add_object(dictionary! { "FT" => "Sig", "T" => Object::string_literal("Signature1"), "V" => value });
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "AcroForm" => dictionary! { "Fields" => vec![field.into()] } });
+        document.trailer.set("Root", catalog);
+
+        let whole = signatures(&document, 500);
+        assert_eq!(whole.len(), 1);
+        let signature = &whole[0];
+        assert_eq!(signature.field_name.as_deref(), Some("Signature1"));
+        assert_eq!(signature.signer.as_deref(), Some("Иванов Иван Иванович"));
+        assert_eq!(signature.reason.as_deref(), Some("Согласовано"));
+        assert_eq!(signature.sub_filter.as_deref(), Some("adbe.pkcs7.detached"));
+        assert_eq!(signature.byte_ranges, vec![(0, 100), (200, 300)]);
+        assert!(signature.covers_whole_file);
+        assert!(!signature.is_modified_after_signing());
+
+        //после подписания дописано инкрементальное обновление
+        let updated = signatures(&document, 740);
+        assert!(updated[0].is_modified_after_signing());
+        assert_eq!(updated[0].appended_bytes, 240);
+    }
+}
@@ -0,0 +1,466 @@
+use std::{io::Cursor, path::{Path, PathBuf}, time::Duration};
+use image::ImageFormat;
+use pdfium_render::prelude::{PdfPage, PdfRenderConfig};
+use serde::Serialize;
+use tokio::process::Command;
+use crate::{bitmap, error::Error, geometry::{PageRect, PixelRect}, ocrmypdf, PageImageFormat};
+
+///Разрешение рендера для распознавания по умолчанию
+const DEFAULT_DPI: f32 = 300.0;
+
+///Распознанное слово
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OcrWord
+{
+    pub text: String,
+    ///Уверенность tesseract от 0 до 1
+    pub confidence: f32,
+    ///Область слова в пунктах pdf
+    pub rect: PageRect,
+    ///Область слова в пикселях распознанного изображения
+    pub pixels: PixelRect,
+    pub block: u32,
+    pub paragraph: u32,
+    pub line: u32,
+}
+
+///Результат распознавания страницы
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OcrPage
+{
+    pub page: u32,
+    ///Размер распознанного изображения в пикселях
+    pub image_width: u32,
+    pub image_height: u32,
+    pub dpi: f32,
+    pub words: Vec<OcrWord>,
+}
+impl OcrPage
+{
+    ///Текст страницы: слова строки через пробел, строки через перевод строки, блоки через пустую строку
+    pub fn text(&self) -> String
+    {
+        let mut text = String::new();
+        let lines = self.lines();
+        for (index, line) in lines.iter().enumerate()
+        {
+            if index > 0
+            {
+                text.push_str(if line[0].block != lines[index - 1][0].block { "\n\n" } else { "\n" });
+            }
+            text.push_str(&line.iter().map(|w| w.text.as_str()).collect::<Vec<_>>().join(" "));
+        }
+        text
+    }
+    ///Средняя уверенность по словам
+    pub fn confidence(&self) -> f32
+    {
+        if self.words.is_empty()
+        {
+            return 0.0;
+        }
+        self.words.iter().map(|w| w.confidence).sum::<f32>() / self.words.len() as f32
+    }
+    ///Слова, сгруппированные по строкам
+    fn lines(&self) -> Vec<&[OcrWord]>
+    {
+        self.words.chunk_by(|a, b| (a.block, a.paragraph, a.line) == (b.block, b.paragraph, b.line)).collect()
+    }
+}
+
+///Результат распознавания нескольких страниц с выгрузкой в hOCR и ALTO,
+///координаты в выгрузках - пиксели распознанных изображений
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OcrDocument
+{
+    pub pages: Vec<OcrPage>,
+}
+impl OcrDocument
+{
+    pub fn text(&self) -> String
+    {
+        self.pages.iter().map(|p| p.text()).collect::<Vec<_>>().join("\n\u{c}\n")
+    }
+    ///hOCR 1.2 (XHTML)
+    pub fn to_hocr(&self) -> String
+    {
+        let mut html = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\">\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\">\n<head>\n<meta http-equiv=\"Content-Type\" content=\"text/html;charset=utf-8\"/>\n\
+            <meta name=\"ocr-system\" content=\"tesseract\"/>\n\
+            <meta name=\"ocr-capabilities\" content=\"ocr_page ocr_carea ocr_par ocr_line ocrx_word\"/>\n</head>\n<body>\n");
+        for page in &self.pages
+        {
+            html.push_str(&format!("<div class=\"ocr_page\" id=\"page_{0}\" title=\"bbox 0 0 {1} {2}; ppageno {3}; scan_res {4} {4}\">\n",
+                page.page, page.image_width, page.image_height, page.page - 1, page.dpi.round() as u32));
+            let lines = page.lines();
+            for (index, line) in lines.iter().enumerate()
+            {
+                let first = &line[0];
+                let starts_block = index == 0 || (lines[index - 1][0].block, lines[index - 1][0].paragraph) != (first.block, first.paragraph);
+                if starts_block && index > 0
+                {
+                    html.push_str("</p>\n</div>\n");
+                }
+                if starts_block
+                {
+                    let block = union(page.words.iter().filter(|w| w.block == first.block && w.paragraph == first.paragraph).map(|w| w.pixels));
+                    html.push_str(&format!("<div class=\"ocr_carea\" id=\"block_{0}_{1}_{2}\" title=\"{3}\">\n<p class=\"ocr_par\" id=\"par_{0}_{1}_{2}\" title=\"{3}\">\n",
+                        page.page, first.block, first.paragraph, bbox(&block)));
+                }
+                html.push_str(&format!("<span class=\"ocr_line\" id=\"line_{}_{}\" title=\"{}\">", page.page, index + 1, bbox(&union(line.iter().map(|w| w.pixels)))));
+                for (word_index, word) in line.iter().enumerate()
+                {
+                    if word_index > 0
+                    {
+                        html.push(' ');
+                    }
+                    html.push_str(&format!("<span class=\"ocrx_word\" id=\"word_{}_{}_{}\" title=\"{}; x_wconf {}\">{}</span>",
+                        page.page, index + 1, word_index + 1, bbox(&word.pixels), (word.confidence * 100.0).round() as u32, escape(&word.text)));
+                }
+                html.push_str("</span>\n");
+            }
+            if !page.words.is_empty()
+            {
+                html.push_str("</p>\n</div>\n");
+            }
+            html.push_str("</div>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+    ///ALTO 4 (XML)
+    pub fn to_alto(&self) -> String
+    {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <alto xmlns=\"http://www.loc.gov/standards/alto/ns-v4#\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+            xsi:schemaLocation=\"http://www.loc.gov/standards/alto/ns-v4# http://www.loc.gov/alto/v4/alto-4-2.xsd\">\n\
+            <Description>\n<MeasurementUnit>pixel</MeasurementUnit>\n<OCRProcessing ID=\"OCR_0\">\n<ocrProcessingStep>\n<processingSoftware>\n\
+            <softwareName>tesseract</softwareName>\n</processingSoftware>\n</ocrProcessingStep>\n</OCRProcessing>\n</Description>\n<Layout>\n");
+        for page in &self.pages
+        {
+            xml.push_str(&format!("<Page ID=\"page_{0}\" PHYSICAL_IMG_NR=\"{0}\" WIDTH=\"{1}\" HEIGHT=\"{2}\">\n<PrintSpace HPOS=\"0\" VPOS=\"0\" WIDTH=\"{1}\" HEIGHT=\"{2}\">\n",
+                page.page, page.image_width, page.image_height));
+            let lines = page.lines();
+            for block in lines.chunk_by(|a, b| a[0].block == b[0].block)
+            {
+                let first = &block[0][0];
+                let area = union(block.iter().flat_map(|l| l.iter().map(|w| w.pixels)));
+                xml.push_str(&format!("<TextBlock ID=\"block_{}_{}\" {}>\n", page.page, first.block, position(&area)));
+                for line in block
+                {
+                    let line_area = union(line.iter().map(|w| w.pixels));
+                    xml.push_str(&format!("<TextLine ID=\"line_{}_{}_{}_{}\" {}>\n", page.page, line[0].block, line[0].paragraph, line[0].line, position(&line_area)));
+                    for (index, word) in line.iter().enumerate()
+                    {
+                        if index > 0
+                        {
+                            xml.push_str("<SP/>\n");
+                        }
+                        xml.push_str(&format!("<String CONTENT=\"{}\" {} WC=\"{:.2}\"/>\n", escape(&word.text), position(&word.pixels), word.confidence));
+                    }
+                    xml.push_str("</TextLine>\n");
+                }
+                xml.push_str("</TextBlock>\n");
+            }
+            xml.push_str("</PrintSpace>\n</Page>\n");
+        }
+        xml.push_str("</Layout>\n</alto>\n");
+        xml
+    }
+}
+
+///Откуда получен текст страницы
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSource
+{
+    TextLayer,
+    Ocr,
+}
+
+///Текст страницы из текстового слоя или, для сканов, распознанный
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageText
+{
+    pub page: u32,
+    pub text: String,
+    pub source: TextSource,
+    ///Результат распознавания со словами и областями, если текст распознан
+    pub ocr: Option<OcrPage>,
+}
+
+///Текстовый слой страницы или ее рендер, если текст нужно распознать
+pub(crate) enum PageSource
+{
+    Text(String),
+    Scan(RenderedPage),
+}
+
+///Распознавание отрендеренных страниц через tesseract (CLI), без создания нового pdf
+#[derive(Debug, Clone)]
+pub struct Tesseract
+{
+    program: PathBuf,
+    languages: Vec<String>,
+    dpi: f32,
+    psm: Option<u8>,
+    timeout: Duration,
+}
+impl Default for Tesseract
+{
+    fn default() -> Self
+    {
+        Self { program: PathBuf::from("tesseract"), languages: vec!["rus".to_owned(), "eng".to_owned()], dpi: DEFAULT_DPI, psm: None, timeout: Duration::from_secs(120) }
+    }
+}
+impl Tesseract
+{
+    ///tesseract из PATH
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+    ///Путь к программе tesseract (или совместимой заглушке)
+    pub fn set_program<P: AsRef<Path>>(mut self, program: P) -> Self
+    {
+        self.program = program.as_ref().to_owned();
+        self
+    }
+    ///Языки распознавания, по умолчанию `rus` и `eng`
+    pub fn set_languages(mut self, languages: &[&str]) -> Self
+    {
+        self.languages = languages.iter().map(|l| l.to_string()).collect();
+        self
+    }
+    ///Разрешение рендера страницы для распознавания, по умолчанию 300 dpi
+    pub fn set_dpi(mut self, dpi: f32) -> Self
+    {
+        self.dpi = dpi;
+        self
+    }
+    ///Режим сегментации страницы tesseract (`--psm`), по умолчанию автоматический
+    pub fn set_psm(mut self, psm: u8) -> Self
+    {
+        self.psm = Some(psm);
+        self
+    }
+    ///Максимальное время распознавания одной страницы, по умолчанию 2 минуты
+    pub fn set_timeout(mut self, timeout: Duration) -> Self
+    {
+        self.timeout = timeout;
+        self
+    }
+    pub(crate) fn dpi(&self) -> f32
+    {
+        self.dpi
+    }
+
+    ///Распознавание страницы, отрендеренной `render`
+    pub(crate) async fn recognize(&self, rendered: RenderedPage, page_number: u32) -> Result<OcrPage, Error>
+    {
+        let mut command = Command::new(&self.program);
+        command.args(["stdin", "stdout", "-l", &self.languages.join("+"), "--dpi", &self.dpi.round().to_string()]);
+        if let Some(psm) = self.psm
+        {
+            command.args(["--psm", &psm.to_string()]);
+        }
+        command.arg("tsv");
+        let program = self.program.display().to_string();
+        let tsv = ocrmypdf::run_command(command, program, Some(rendered.png), self.timeout, None, |_| "ошибка tesseract").await?;
+        let words = parse_tsv(&String::from_utf8_lossy(&tsv), &rendered.transform);
+        Ok(OcrPage { page: page_number, image_width: rendered.width, image_height: rendered.height, dpi: self.dpi, words })
+    }
+}
+
+///Страница, отрендеренная для распознавания
+pub(crate) struct RenderedPage
+{
+    png: Vec<u8>,
+    width: u32,
+    height: u32,
+    transform: PixelTransform,
+}
+
+///Перевод пикселей рендера в пункты pdf, поворот страницы кратен 90 градусам, поэтому перевод аффинный
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PixelTransform
+{
+    origin: (f32, f32),
+    ///Смещение в пунктах на один пиксель по горизонтали изображения
+    x_step: (f32, f32),
+    ///Смещение в пунктах на один пиксель по вертикали изображения
+    y_step: (f32, f32),
+}
+impl PixelTransform
+{
+    fn point(&self, x: i32, y: i32) -> (f32, f32)
+    {
+        (self.origin.0 + x as f32 * self.x_step.0 + y as f32 * self.y_step.0, self.origin.1 + x as f32 * self.x_step.1 + y as f32 * self.y_step.1)
+    }
+    fn rect(&self, pixels: &PixelRect) -> PageRect
+    {
+        let (left, top) = self.point(pixels.x, pixels.y);
+        let (right, bottom) = self.point(pixels.x + pixels.width, pixels.y + pixels.height);
+        PageRect::new(left, bottom, right, top)
+    }
+}
+
+///Рендер страницы в оттенках серого для распознавания
+pub(crate) fn render(page: &PdfPage, dpi: f32, path: &str, page_number: u32) -> Result<RenderedPage, Error>
+{
+    const STEP: i32 = 1000;
+    let config = PdfRenderConfig::new().scale_page_by_factor(dpi / 72.0);
+    let bitmap = page.render_with_config(&config)?;
+    let (width, height) = (bitmap.width() as u32, bitmap.height() as u32);
+    let image = bitmap::bitmap_to_image(bitmap.as_rgba_bytes(), width, height, bitmap.format().unwrap_or_default(), PageImageFormat::Jpeg, bitmap::DEFAULT_BACKGROUND)
+        .ok_or(Error::ExtractDynamicImageError(path.to_owned(), page_number))?
+        .into_luma8();
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let point = |x: i32, y: i32| page.pixels_to_points(x, y, &config).map(|(x, y)| (x.value, y.value));
+    let origin = point(0, 0)?;
+    let (x_end, y_end) = (point(STEP, 0)?, point(0, STEP)?);
+    let step = |end: (f32, f32)| ((end.0 - origin.0) / STEP as f32, (end.1 - origin.1) / STEP as f32);
+    let transform = PixelTransform { origin, x_step: step(x_end), y_step: step(y_end) };
+    Ok(RenderedPage { png, width, height, transform })
+}
+
+///Разбор вывода tesseract в формате tsv, берутся строки уровня 5 (слова)
+///level page_num block_num par_num line_num word_num left top width height conf text
+fn parse_tsv(tsv: &str, transform: &PixelTransform) -> Vec<OcrWord>
+{
+    let mut words = Vec::new();
+    for line in tsv.lines().skip(1)
+    {
+        let columns: Vec<&str> = line.splitn(12, '\t').collect();
+        if columns.len() < 12 || columns[0] != "5"
+        {
+            continue;
+        }
+        let text = columns[11].trim();
+        let number = |i: usize| columns[i].trim().parse::<i32>().unwrap_or(0);
+        let confidence: f32 = columns[10].trim().parse().unwrap_or(-1.0);
+        if text.is_empty() || confidence < 0.0
+        {
+            continue;
+        }
+        let pixels = PixelRect { x: number(6), y: number(7), width: number(8), height: number(9) };
+        words.push(OcrWord
+        {
+            text: text.to_owned(),
+            confidence: confidence / 100.0,
+            rect: transform.rect(&pixels),
+            pixels,
+            block: number(2) as u32,
+            paragraph: number(3) as u32,
+            line: number(4) as u32,
+        });
+    }
+    words
+}
+
+fn union(rects: impl Iterator<Item = PixelRect>) -> PixelRect
+{
+    let points: Vec<(i32, i32)> = rects.flat_map(|r| [(r.x, r.y), (r.x + r.width, r.y + r.height)]).collect();
+    PixelRect::bounding(&points)
+}
+
+fn bbox(rect: &PixelRect) -> String
+{
+    format!("bbox {} {} {} {}", rect.x, rect.y, rect.x + rect.width, rect.y + rect.height)
+}
+
+fn position(rect: &PixelRect) -> String
+{
+    format!("HPOS=\"{}\" VPOS=\"{}\" WIDTH=\"{}\" HEIGHT=\"{}\"", rect.x, rect.y, rect.width, rect.height)
+}
+
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::geometry::{PageRect, PixelRect};
+    use super::{parse_tsv, OcrDocument, OcrPage, PixelTransform, RenderedPage, Tesseract};
+
+    const TSV: &str = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n\
+        1\t1\t0\t0\t0\t0\t0\t0\t2480\t3508\t-1\t\n\
+        2\t1\t1\t0\t0\t0\t300\t300\t900\t100\t-1\t\n\
+        5\t1\t1\t1\t1\t1\t300\t300\t400\t100\t96.5\tДоговор\n\
+        5\t1\t1\t1\t1\t2\t750\t300\t150\t100\t91\t№12\n\
+        5\t1\t2\t1\t1\t1\t300\t600\t500\t90\t88\tR&D <test>\n\
+        5\t1\t2\t1\t1\t2\t850\t600\t50\t90\t-1\t \n";
+
+    ///300 dpi, страница A4 без поворота: пиксель 300/72 пункта, ось y направлена вниз
+    fn transform() -> PixelTransform
+    {
+        let step = 72.0 / 300.0;
+        PixelTransform { origin: (0.0, 842.0), x_step: (step, 0.0), y_step: (0.0, -step) }
+    }
+
+    fn page() -> OcrPage
+    {
+        OcrPage { page: 1, image_width: 2480, image_height: 3508, dpi: 300.0, words: parse_tsv(TSV, &transform()) }
+    }
+
+    #[test]
+    fn test_parse_tsv()
+    {
+        let page = page();
+        assert_eq!(page.words.len(), 3);
+        assert_eq!(page.words[0].text, "Договор");
+        assert_eq!(page.words[0].confidence, 0.965);
+        assert_eq!(page.words[0].pixels, PixelRect { x: 300, y: 300, width: 400, height: 100 });
+        assert_eq!(page.words[0].rect, PageRect::new(72.0, 842.0 - 96.0, 168.0, 842.0 - 72.0));
+        assert_eq!(page.text(), "Договор №12\n\nR&D <test>");
+        assert!((page.confidence() - (0.965 + 0.91 + 0.88) / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rotated_transform()
+    {
+        //страница повернута на 90 градусов: горизонталь изображения идет вверх по странице
+        let transform = PixelTransform { origin: (0.0, 0.0), x_step: (0.0, 0.24), y_step: (0.24, 0.0) };
+        let rect = transform.rect(&PixelRect { x: 100, y: 50, width: 200, height: 25 });
+        assert_eq!(rect, PageRect::new(12.0, 24.0, 18.0, 72.0));
+    }
+
+    #[test]
+    fn test_export()
+    {
+        let document = OcrDocument { pages: vec![page()] };
+        let hocr = document.to_hocr();
+        assert!(hocr.contains("<div class=\"ocr_page\" id=\"page_1\" title=\"bbox 0 0 2480 3508; ppageno 0; scan_res 300 300\">"));
+        assert!(hocr.contains("<span class=\"ocrx_word\" id=\"word_1_1_1\" title=\"bbox 300 300 700 400; x_wconf 97\">Договор</span>"));
+        assert!(hocr.contains(">R&amp;D &lt;test&gt;</span>"));
+        assert_eq!(hocr.matches("class=\"ocr_carea\"").count(), 2);
+        assert_eq!(hocr.matches("<div").count(), hocr.matches("</div>").count());
+        let alto = document.to_alto();
+        assert!(alto.contains("<String CONTENT=\"№12\" HPOS=\"750\" VPOS=\"300\" WIDTH=\"150\" HEIGHT=\"100\" WC=\"0.91\"/>"));
+        assert!(alto.contains("<TextBlock ID=\"block_1_1\" HPOS=\"300\" VPOS=\"300\" WIDTH=\"600\" HEIGHT=\"100\">"));
+        assert_eq!(alto.matches("<TextLine").count(), 2);
+        assert_eq!(alto.matches("<SP/>").count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_recognize_with_stub()
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("pdf_viewer_tesseract_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("tesseract");
+        let args = dir.join("args");
+        std::fs::write(&script, format!("#!/bin/sh\necho \"$@\" > '{}'\ncat > /dev/null\nprintf '{}'\n", args.display(), TSV.replace('\t', "\\t").replace('\n', "\\n"))).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let tesseract = Tesseract::new().set_program(&script).set_psm(6);
+        let rendered = RenderedPage { png: vec![0; 1000], width: 2480, height: 3508, transform: transform() };
+        let page = tesseract.recognize(rendered, 3).await.unwrap();
+        assert_eq!((page.page, page.words.len()), (3, 3));
+        assert_eq!(std::fs::read_to_string(args).unwrap().trim(), "stdin stdout -l rus+eng --dpi 300 --psm 6 tsv");
+    }
+}
@@ -0,0 +1,422 @@
+use image::{DynamicImage, Rgb};
+use pdfium_render::prelude::{PdfDocument, PdfPage, PdfiumError};
+use serde::Serialize;
+use crate::{geometry::{PageRect, PixelRect}, pii};
+
+///Цвет удаленного текста на первой версии
+pub(crate) const REMOVED_COLOR: Rgb<u8> = Rgb([230, 40, 40]);
+///Цвет добавленного текста на второй версии
+pub(crate) const INSERTED_COLOR: Rgb<u8> = Rgb([40, 180, 60]);
+///Непрозрачность подсветки
+const HIGHLIGHT_OPACITY: f32 = 0.35;
+///Предел количества правок, после которого оставшийся текст считается замененным целиком,
+///чтобы сравнение совершенно разных документов не занимало гигабайты памяти
+const MAX_EDITS: usize = 4000;
+
+///Версия документа в сравнении
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffVersion
+{
+    First,
+    Second,
+}
+
+///Вид изменения текста
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextChangeKind
+{
+    ///Слова есть только во второй версии
+    Inserted,
+    ///Слова есть только в первой версии
+    Deleted,
+    ///Слова первой версии заменены другими
+    Replaced,
+}
+
+///Область на странице одной из версий
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TextArea
+{
+    pub page: u32,
+    pub rect: PageRect,
+}
+
+///Изменение текста: подряд идущие удаленные и добавленные слова
+#[derive(Debug, Clone, Serialize)]
+pub struct TextChange
+{
+    pub kind: TextChangeKind,
+    ///Текст первой версии, пустой для `Inserted`
+    pub removed: String,
+    ///Текст второй версии, пустой для `Deleted`
+    pub inserted: String,
+    ///Области удаленного текста в первой версии по строкам
+    pub first: Vec<TextArea>,
+    ///Области добавленного текста во второй версии по строкам
+    pub second: Vec<TextArea>,
+}
+
+///Результат сравнения текста двух версий документа
+#[derive(Debug, Clone, Serialize)]
+pub struct TextDiff
+{
+    ///Количество слов (и знаков препинания) в первой версии
+    pub first_words: usize,
+    ///Количество слов (и знаков препинания) во второй версии
+    pub second_words: usize,
+    pub changes: Vec<TextChange>,
+}
+impl TextDiff
+{
+    pub fn is_identical(&self) -> bool
+    {
+        self.changes.is_empty()
+    }
+    ///Области изменений на странице выбранной версии
+    pub fn areas(&self, version: DiffVersion, page_number: u32) -> Vec<PageRect>
+    {
+        self.changes.iter()
+            .flat_map(|c| match version { DiffVersion::First => c.first.iter(), DiffVersion::Second => c.second.iter() })
+            .filter(|a| a.page == page_number)
+            .map(|a| a.rect)
+            .collect()
+    }
+}
+
+///Слово или знак препинания с областями символов
+#[derive(Debug, Clone)]
+struct Token
+{
+    text: String,
+    page: u32,
+    space_before: bool,
+    chars: Vec<PageRect>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit
+{
+    Equal,
+    Delete,
+    Insert,
+}
+
+///Сравнение текста двух документов по словам
+pub(crate) fn compare(first: &PdfDocument, second: &PdfDocument) -> Result<TextDiff, PdfiumError>
+{
+    let first = document_tokens(first)?;
+    let second = document_tokens(second)?;
+    Ok(diff_tokens(&first, &second))
+}
+
+fn document_tokens(document: &PdfDocument) -> Result<Vec<Token>, PdfiumError>
+{
+    let mut tokens = Vec::new();
+    for (index, page) in document.pages().iter().enumerate()
+    {
+        tokens.extend(page_tokens(&page, index as u32 + 1)?);
+    }
+    Ok(tokens)
+}
+
+fn page_tokens(page: &PdfPage, page_number: u32) -> Result<Vec<Token>, PdfiumError>
+{
+    let text = page.text()?;
+    let chars = text.chars().iter().filter_map(|c|
+    {
+        let ch = c.unicode_char()?;
+        let rect = if ch.is_whitespace() { None } else { c.loose_bounds().ok().map(|b| PageRect::from_pdf(&b)) };
+        Some((ch, rect))
+    }).collect::<Vec<_>>();
+    Ok(tokenize(chars.into_iter(), page_number))
+}
+
+///Разбиение текста на слова: буквы и цифры идут подряд, каждый знак препинания отдельным словом,
+///так замена одной цифры или точки на запятую не задевает соседние слова
+fn tokenize(chars: impl Iterator<Item = (char, Option<PageRect>)>, page_number: u32) -> Vec<Token>
+{
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut space_before = true;
+    let mut in_word = false;
+    for (ch, rect) in chars
+    {
+        if ch.is_whitespace() || ch.is_control()
+        {
+            space_before = true;
+            in_word = false;
+            continue;
+        }
+        let alphanumeric = ch.is_alphanumeric();
+        match tokens.last_mut()
+        {
+            Some(token) if alphanumeric && in_word =>
+            {
+                token.text.push(ch);
+                token.chars.extend(rect);
+            },
+            _ =>
+            {
+                tokens.push(Token { text: ch.to_string(), page: page_number, space_before, chars: rect.into_iter().collect() });
+            }
+        }
+        in_word = alphanumeric;
+        space_before = false;
+    }
+    tokens
+}
+
+fn diff_tokens(first: &[Token], second: &[Token]) -> TextDiff
+{
+    let a: Vec<&str> = first.iter().map(|t| t.text.as_str()).collect();
+    let b: Vec<&str> = second.iter().map(|t| t.text.as_str()).collect();
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut inserted): (Vec<&Token>, Vec<&Token>) = (Vec::new(), Vec::new());
+    for edit in edits(&a, &b).into_iter().chain(std::iter::once(Edit::Equal))
+    {
+        match edit
+        {
+            Edit::Delete =>
+            {
+                removed.push(&first[i]);
+                i += 1;
+            },
+            Edit::Insert =>
+            {
+                inserted.push(&second[j]);
+                j += 1;
+            },
+            Edit::Equal =>
+            {
+                if !removed.is_empty() || !inserted.is_empty()
+                {
+                    changes.push(change(&removed, &inserted));
+                    removed.clear();
+                    inserted.clear();
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    TextDiff { first_words: first.len(), second_words: second.len(), changes }
+}
+
+fn change(removed: &[&Token], inserted: &[&Token]) -> TextChange
+{
+    let kind = match (removed.is_empty(), inserted.is_empty())
+    {
+        (true, _) => TextChangeKind::Inserted,
+        (_, true) => TextChangeKind::Deleted,
+        _ => TextChangeKind::Replaced
+    };
+    TextChange { kind, removed: join(removed), inserted: join(inserted), first: areas(removed), second: areas(inserted) }
+}
+
+///Текст слов с пробелами там, где они были в документе
+fn join(tokens: &[&Token]) -> String
+{
+    let mut text = String::new();
+    for (index, token) in tokens.iter().enumerate()
+    {
+        if index > 0 && token.space_before
+        {
+            text.push(' ');
+        }
+        text.push_str(&token.text);
+    }
+    text
+}
+
+///Области слов по страницам и строкам
+fn areas(tokens: &[&Token]) -> Vec<TextArea>
+{
+    let mut areas = Vec::new();
+    for page_tokens in tokens.chunk_by(|a, b| a.page == b.page)
+    {
+        let page = page_tokens[0].page;
+        let rects = pii::line_rects(page_tokens.iter().flat_map(|t| t.chars.iter().copied()));
+        areas.extend(rects.into_iter().map(|rect| TextArea { page, rect }));
+    }
+    areas
+}
+
+///Кратчайший список правок (алгоритм Майерса), общие начало и конец отбрасываются заранее
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit>
+{
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let mut result = vec![Edit::Equal; prefix];
+    result.extend(myers(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]));
+    result.extend(std::iter::repeat_n(Edit::Equal, suffix));
+    result
+}
+
+fn myers(a: &[&str], b: &[&str]) -> Vec<Edit>
+{
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    //на шаге d хранятся только диагонали -d..=d, индекс диагонали k это k + d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut found = false;
+    for d in 0..=(n + m)
+    {
+        if d as usize > MAX_EDITS
+        {
+            break;
+        }
+        let previous = trace.last();
+        let mut current = vec![0; (2 * d + 1) as usize];
+        for k in (-d..=d).step_by(2)
+        {
+            let mut x = match previous
+            {
+                None => 0,
+                Some(v) if k == -d || (k != d && v[(k - 1 + d - 1) as usize] < v[(k + 1 + d - 1) as usize]) => v[(k + 1 + d - 1) as usize],
+                Some(v) => v[(k - 1 + d - 1) as usize] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize]
+            {
+                x += 1;
+                y += 1;
+            }
+            current[(k + d) as usize] = x;
+            if x >= n && y >= m
+            {
+                found = true;
+            }
+        }
+        trace.push(current);
+        if found
+        {
+            break;
+        }
+    }
+    if !found
+    {
+        let mut result = vec![Edit::Delete; a.len()];
+        result.extend(std::iter::repeat_n(Edit::Insert, b.len()));
+        return result;
+    }
+    let mut result = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev()
+    {
+        let previous = &trace[(d - 1) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && previous[(k - 1 + d - 1) as usize] < previous[(k + 1 + d - 1) as usize]);
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let previous_x = previous[(previous_k + d - 1) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y
+        {
+            result.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        result.push(if down { Edit::Insert } else { Edit::Delete });
+        x = previous_x;
+        y = previous_y;
+    }
+    result.extend(std::iter::repeat_n(Edit::Equal, x as usize));
+    result.reverse();
+    result
+}
+
+///Подсветка областей на изображении страницы
+pub(crate) fn highlight(image: DynamicImage, rects: &[PixelRect], color: Rgb<u8>) -> DynamicImage
+{
+    let mut rgba = image.into_rgba8();
+    let (width, height) = (rgba.width() as i32, rgba.height() as i32);
+    for rect in rects
+    {
+        for y in rect.y.max(0)..(rect.y + rect.height).min(height)
+        {
+            for x in rect.x.max(0)..(rect.x + rect.width).min(width)
+            {
+                let pixel = rgba.get_pixel_mut(x as u32, y as u32);
+                for channel in 0..3
+                {
+                    let value = pixel.0[channel] as f32 * (1.0 - HIGHLIGHT_OPACITY) + color.0[channel] as f32 * HIGHLIGHT_OPACITY;
+                    pixel.0[channel] = value.round() as u8;
+                }
+            }
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::geometry::PageRect;
+    use super::{diff_tokens, edits, tokenize, Edit, TextChangeKind, Token};
+
+    ///Текст в одну строку, символ шириной 5 пунктов
+    fn tokens(text: &str, page: u32) -> Vec<Token>
+    {
+        let chars = text.chars().enumerate().map(|(i, ch)|
+        {
+            let left = i as f32 * 5.0;
+            (ch, (!ch.is_whitespace()).then(|| PageRect::new(left, 700.0, left + 5.0, 710.0)))
+        });
+        tokenize(chars, page)
+    }
+
+    #[test]
+    fn test_tokenize()
+    {
+        let tokens = tokens("Сумма: 1 500,00 руб.", 1);
+        let words: Vec<_> = tokens.iter().map(|t| (t.text.as_str(), t.space_before)).collect();
+        assert_eq!(words, vec![("Сумма", true), (":", false), ("1", true), ("500", true), (",", false), ("00", false), ("руб", true), (".", false)]);
+        assert_eq!(tokens[3].chars.len(), 3);
+    }
+
+    #[test]
+    fn test_edits()
+    {
+        use Edit::*;
+        let a = ["a", "b", "c", "a", "b", "b", "a"];
+        let b = ["c", "b", "a", "b", "a", "c"];
+        let result = edits(&a, &b);
+        assert_eq!(result.iter().filter(|e| **e != Equal).count(), 5);
+        //применение правок к первой последовательности дает вторую
+        let (mut i, mut j) = (0, 0);
+        for edit in result
+        {
+            match edit
+            {
+                Equal => { assert_eq!(a[i], b[j]); i += 1; j += 1; },
+                Delete => i += 1,
+                Insert => j += 1
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        assert_eq!(edits(&["x"], &["x"]), vec![Equal]);
+        assert_eq!(edits(&[], &["x"]), vec![Insert]);
+    }
+
+    #[test]
+    fn test_diff_changes()
+    {
+        let first = tokens("Срок оплаты 10 дней с даты подписания договора.", 1);
+        let mut second = tokens("Срок оплаты 15 дней с даты подписания", 1);
+        second.extend(tokens("настоящего договора, без штрафа.", 2));
+        let diff = diff_tokens(&first, &second);
+        let changes: Vec<_> = diff.changes.iter().map(|c| (c.kind, c.removed.as_str(), c.inserted.as_str())).collect();
+        assert_eq!(changes, vec!
+        [
+            (TextChangeKind::Replaced, "10", "15"),
+            (TextChangeKind::Inserted, "", "настоящего"),
+            (TextChangeKind::Inserted, "", ", без штрафа"),
+        ]);
+        //области: замененное число на первой и второй версии, вставка на второй странице второй версии
+        assert_eq!(diff.changes[0].first.len(), 1);
+        assert_eq!(diff.changes[0].first[0].rect, PageRect::new(60.0, 700.0, 70.0, 710.0));
+        assert_eq!(diff.changes[1].second[0].page, 2);
+        assert!(diff.changes[1].first.is_empty());
+        assert_eq!(diff.areas(super::DiffVersion::Second, 2).len(), 2);
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::{Seek, Write};
+use image::{DynamicImage, GrayImage};
+use tiff::{encoder::{colortype, compression::{Deflate, Lzw}, Rational, TiffEncoder}, tags::{ResolutionUnit, Tag}, TiffError, TiffResult};
+use crate::{bitmap::DEFAULT_BACKGROUND, dither::{apply_color_mode, binarize, Binarization, ColorMode}};
+
+///Параметры экспорта страниц в многостраничный tiff
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TiffOptions
+{
+    ///Разрешение рендера, записывается в теги XResolution и YResolution
+    pub dpi: u32,
+    pub mode: TiffMode,
+}
+impl Default for TiffOptions
+{
+    fn default() -> Self
+    {
+        Self { dpi: 200, mode: TiffMode::Color(TiffCompression::Lzw) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TiffMode
+{
+    Color(TiffCompression),
+    Gray(TiffCompression),
+    ///Оттенки серого с ограниченным количеством уровней, хранятся как 8 бит, но сжимаются заметно лучше
+    GrayLevels { levels: u8, dither: bool, compression: TiffCompression },
+    ///1 бит на пиксель, сжатие CCITT Group 4 (факс, архив)
+    Bilevel(Binarization),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TiffCompression
+{
+    #[default]
+    Lzw,
+    Deflate,
+}
+
+///Код сжатия CCITT Group 4 в теге Compression
+const COMPRESSION_CCITT_G4: u16 = 4;
+///Значение тега NewSubfileType для страницы многостраничного документа
+const SUBFILE_PAGE: u32 = 2;
+///Тег PageNumber, в tiff нет для него варианта
+const TAG_PAGE_NUMBER: u16 = 297;
+
+///Запись одной страницы многостраничного tiff
+///изображение должно быть без альфа канала (`bitmap::remove_alpha`)
+pub(crate) fn write_page<W: Write + Seek>(encoder: &mut TiffEncoder<W>, image: &DynamicImage, options: &TiffOptions, page: u16, total: u16) -> TiffResult<()>
+{
+    let resolution = Rational { n: options.dpi.max(1), d: 1 };
+    let (width, height) = (image.width(), image.height());
+    match options.mode
+    {
+        TiffMode::Color(compression) =>
+        {
+            let rgb = image.to_rgb8();
+            match compression
+            {
+                TiffCompression::Lzw =>
+                {
+                    let mut page_encoder = encoder.new_image_with_compression::<colortype::RGB8, _>(width, height, Lzw)?;
+                    write_page_tags(page_encoder.encoder(), page, total)?;
+                    page_encoder.resolution(ResolutionUnit::Inch, resolution);
+                    page_encoder.write_data(rgb.as_raw())
+                },
+                TiffCompression::Deflate =>
+                {
+                    let mut page_encoder = encoder.new_image_with_compression::<colortype::RGB8, _>(width, height, Deflate::default())?;
+                    write_page_tags(page_encoder.encoder(), page, total)?;
+                    page_encoder.resolution(ResolutionUnit::Inch, resolution);
+                    page_encoder.write_data(rgb.as_raw())
+                }
+            }
+        },
+        TiffMode::Gray(compression) | TiffMode::GrayLevels { compression, .. } =>
+        {
+            let gray = match options.mode
+            {
+                TiffMode::GrayLevels { levels, dither, .. } => apply_color_mode(image.clone(), ColorMode::GrayLevels { levels, dither }, DEFAULT_BACKGROUND).into_luma8(),
+                _ => image.to_luma8()
+            };
+            match compression
+            {
+                TiffCompression::Lzw =>
+                {
+                    let mut page_encoder = encoder.new_image_with_compression::<colortype::Gray8, _>(width, height, Lzw)?;
+                    write_page_tags(page_encoder.encoder(), page, total)?;
+                    page_encoder.resolution(ResolutionUnit::Inch, resolution);
+                    page_encoder.write_data(gray.as_raw())
+                },
+                TiffCompression::Deflate =>
+                {
+                    let mut page_encoder = encoder.new_image_with_compression::<colortype::Gray8, _>(width, height, Deflate::default())?;
+                    write_page_tags(page_encoder.encoder(), page, total)?;
+                    page_encoder.resolution(ResolutionUnit::Inch, resolution);
+                    page_encoder.write_data(gray.as_raw())
+                }
+            }
+        },
+        TiffMode::Bilevel(method) =>
+        {
+            let bilevel = binarize(&image.to_luma8(), method);
+            let data = encode_g4(&bilevel)?;
+            //в tiff нет готового кодировщика для 1 бит, поэтому директорию собираем вручную
+            let mut dir = encoder.new_directory()?;
+            let offset = dir.write_data(data.as_slice())?;
+            dir.write_tag(Tag::ImageWidth, width)?;
+            dir.write_tag(Tag::ImageLength, height)?;
+            dir.write_tag(Tag::BitsPerSample, 1u16)?;
+            dir.write_tag(Tag::Compression, COMPRESSION_CCITT_G4)?;
+            dir.write_tag(Tag::PhotometricInterpretation, 0u16)?;
+            dir.write_tag(Tag::StripOffsets, offset as u32)?;
+            dir.write_tag(Tag::SamplesPerPixel, 1u16)?;
+            dir.write_tag(Tag::RowsPerStrip, height)?;
+            dir.write_tag(Tag::StripByteCounts, data.len() as u32)?;
+            dir.write_tag(Tag::XResolution, resolution.clone())?;
+            dir.write_tag(Tag::YResolution, resolution)?;
+            dir.write_tag(Tag::ResolutionUnit, ResolutionUnit::Inch.to_u16())?;
+            write_page_tags(&mut dir, page, total)?;
+            dir.finish()
+        }
+    }
+}
+
+fn write_page_tags<W: Write + Seek, K: tiff::encoder::TiffKind>(dir: &mut tiff::encoder::DirectoryEncoder<W, K>, page: u16, total: u16) -> TiffResult<()>
+{
+    dir.write_tag(Tag::NewSubfileType, SUBFILE_PAGE)?;
+    dir.write_tag(Tag::Unknown(TAG_PAGE_NUMBER), &[page, total][..])
+}
+
+///Сжатие черно-белого изображения (0 - черный, остальное - белый) в CCITT Group 4
+///кодировщик принимает ширину строки в u16, более широкие страницы (большой формат с высоким разрешением) не сжимаются
+fn encode_g4(bilevel: &GrayImage) -> TiffResult<Vec<u8>>
+{
+    let width = u16::try_from(bilevel.width()).map_err(|_| TiffError::LimitsExceeded)?;
+    let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+    for row in bilevel.rows()
+    {
+        let pels = row.map(|p| if p.0[0] == 0 { fax::Color::Black } else { fax::Color::White });
+        //VecWriter не возвращает ошибок
+        let _ = encoder.encode_line(pels, width);
+    }
+    match encoder.finish()
+    {
+        Ok(writer) => Ok(writer.finish()),
+        Err(e) => match e {}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Cursor;
+    use image::{DynamicImage, GrayImage, RgbImage};
+    use tiff::{decoder::{Decoder, DecodingResult}, encoder::TiffEncoder, tags::Tag, TiffError};
+    use crate::dither::Binarization;
+    use super::{encode_g4, write_page, TiffCompression, TiffMode, TiffOptions};
+
+    fn sample_page() -> DynamicImage
+    {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(50, 30, |x, y| if (x / 5 + y / 5) % 2 == 0 { image::Rgb([0, 0, 0]) } else { image::Rgb([250, 240, 230]) }))
+    }
+
+    fn write(mode: TiffMode) -> Vec<u8>
+    {
+        let options = TiffOptions { dpi: 300, mode };
+        let mut buffer = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+            write_page(&mut encoder, &sample_page(), &options, 0, 2).unwrap();
+            write_page(&mut encoder, &sample_page(), &options, 1, 2).unwrap();
+        }
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_multipage_color_and_gray()
+    {
+        for mode in [TiffMode::Color(TiffCompression::Lzw), TiffMode::Color(TiffCompression::Deflate), TiffMode::Gray(TiffCompression::Lzw), TiffMode::Gray(TiffCompression::Deflate), TiffMode::GrayLevels { levels: 4, dither: true, compression: TiffCompression::Lzw }]
+        {
+            let data = write(mode);
+            let mut decoder = Decoder::new(Cursor::new(data)).unwrap();
+            let mut pages = 0;
+            loop
+            {
+                pages += 1;
+                assert_eq!(decoder.dimensions().unwrap(), (50, 30));
+                assert_eq!(decoder.get_tag_u32_vec(Tag::XResolution).unwrap(), vec![300, 1]);
+                match decoder.read_image().unwrap()
+                {
+                    DecodingResult::U8(data) => assert_eq!(data[0], 0),
+                    _ => panic!("{:?}", mode)
+                }
+                if !decoder.more_images()
+                {
+                    break;
+                }
+                decoder.next_image().unwrap();
+            }
+            assert_eq!(pages, 2);
+        }
+    }
+
+    #[test]
+    fn test_bilevel_tags()
+    {
+        for method in [Binarization::Threshold(128), Binarization::FloydSteinberg, Binarization::Ordered, Binarization::Sauvola { window: 15, k: 0.34 }]
+        {
+            let data = write(TiffMode::Bilevel(method));
+            let mut decoder = Decoder::new(Cursor::new(data)).unwrap();
+            assert_eq!(decoder.get_tag_u32(Tag::Compression).unwrap(), 4);
+            assert_eq!(decoder.get_tag_u32(Tag::BitsPerSample).unwrap(), 1);
+            assert_eq!(decoder.get_tag_u32_vec(Tag::YResolution).unwrap(), vec![300, 1]);
+            assert!(decoder.more_images());
+        }
+    }
+
+    #[test]
+    fn test_g4_roundtrip()
+    {
+        let bilevel = GrayImage::from_fn(37, 11, |x, y| if (x * 3 + y * 7) % 5 < 2 || x == 36 { image::Luma([0]) } else { image::Luma([255]) });
+        let data = encode_g4(&bilevel).unwrap();
+        let mut lines = Vec::new();
+        fax::decoder::decode_g4(data.into_iter(), 37, Some(11), |transitions|
+        {
+            lines.push(fax::decoder::pels(transitions, 37).map(|c| if c == fax::Color::Black { 0 } else { 255 }).collect::<Vec<u8>>());
+        }).unwrap();
+        assert_eq!(lines.concat(), bilevel.into_raw());
+        //ширина больше u16 не обрезается молча, а дает ошибку
+        assert!(matches!(encode_g4(&GrayImage::new(70_000, 1)), Err(TiffError::LimitsExceeded)));
+    }
+}
@@ -0,0 +1,394 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use pdfium_render::prelude::{PdfDocument, PdfPage, PdfRenderConfig};
+use serde::Serialize;
+use crate::{bitmap, error::Error, geometry::{PageRect, PixelRect}, PageImageFormat};
+
+const HIGHLIGHT: Rgb<u8> = Rgb([230, 30, 30]);
+///Насколько осветляется неизмененная часть страницы на изображении различий (0 - как есть, 1 - белый)
+const FADE: f32 = 0.75;
+
+///Настройки визуального сравнения двух pdf
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VisualDiffOptions
+{
+    dpi: f32,
+    threshold: u8,
+    region_gap: u32,
+    min_region_pixels: u32,
+}
+impl Default for VisualDiffOptions
+{
+    fn default() -> Self
+    {
+        Self { dpi: 100.0, threshold: 48, region_gap: 8, min_region_pixels: 4 }
+    }
+}
+impl VisualDiffOptions
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+    ///Разрешение рендера, обе версии рендерятся в одном масштабе, по умолчанию 100 dpi
+    pub fn set_dpi(mut self, dpi: f32) -> Self
+    {
+        self.dpi = dpi;
+        self
+    }
+    ///Максимальная разница канала цвета, которая еще не считается изменением (сглаживание, сжатие), по умолчанию 48
+    pub fn set_threshold(mut self, threshold: u8) -> Self
+    {
+        self.threshold = threshold;
+        self
+    }
+    ///Расстояние в пикселях, на котором соседние изменения объединяются в одну область, по умолчанию 8
+    pub fn set_region_gap(mut self, gap: u32) -> Self
+    {
+        self.region_gap = gap.max(1);
+        self
+    }
+    ///Области с меньшим количеством измененных пикселей считаются шумом и не попадают в результат, по умолчанию 4
+    pub fn set_min_region_pixels(mut self, pixels: u32) -> Self
+    {
+        self.min_region_pixels = pixels;
+        self
+    }
+    fn render_config(&self) -> PdfRenderConfig
+    {
+        PdfRenderConfig::new().scale_page_by_factor(self.dpi / 72.0)
+    }
+}
+
+///Наличие страницы в сравниваемых версиях
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageDiffStatus
+{
+    ///Страница есть в обеих версиях
+    Compared,
+    ///Страница есть только в первой версии (удалена)
+    Removed,
+    ///Страница есть только во второй версии (добавлена)
+    Added,
+}
+
+///Результат сравнения одной страницы
+#[derive(Debug, Clone, Serialize)]
+pub struct PageDiff
+{
+    pub page: u32,
+    pub status: PageDiffStatus,
+    ///Доля совпавших пикселей от 0 до 1, для добавленных и удаленных страниц 0
+    pub similarity: f32,
+    ///Измененные области в пикселях `image`
+    pub regions: Vec<PixelRect>,
+    ///Те же области в пунктах pdf второй версии (первой, если страница удалена)
+    pub rects: Vec<PageRect>,
+    ///Вторая версия страницы (первая, если страница удалена) в бледных тонах, измененные пиксели выделены красным
+    #[serde(skip)]
+    pub image: DynamicImage,
+}
+impl PageDiff
+{
+    pub fn is_identical(&self) -> bool
+    {
+        self.status == PageDiffStatus::Compared && self.regions.is_empty()
+    }
+}
+
+///Результат сравнения двух версий документа
+#[derive(Debug, Clone, Serialize)]
+pub struct VisualDiff
+{
+    pub first_pages_count: u32,
+    pub second_pages_count: u32,
+    ///Страницы по порядку, сравниваются страницы с одинаковыми номерами
+    pub pages: Vec<PageDiff>,
+}
+impl VisualDiff
+{
+    ///Средняя схожесть по всем страницам
+    pub fn similarity(&self) -> f32
+    {
+        if self.pages.is_empty()
+        {
+            return 1.0;
+        }
+        self.pages.iter().map(|p| p.similarity).sum::<f32>() / self.pages.len() as f32
+    }
+    pub fn is_identical(&self) -> bool
+    {
+        self.pages.iter().all(|p| p.is_identical())
+    }
+    ///Страницы, на которых есть изменения
+    pub fn changed_pages(&self) -> impl Iterator<Item = &PageDiff>
+    {
+        self.pages.iter().filter(|p| !p.is_identical())
+    }
+}
+
+///Постраничное сравнение двух документов, при разном количестве страниц лишние страницы отмечаются как добавленные или удаленные
+pub(crate) fn compare(first: &PdfDocument, second: &PdfDocument, options: &VisualDiffOptions, path: &str) -> Result<VisualDiff, Error>
+{
+    let config = options.render_config();
+    let first_pages_count = first.pages().len() as u32;
+    let second_pages_count = second.pages().len() as u32;
+    let mut pages = Vec::with_capacity(first_pages_count.max(second_pages_count) as usize);
+    for page_number in 1..=first_pages_count.max(second_pages_count)
+    {
+        let index = (page_number - 1) as u16;
+        let diff = match (page_number <= first_pages_count, page_number <= second_pages_count)
+        {
+            (true, true) =>
+            {
+                let first_page = first.pages().get(index)?;
+                let second_page = second.pages().get(index)?;
+                let before = render(&first_page, &config, path, page_number)?;
+                let after = render(&second_page, &config, path, page_number)?;
+                let (image, mask, changed) = diff_images(&before, &after, options.threshold);
+                let regions = regions(&mask, options.region_gap, options.min_region_pixels);
+                let total = (mask.width * mask.height).max(1) as f32;
+                PageDiff
+                {
+                    page: page_number,
+                    status: PageDiffStatus::Compared,
+                    similarity: 1.0 - changed as f32 / total,
+                    rects: page_rects(&second_page, &regions, &config)?,
+                    regions,
+                    image: DynamicImage::ImageRgb8(image),
+                }
+            },
+            (only_first, _) =>
+            {
+                let page = if only_first { first.pages().get(index)? } else { second.pages().get(index)? };
+                let rendered = render(&page, &config, path, page_number)?;
+                let regions = vec![PixelRect { x: 0, y: 0, width: rendered.width() as i32, height: rendered.height() as i32 }];
+                PageDiff
+                {
+                    page: page_number,
+                    status: if only_first { PageDiffStatus::Removed } else { PageDiffStatus::Added },
+                    similarity: 0.0,
+                    rects: page_rects(&page, &regions, &config)?,
+                    regions,
+                    image: DynamicImage::ImageRgb8(tint(&rendered)),
+                }
+            }
+        };
+        pages.push(diff);
+    }
+    Ok(VisualDiff { first_pages_count, second_pages_count, pages })
+}
+
+fn render(page: &PdfPage, config: &PdfRenderConfig, path: &str, page_number: u32) -> Result<RgbImage, Error>
+{
+    let bitmap = page.render_with_config(config)?;
+    let image = bitmap::bitmap_to_image(bitmap.as_rgba_bytes(), bitmap.width() as u32, bitmap.height() as u32, bitmap.format().unwrap_or_default(), PageImageFormat::Jpeg, bitmap::DEFAULT_BACKGROUND);
+    image.map(|i| i.into_rgb8()).ok_or(Error::ExtractDynamicImageError(path.to_owned(), page_number))
+}
+
+///Маска измененных пикселей
+struct DiffMask
+{
+    width: u32,
+    height: u32,
+    changed: Vec<bool>,
+}
+
+///Попиксельное сравнение, при разных размерах страниц недостающая часть считается белой
+///возвращается изображение различий, маска и количество измененных пикселей
+fn diff_images(before: &RgbImage, after: &RgbImage, threshold: u8) -> (RgbImage, DiffMask, u32)
+{
+    let width = before.width().max(after.width());
+    let height = before.height().max(after.height());
+    let white = bitmap::DEFAULT_BACKGROUND;
+    let pixel = |image: &RgbImage, x: u32, y: u32| if x < image.width() && y < image.height() { *image.get_pixel(x, y) } else { white };
+    let mut image = RgbImage::new(width, height);
+    let mut changed = vec![false; (width * height) as usize];
+    let mut count = 0;
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            let a = pixel(before, x, y);
+            let b = pixel(after, x, y);
+            let difference = a.0.iter().zip(b.0.iter()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+            if difference > threshold
+            {
+                changed[(y * width + x) as usize] = true;
+                count += 1;
+                image.put_pixel(x, y, HIGHLIGHT);
+            }
+            else
+            {
+                image.put_pixel(x, y, fade(b));
+            }
+        }
+    }
+    (image, DiffMask { width, height, changed }, count)
+}
+
+fn fade(pixel: Rgb<u8>) -> Rgb<u8>
+{
+    Rgb(pixel.0.map(|c| (c as f32 + (255.0 - c as f32) * FADE).round() as u8))
+}
+
+///Страница целиком, которой нет в другой версии: бледная копия с красным оттенком
+fn tint(image: &RgbImage) -> RgbImage
+{
+    let mut tinted = image.clone();
+    for pixel in tinted.pixels_mut()
+    {
+        let faded = fade(*pixel);
+        *pixel = Rgb([faded.0[0], (faded.0[1] as f32 * 0.8) as u8, (faded.0[2] as f32 * 0.8) as u8]);
+    }
+    tinted
+}
+
+///Границы измененных пикселей в клетке: min_x, min_y, max_x, max_y, количество
+type CellBounds = (u32, u32, u32, u32, u32);
+
+///Объединение измененных пикселей в прямоугольные области
+///маска делится на клетки размером `gap`, соседние (в том числе по диагонали) клетки с изменениями образуют одну область,
+///границы области считаются по самим пикселям, а не по клеткам
+fn regions(mask: &DiffMask, gap: u32, min_pixels: u32) -> Vec<PixelRect>
+{
+    let gap = gap.max(1);
+    let columns = mask.width.div_ceil(gap) as usize;
+    let rows = mask.height.div_ceil(gap) as usize;
+    let mut cells: Vec<Option<CellBounds>> = vec![None; columns * rows];
+    for y in 0..mask.height
+    {
+        for x in 0..mask.width
+        {
+            if !mask.changed[(y * mask.width + x) as usize]
+            {
+                continue;
+            }
+            let cell = &mut cells[(y / gap) as usize * columns + (x / gap) as usize];
+            *cell = Some(match cell
+            {
+                Some((min_x, min_y, max_x, max_y, count)) => ((*min_x).min(x), (*min_y).min(y), (*max_x).max(x), (*max_y).max(y), *count + 1),
+                None => (x, y, x, y, 1)
+            });
+        }
+    }
+    let mut visited = vec![false; cells.len()];
+    let mut regions = Vec::new();
+    for start in 0..cells.len()
+    {
+        if visited[start] || cells[start].is_none()
+        {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start];
+        let (mut min_x, mut min_y, mut max_x, mut max_y, mut count) = (u32::MAX, u32::MAX, 0, 0, 0);
+        while let Some(current) = stack.pop()
+        {
+            if let Some((x0, y0, x1, y1, c)) = cells[current]
+            {
+                min_x = min_x.min(x0);
+                min_y = min_y.min(y0);
+                max_x = max_x.max(x1);
+                max_y = max_y.max(y1);
+                count += c;
+            }
+            let (column, row) = ((current % columns) as i64, (current / columns) as i64);
+            for dy in -1..=1
+            {
+                for dx in -1..=1
+                {
+                    let (nc, nr) = (column + dx, row + dy);
+                    if nc < 0 || nr < 0 || nc >= columns as i64 || nr >= rows as i64
+                    {
+                        continue;
+                    }
+                    let next = nr as usize * columns + nc as usize;
+                    if !visited[next] && cells[next].is_some()
+                    {
+                        visited[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        if count >= min_pixels
+        {
+            regions.push(PixelRect { x: min_x as i32, y: min_y as i32, width: (max_x - min_x + 1) as i32, height: (max_y - min_y + 1) as i32 });
+        }
+    }
+    regions.sort_by_key(|r| (r.y, r.x));
+    regions
+}
+
+///Перевод областей из пикселей рендера в пункты pdf
+fn page_rects(page: &PdfPage, regions: &[PixelRect], config: &PdfRenderConfig) -> Result<Vec<PageRect>, Error>
+{
+    let mut rects = Vec::with_capacity(regions.len());
+    for region in regions
+    {
+        let (left, top) = page.pixels_to_points(region.x, region.y, config)?;
+        let (right, bottom) = page.pixels_to_points(region.x + region.width, region.y + region.height, config)?;
+        rects.push(PageRect::new(left.value, bottom.value, right.value, top.value));
+    }
+    Ok(rects)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use image::{Rgb, RgbImage};
+    use crate::PixelRect;
+    use super::{diff_images, regions, HIGHLIGHT};
+
+    fn page(width: u32, height: u32) -> RgbImage
+    {
+        RgbImage::from_pixel(width, height, Rgb([255, 255, 255]))
+    }
+
+    fn fill(image: &mut RgbImage, x: u32, y: u32, width: u32, height: u32)
+    {
+        for py in y..y + height
+        {
+            for px in x..x + width
+            {
+                image.put_pixel(px, py, Rgb([0, 0, 0]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_regions()
+    {
+        let before = page(100, 100);
+        let mut after = page(100, 100);
+        //два близких прямоугольника объединяются, дальний остается отдельной областью
+        fill(&mut after, 10, 10, 5, 5);
+        fill(&mut after, 17, 12, 4, 4);
+        fill(&mut after, 70, 80, 10, 6);
+        //одиночный пиксель - шум
+        after.put_pixel(50, 40, Rgb([0, 0, 0]));
+        //небольшое отличие цвета (сглаживание) не считается изменением
+        after.put_pixel(60, 60, Rgb([230, 230, 230]));
+        let (image, mask, changed) = diff_images(&before, &after, 48);
+        assert_eq!(changed, 25 + 16 + 60 + 1);
+        assert_eq!(*image.get_pixel(12, 12), HIGHLIGHT);
+        assert_ne!(*image.get_pixel(60, 60), HIGHLIGHT);
+        let regions = regions(&mask, 8, 4);
+        assert_eq!(regions, vec![PixelRect { x: 10, y: 10, width: 11, height: 6 }, PixelRect { x: 70, y: 80, width: 10, height: 6 }]);
+    }
+
+    #[test]
+    fn test_diff_different_sizes()
+    {
+        //вторая версия длиннее, добавленная часть белая и изменением не считается, пока на ней ничего нет
+        let before = page(40, 40);
+        let mut after = page(40, 60);
+        let (_, mask, changed) = diff_images(&before, &after, 48);
+        assert_eq!((mask.width, mask.height, changed), (40, 60, 0));
+        fill(&mut after, 0, 50, 40, 2);
+        let (_, mask, changed) = diff_images(&before, &after, 48);
+        assert_eq!(changed, 80);
+        assert_eq!(regions(&mask, 8, 4), vec![PixelRect { x: 0, y: 50, width: 40, height: 2 }]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::{path::Path, sync::Arc};
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use image::{imageops, DynamicImage, Rgb, Rgba, RgbaImage};
+use crate::error::Error;
+
+///Где на странице располагается водяной знак, если он не размножается по странице
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WatermarkPosition
+{
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    ///Центр знака в долях ширины и высоты страницы, от левого верхнего угла
+    Custom { x: f32, y: f32 },
+}
+
+#[derive(Clone)]
+enum WatermarkContent
+{
+    Text { text: String, font: FontArc, color: Rgb<u8> },
+    Image(Arc<DynamicImage>),
+}
+
+///Водяной знак, который накладывается на отрендеренную страницу перед кодированием,
+///сам pdf не меняется
+#[derive(Clone)]
+pub struct Watermark
+{
+    content: WatermarkContent,
+    opacity: f32,
+    rotation: f32,
+    size: f32,
+    position: WatermarkPosition,
+    tile_spacing: Option<f32>,
+}
+impl Watermark
+{
+    ///Текстовый знак, строки разделяются `\n`
+    ///шрифт передается файлом ttf или otf, для кириллицы он должен содержать кириллические символы (DejaVu Sans, PT Sans и т.д.)
+    pub fn text(text: &str, font: Vec<u8>) -> Result<Self, Error>
+    {
+        let font = FontArc::try_from_vec(font).map_err(|e| Error::WatermarkFontError(e.to_string()))?;
+        Ok(Self::new(WatermarkContent::Text { text: text.to_owned(), font, color: Rgb([128, 128, 128]) }, 0.06))
+    }
+    ///То же что и `text`, но шрифт загружается из файла
+    pub fn text_with_font_file<P: AsRef<Path>>(text: &str, font_path: P) -> Result<Self, Error>
+    {
+        Self::text(text, std::fs::read(font_path)?)
+    }
+    ///Знак из изображения (например логотип в png с прозрачностью)
+    pub fn image(image: DynamicImage) -> Self
+    {
+        Self::new(WatermarkContent::Image(Arc::new(image)), 0.3)
+    }
+    fn new(content: WatermarkContent, size: f32) -> Self
+    {
+        Self { content, opacity: 0.3, rotation: 0.0, size, position: WatermarkPosition::default(), tile_spacing: None }
+    }
+    ///Цвет текста, по умолчанию серый
+    pub fn set_color(mut self, r: u8, g: u8, b: u8) -> Self
+    {
+        if let WatermarkContent::Text { color, .. } = &mut self.content
+        {
+            *color = Rgb([r, g, b]);
+        }
+        self
+    }
+    ///Непрозрачность от 0 до 1, по умолчанию 0.3
+    pub fn set_opacity(mut self, opacity: f32) -> Self
+    {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+    ///Поворот в градусах против часовой стрелки, например 45 - знак по диагонали снизу вверх
+    pub fn set_rotation(mut self, degrees: f32) -> Self
+    {
+        self.rotation = degrees;
+        self
+    }
+    ///Размер знака в долях ширины страницы, чтобы знак выглядел одинаково при любом разрешении рендера:
+    ///для текста - высота строки (по умолчанию 0.06), для изображения - ширина (по умолчанию 0.3)
+    pub fn set_size(mut self, size: f32) -> Self
+    {
+        self.size = size.max(0.001);
+        self
+    }
+    pub fn set_position(mut self, position: WatermarkPosition) -> Self
+    {
+        self.position = position;
+        self
+    }
+    ///Размножение знака по всей странице, `spacing` - расстояние между знаками в долях ширины страницы
+    pub fn set_tiled(mut self, spacing: f32) -> Self
+    {
+        self.tile_spacing = Some(spacing.max(0.0));
+        self
+    }
+
+    ///Наложение знака на отрендеренную страницу, набор каналов страницы сохраняется (кроме l8 и la8, они становятся rgb8 и rgba8)
+    pub(crate) fn apply(&self, page: DynamicImage) -> DynamicImage
+    {
+        let stamp = self.stamp(page.width());
+        if stamp.width() == 0 || stamp.height() == 0
+        {
+            return page;
+        }
+        let has_alpha = page.color().has_alpha();
+        let mut canvas = page.into_rgba8();
+        for (x, y) in self.placements(canvas.width(), canvas.height(), stamp.width(), stamp.height())
+        {
+            imageops::overlay(&mut canvas, &stamp, x, y);
+        }
+        if has_alpha
+        {
+            DynamicImage::ImageRgba8(canvas)
+        }
+        else
+        {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).into_rgb8())
+        }
+    }
+
+    ///Знак для страницы шириной `page_width` пикселей: с учетом размера, поворота и прозрачности
+    fn stamp(&self, page_width: u32) -> RgbaImage
+    {
+        let size = (page_width as f32 * self.size).max(1.0);
+        let mut stamp = match &self.content
+        {
+            WatermarkContent::Text { text, font, color } => render_text(text, font, *color, size),
+            WatermarkContent::Image(image) =>
+            {
+                let height = (size * image.height() as f32 / image.width().max(1) as f32).round().max(1.0);
+                imageops::resize(&image.to_rgba8(), size.round() as u32, height as u32, imageops::FilterType::Triangle)
+            }
+        };
+        if self.rotation.rem_euclid(360.0) != 0.0
+        {
+            stamp = rotate(&stamp, self.rotation);
+        }
+        for pixel in stamp.pixels_mut()
+        {
+            pixel[3] = (pixel[3] as f32 * self.opacity).round() as u8;
+        }
+        stamp
+    }
+
+    ///Левые верхние углы знаков на странице
+    fn placements(&self, page_width: u32, page_height: u32, width: u32, height: u32) -> Vec<(i64, i64)>
+    {
+        let (page_width, page_height, width, height) = (page_width as i64, page_height as i64, width as i64, height as i64);
+        if let Some(spacing) = self.tile_spacing
+        {
+            let gap = (page_width as f32 * spacing).round() as i64;
+            let (step_x, step_y) = ((width + gap).max(1), (height + gap).max(1));
+            let mut placements = Vec::new();
+            //каждый второй ряд сдвигается на полшага, чтобы знаки не стояли столбцами
+            for (row, y) in (-(step_y / 2)..page_height).step_by(step_y as usize).enumerate()
+            {
+                let shift = if row % 2 == 1 { step_x / 2 } else { 0 };
+                for x in (-step_x + shift..page_width).step_by(step_x as usize)
+                {
+                    placements.push((x, y));
+                }
+            }
+            return placements;
+        }
+        let margin = page_width / 30;
+        let (x, y) = match self.position
+        {
+            WatermarkPosition::Center => ((page_width - width) / 2, (page_height - height) / 2),
+            WatermarkPosition::Top => ((page_width - width) / 2, margin),
+            WatermarkPosition::Bottom => ((page_width - width) / 2, page_height - height - margin),
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (page_width - width - margin, margin),
+            WatermarkPosition::BottomLeft => (margin, page_height - height - margin),
+            WatermarkPosition::BottomRight => (page_width - width - margin, page_height - height - margin),
+            WatermarkPosition::Custom { x, y } => ((page_width as f32 * x) as i64 - width / 2, (page_height as f32 * y) as i64 - height / 2)
+        };
+        vec![(x, y)]
+    }
+}
+
+///Текст с выравниванием строк по центру, `line_height` - высота строки в пикселях
+fn render_text(text: &str, font: &FontArc, color: Rgb<u8>, line_height: f32) -> RgbaImage
+{
+    let font = font.as_scaled(PxScale::from(line_height));
+    let lines: Vec<&str> = text.lines().collect();
+    let widths: Vec<f32> = lines.iter().map(|line| line_width(&font, line)).collect();
+    let width = widths.iter().cloned().fold(0.0, f32::max).ceil() as u32;
+    let step = font.height() + font.line_gap();
+    let height = (step * lines.len() as f32).ceil() as u32;
+    let mut image = RgbaImage::from_pixel(width, height, Rgba([color[0], color[1], color[2], 0]));
+    for (index, line) in lines.iter().enumerate()
+    {
+        let mut caret = (width as f32 - widths[index]) / 2.0;
+        let baseline = step * index as f32 + font.ascent();
+        let mut previous = None;
+        for c in line.chars()
+        {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous
+            {
+                caret += font.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(font.scale(), ab_glyph::point(caret, baseline));
+            caret += font.h_advance(id);
+            previous = Some(id);
+            let Some(outlined) = font.outline_glyph(glyph) else { continue };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage|
+            {
+                let (px, py) = (bounds.min.x as i64 + x as i64, bounds.min.y as i64 + y as i64);
+                if px >= 0 && py >= 0 && (px as u32) < width && (py as u32) < height
+                {
+                    let pixel = image.get_pixel_mut(px as u32, py as u32);
+                    pixel[3] = pixel[3].max((coverage.clamp(0.0, 1.0) * 255.0).round() as u8);
+                }
+            });
+        }
+    }
+    image
+}
+
+fn line_width<F: Font, S: ScaleFont<F>>(font: &S, line: &str) -> f32
+{
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in line.chars()
+    {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous
+        {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+///Поворот против часовой стрелки на произвольный угол с билинейной интерполяцией, холст расширяется под повернутое изображение
+fn rotate(image: &RgbaImage, degrees: f32) -> RgbaImage
+{
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    //погрешность sin и cos для прямых углов не должна добавлять лишний пиксель
+    let out_width = (width * cos.abs() + height * sin.abs() - 0.001).ceil().max(1.0) as u32;
+    let out_height = (width * sin.abs() + height * cos.abs() - 0.001).ceil().max(1.0) as u32;
+    let (cx, cy) = (width / 2.0, height / 2.0);
+    let (ox, oy) = (out_width as f32 / 2.0, out_height as f32 / 2.0);
+    let mut out = RgbaImage::new(out_width, out_height);
+    for (x, y, pixel) in out.enumerate_pixels_mut()
+    {
+        //обратное преобразование: ось y направлена вниз, поэтому поворот против часовой стрелки на экране
+        let (dx, dy) = (x as f32 + 0.5 - ox, y as f32 + 0.5 - oy);
+        let sx = dx * cos - dy * sin + cx - 0.5;
+        let sy = dx * sin + dy * cos + cy - 0.5;
+        *pixel = sample(image, sx, sy);
+    }
+    out
+}
+
+fn sample(image: &RgbaImage, x: f32, y: f32) -> Rgba<u8>
+{
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let mut color = [0.0f32; 4];
+    for (ox, oy, weight) in [(0.0, 0.0, (1.0 - fx) * (1.0 - fy)), (1.0, 0.0, fx * (1.0 - fy)), (0.0, 1.0, (1.0 - fx) * fy), (1.0, 1.0, fx * fy)]
+    {
+        let (sx, sy) = (x0 + ox, y0 + oy);
+        if weight <= 0.0 || sx < 0.0 || sy < 0.0 || sx >= image.width() as f32 || sy >= image.height() as f32
+        {
+            continue;
+        }
+        let pixel = image.get_pixel(sx as u32, sy as u32);
+        //цвет взвешивается по альфа каналу, чтобы края не темнели от прозрачных пикселей
+        let alpha = pixel[3] as f32 * weight;
+        for channel in 0..3
+        {
+            color[channel] += pixel[channel] as f32 * alpha;
+        }
+        color[3] += alpha;
+    }
+    if color[3] <= 0.0
+    {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([(color[0] / color[3]).round() as u8, (color[1] / color[3]).round() as u8, (color[2] / color[3]).round() as u8, color[3].round().min(255.0) as u8])
+}
+
+#[cfg(test)]
+mod tests
+{
+    use ab_glyph::{Font, FontArc};
+    use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
+    use super::{rotate, Watermark, WatermarkPosition};
+
+    ///DejaVu Sans только с символами `TEXT`
+    const FONT: &[u8] = include_bytes!("../tests/fixtures/DejaVuSans-watermark.ttf");
+    const TEXT: &str = "КОПИЯ / для ознакомления";
+
+    fn page() -> DynamicImage
+    {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(300, 400, Rgb([255, 255, 255])))
+    }
+    fn black() -> DynamicImage
+    {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255])))
+    }
+
+    #[test]
+    fn test_image_watermark_position_and_opacity()
+    {
+        let marked = Watermark::image(black()).set_opacity(0.5).set_size(0.1).apply(page());
+        let marked = marked.as_rgb8().unwrap();
+        assert!((126..=129).contains(&marked.get_pixel(150, 200)[0]));
+        assert_eq!(marked.get_pixel(5, 5)[0], 255);
+        let marked = Watermark::image(black()).set_opacity(1.0).set_size(0.1).set_position(WatermarkPosition::BottomRight).apply(page());
+        let marked = marked.as_rgb8().unwrap();
+        assert_eq!(marked.get_pixel(285, 385)[0], 0);
+        assert_eq!(marked.get_pixel(150, 200)[0], 255);
+    }
+
+    #[test]
+    fn test_tiled_watermark_covers_page()
+    {
+        let watermark = Watermark::image(black()).set_size(0.1).set_tiled(0.1);
+        let placements = watermark.placements(300, 400, 30, 30);
+        assert!(placements.iter().any(|(x, y)| *x <= 0 && *y <= 0));
+        assert!(placements.iter().any(|(x, y)| *x + 30 >= 300 && *y + 30 >= 400));
+        let marked = watermark.set_opacity(1.0).apply(page());
+        let dark = marked.as_rgb8().unwrap().pixels().filter(|p| p[0] == 0).count();
+        assert!(dark > 300 * 400 / 5);
+    }
+
+    #[test]
+    fn test_rotate()
+    {
+        let rotated = rotate(&RgbaImage::from_pixel(40, 10, Rgba([0, 0, 0, 255])), 90.0);
+        assert_eq!((rotated.width(), rotated.height()), (10, 40));
+        assert_eq!(rotated.get_pixel(5, 20)[3], 255);
+        let diagonal = rotate(&RgbaImage::from_pixel(40, 10, Rgba([0, 0, 0, 255])), 45.0);
+        //углы расширенного холста прозрачные
+        assert_eq!(diagonal.get_pixel(0, 0)[3], 0);
+    }
+
+    ///Пиксели страницы, задетые знаком (белая страница, красный знак)
+    fn marked(page: &DynamicImage) -> Vec<(u32, u32, Rgb<u8>)>
+    {
+        page.as_rgb8().unwrap().enumerate_pixels().filter(|(_, _, p)| p[1] < 250).map(|(x, y, p)| (x, y, *p)).collect()
+    }
+
+    #[test]
+    fn test_cyrillic_text_watermark()
+    {
+        let font = FontArc::try_from_slice(FONT).unwrap();
+        assert!(TEXT.chars().all(|c| font.glyph_id(c).0 != 0));
+        let watermark = Watermark::text(TEXT, FONT.to_vec()).unwrap().set_color(200, 0, 0).set_opacity(0.5).set_size(0.05).set_position(WatermarkPosition::TopLeft);
+        let page = DynamicImage::ImageRgb8(RgbImage::from_pixel(600, 800, Rgb([255, 255, 255])));
+        let pixels = marked(&watermark.apply(page));
+        //строка высотой 30 пикселей от отступа 20 пикселей в левом верхнем углу, шире половины страницы
+        assert!(pixels.len() > 1000, "{}", pixels.len());
+        assert!(pixels.iter().all(|(x, y, _)| *x >= 20 && *y >= 20 && *y < 60));
+        let right = pixels.iter().map(|(x, _, _)| *x).max().unwrap();
+        assert!(right > 300 && right < 580, "{}", right);
+        //внутри штрихов знак полностью закрашен с непрозрачностью 0.5: красный (200, 0, 0) пополам с белым
+        let (_, _, darkest) = pixels.iter().min_by_key(|(_, _, p)| p[1]).unwrap();
+        assert!((126..=129).contains(&darkest[1]) && (226..=229).contains(&darkest[0]), "{:?}", darkest);
+        assert!(pixels.iter().filter(|(_, _, p)| p[1] <= 129).count() > 100);
+    }
+
+    #[test]
+    fn test_tiled_text_watermark()
+    {
+        let watermark = Watermark::text(TEXT, FONT.to_vec()).unwrap().set_color(200, 0, 0).set_opacity(0.8).set_size(0.03).set_rotation(30.0).set_tiled(0.05);
+        let page = DynamicImage::ImageRgb8(RgbImage::from_pixel(600, 800, Rgb([255, 255, 255])));
+        let pixels = marked(&watermark.apply(page));
+        //знаки есть в каждой клетке страницы 4x4
+        for row in 0..4
+        {
+            for column in 0..4
+            {
+                let cell = |x: u32, y: u32| x / 150 == column && y / 200 == row;
+                assert!(pixels.iter().any(|(x, y, _)| cell(*x, *y)), "{} {}", column, row);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_font()
+    {
+        assert!(Watermark::text("КОПИЯ", b"not a font".to_vec()).is_err());
+    }
+}